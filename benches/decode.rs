@@ -1,62 +1,73 @@
-use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::StreamExt;
 
-// use futures::Stream;
+use rmq::zmtp::{Framed, Zmtp};
 
-// mod private {
-//     #![allow(warnings)]
+// Decodes all frames from the data, reading it like a socket would.
+fn decode(data: &[u8], read_buffer_size: usize) {
+    let mut codec = Zmtp::default();
+    codec.read_buffer_size = read_buffer_size;
 
-//     macro_rules! trace {
-//         ($($arg:tt)+) => {}
-//     }
+    let mut framed = Framed::new(data, codec);
+    futures::executor::block_on(async {
+        while let Some(frame) = framed.next().await {
+            frame.expect("valid frame");
+        }
+    });
+}
+
+fn message(len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len + 9);
+    if len > 255 {
+        data.push(2);
+        data.extend_from_slice(&(len as u64).to_be_bytes());
+    } else {
+        data.push(0);
+        data.push(len as u8);
+    }
+
+    data.resize(data.len() + len, b'x');
+    data
+}
 
-//     include!("../src/zmtp/mod.rs");
-// }
+fn cfg_bench(c: &mut Criterion) {
+    const MESSAGE: &[u8] = b"\x01\x0cHello world!";
+    const GREETING: &[u8] = b"\xff\0\0\0\0\0\0\0\0\x7f\x03\x01NULL\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+    const READY: &[u8] = b"\x04\x1c\x05READY\x0bSocket-Type\0\0\0\x06ROUTER";
+    const PING: &[u8] = b"\x04\x08\x04PING\x01\x7fa";
 
-// use private::*;
+    for (name, frame) in &[
+        ("message", MESSAGE),
+        ("greeting", GREETING),
+        ("ready", READY),
+        ("ping", PING),
+    ] {
+        c.bench_with_input(BenchmarkId::new("decode", name), frame, |b, s| {
+            b.iter(|| decode(s, 8 * 1024))
+        });
+    }
 
-// fn decode(buf: &[u8]) {
-//     tokio::runtime::Runtime::new()
-//         .expect("runtime")
-//         .block_on(async {
-//             futures::future::poll_fn(move |cx| {
-//                 tokio::pin! {
-//                     let reader = Reader::new(buf, Zmtp::default());
-//                 };
+    // A thousand small messages in a row.
+    let small = MESSAGE.repeat(1000);
+    c.bench_with_input(
+        BenchmarkId::new("decode", "1000 messages"),
+        &small,
+        |b, s| b.iter(|| decode(s, 8 * 1024)),
+    );
 
-//                 reader.poll_next(cx)
-//             })
-//             .await;
-//         });
-// }
+    let mut group = c.benchmark_group("decode large");
+    for &len in &[1 << 20, 16 << 20] {
+        let data = message(len);
+        group.throughput(Throughput::Bytes(data.len() as u64));
 
-fn cfg_bench(c: &mut Criterion) {
-    //     const MESSAGE: &[u8] = b"\x01\x0cHello world!\xff";
-    //     const LARGE_MESSAGE: &[u8] = b"\x01\0\0\0\0\0\0\x01\x38Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! Hello world! \xff";
-    //     const GREETING: &[u8] = b"\xff\0\0\0\0\0\0\0\0\x7f\x03\x01NULL\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
-    //     const READY: &[u8] = b"\x04\x1c\x05READY\x0bSocket-Type\0\0\0\x06ROUTER\xff";
-    //     const PING: &[u8] = b"\x04\x08\x04PING\x01\x7fa\xff";
-
-    //     c.bench_with_input(BenchmarkId::new("decode", "message"), &MESSAGE, |b, s| {
-    //         b.iter(|| decode(s))
-    //     });
-
-    //     c.bench_with_input(
-    //         BenchmarkId::new("decode", "large message"),
-    //         &LARGE_MESSAGE,
-    //         |b, s| b.iter(|| decode(s)),
-    //     );
-
-    //     c.bench_with_input(BenchmarkId::new("decode", "greeting"), &GREETING, |b, s| {
-    //         b.iter(|| decode(s))
-    //     });
-
-    //     c.bench_with_input(BenchmarkId::new("decode", "ready"), &READY, |b, s| {
-    //         b.iter(|| decode(s))
-    //     });
-
-    //     c.bench_with_input(BenchmarkId::new("decode", "ping"), &PING, |b, s| {
-    //         b.iter(|| decode(s))
-    //     });
+        for &read_buffer_size in &[8 * 1024, 256 * 1024] {
+            let id = format!("{}MB/{}KB buffer", len >> 20, read_buffer_size >> 10);
+            group.bench_with_input(BenchmarkId::from_parameter(id), &data, |b, s| {
+                b.iter(|| decode(s, read_buffer_size))
+            });
+        }
+    }
+    group.finish();
 }
 
 criterion_group!(benches, cfg_bench);
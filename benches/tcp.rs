@@ -2,7 +2,7 @@ use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 
 use rmq::{Client, Server};
 
-async fn rmq_server_client_ipv4<'a>(msg: &'static [u8], a: &'a Client, b: &'a Server) {
+async fn rmq_server_client<'a>(msg: &'static [u8], a: &'a Client, b: &'a Server) {
     for _ in 0..1000 {
         a.send(msg).await.unwrap();
     }
@@ -44,11 +44,59 @@ fn cfg_bench(c: &mut Criterion) {
         &MSG,
         |b, s| {
             b.iter(|| {
-                rt.block_on(rmq_server_client_ipv4(s, &client, &server));
+                rt.block_on(rmq_server_client(s, &client, &server));
             })
         },
     );
 
+    // Same host, over shared memory instead of loopback.
+    #[cfg(feature = "shm")]
+    {
+        let (server, client) = rt.block_on(async {
+            let (server, client): (rmq::Server, rmq::Client) = Default::default();
+            let addr = server.listen("shm://rmq-bench").await.unwrap();
+            client.connect(addr).await.unwrap();
+            (server, client)
+        });
+
+        c.bench_with_input(
+            BenchmarkId::new("rmq server/client", "shm"),
+            &MSG,
+            |b, s| {
+                b.iter(|| {
+                    rt.block_on(rmq_server_client(s, &client, &server));
+                })
+            },
+        );
+    }
+
+    // Same loopback connection, driven through io_uring instead of epoll.
+    #[cfg(feature = "io-uring")]
+    {
+        let options = rmq::Options {
+            io_uring: true,
+            ..Default::default()
+        };
+
+        let (server, client) = rt.block_on(async {
+            let server = rmq::Server::with_options(options.clone());
+            let client = rmq::Client::with_options(options);
+            let addr = server.listen("tcp://127.0.0.1:0").await.unwrap();
+            client.connect(addr).await.unwrap();
+            (server, client)
+        });
+
+        c.bench_with_input(
+            BenchmarkId::new("rmq server/client", "io_uring"),
+            &MSG,
+            |b, s| {
+                b.iter(|| {
+                    rt.block_on(rmq_server_client(s, &client, &server));
+                })
+            },
+        );
+    }
+
     use libzmq::prelude::{BuildSocket, Socket, TryInto};
     let address: libzmq::TcpAddr = "127.0.0.1:*".try_into().expect("addr");
     let endpoint: libzmq::addr::Endpoint = address.into();
@@ -0,0 +1,356 @@
+#![warn(rust_2018_idioms)]
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use rmq::{blocking, tracer, Envelope, Error, Group, Message, Route};
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fmt, process, thread};
+
+const TYPES: [&str; 7] = [
+    "client", "server", "radio", "dish", "scatter", "gather", "peer",
+];
+
+#[derive(Debug, Copy, Clone)]
+enum Format {
+    Text,
+    Hex,
+    Json,
+}
+
+enum Socket {
+    Server(blocking::Server),
+    Client(blocking::Client),
+    Radio(blocking::Radio),
+    Dish(blocking::Dish),
+    Gather(blocking::Gather),
+    Scatter(blocking::Scatter),
+    Peer(blocking::Peer),
+}
+
+macro_rules! each {
+    ($socket:expr, $name:ident => $body:expr) => {
+        match $socket {
+            Socket::Server($name) => $body,
+            Socket::Client($name) => $body,
+            Socket::Radio($name) => $body,
+            Socket::Dish($name) => $body,
+            Socket::Gather($name) => $body,
+            Socket::Scatter($name) => $body,
+            Socket::Peer($name) => $body,
+        }
+    };
+}
+
+impl Socket {
+    fn new(kind: &str) -> Result<Self, Error> {
+        match kind {
+            "server" => blocking::Server::new().map(Self::Server),
+            "client" => blocking::Client::new().map(Self::Client),
+            "radio" => blocking::Radio::new().map(Self::Radio),
+            "dish" => blocking::Dish::new().map(Self::Dish),
+            "gather" => blocking::Gather::new().map(Self::Gather),
+            "scatter" => blocking::Scatter::new().map(Self::Scatter),
+            "peer" => blocking::Peer::new().map(Self::Peer),
+            _ => unreachable!("socket type is validated by clap"),
+        }
+    }
+
+    fn can_send(&self) -> bool {
+        !matches!(self, Self::Dish(..) | Self::Gather(..))
+    }
+
+    fn can_recv(&self) -> bool {
+        !matches!(self, Self::Radio(..) | Self::Scatter(..))
+    }
+
+    fn send(&self, line: String, route: Option<Route>, group: Group) -> Result<(), Error> {
+        match (self, route) {
+            (Self::Client(socket), _) => socket.send(line),
+            (Self::Scatter(socket), _) => socket.send(line),
+            (Self::Radio(socket), _) => socket.broadcast(line, group),
+            (Self::Server(socket), Some(route)) => socket.route(line, route),
+            (Self::Peer(socket), Some(route)) => socket.route(line, route),
+            (Self::Server(..), None) | (Self::Peer(..), None) => Err(Error::RoutingError),
+            _ => Err(Error::Unsupported),
+        }
+    }
+
+    fn recv(&self) -> Result<Envelope<Message>, Error> {
+        match self {
+            Self::Server(socket) => socket.recv(),
+            Self::Client(socket) => socket.recv(),
+            Self::Dish(socket) => socket.recv(),
+            Self::Gather(socket) => socket.recv(),
+            Self::Peer(socket) => socket.recv(),
+            _ => Err(Error::Unsupported),
+        }
+    }
+}
+
+fn main() {
+    let matches = cli().get_matches();
+    let result: Result<(), Box<dyn std::error::Error>> = match matches.subcommand() {
+        Some(("listen", args)) => run(args, true).map_err(Into::into),
+        Some(("connect", args)) => run(args, false).map_err(Into::into),
+        Some(("probe", args)) => probe(args),
+        Some(("trace", args)) => trace(args).map_err(Into::into),
+        _ => unreachable!("subcommand is required"),
+    };
+
+    if let Err(err) = result {
+        eprintln!("rmq: {}", err);
+        process::exit(1);
+    }
+}
+
+fn cli() -> Command {
+    let socket_args = || {
+        [
+            Arg::new("type")
+                .help("Socket type")
+                .required(true)
+                .value_parser(TYPES),
+            Arg::new("endpoint")
+                .help("Endpoint, e.g. tcp://127.0.0.1:5555")
+                .required(true),
+            Arg::new("format")
+                .help("Output format of received messages")
+                .short('f')
+                .long("format")
+                .value_parser(["text", "hex", "json"])
+                .default_value("text"),
+            Arg::new("group")
+                .help("Group to join (dish) or to broadcast to (radio)")
+                .short('g')
+                .long("group")
+                .action(ArgAction::Append),
+            Arg::new("linger")
+                .help("Milliseconds to wait for outgoing messages after stdin is closed")
+                .long("linger")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("100"),
+        ]
+    };
+
+    Command::new("rmq")
+        .about("Send, receive and inspect ZeroMQ traffic")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("listen")
+                .about("Listen on an endpoint; sends lines from stdin and prints received messages")
+                .args(socket_args()),
+        )
+        .subcommand(
+            Command::new("connect")
+                .about(
+                    "Connect to an endpoint; sends lines from stdin and prints received messages",
+                )
+                .args(socket_args()),
+        )
+        .subcommand(
+            Command::new("probe")
+                .about("Perform a handshake over TCP and print the READY properties of the remote")
+                .arg(Arg::new("endpoint").required(true))
+                .arg(
+                    Arg::new("type")
+                        .help("Socket type to present to the remote")
+                        .short('t')
+                        .long("type")
+                        .value_parser(TYPES)
+                        .default_value("client"),
+                ),
+        )
+        .subcommand(
+            Command::new("trace")
+                .about("Print the ZMTP frames in a pcap/pcapng capture or a raw byte stream")
+                .arg(
+                    Arg::new("file")
+                        .help("Capture file, or - to read from stdin")
+                        .default_value("-"),
+                ),
+        )
+}
+
+fn run(args: &ArgMatches, listen: bool) -> Result<(), Error> {
+    let endpoint = args.get_one::<String>("endpoint").unwrap();
+    let socket = Arc::new(Socket::new(args.get_one::<String>("type").unwrap())?);
+    let format = match args.get_one::<String>("format").map(String::as_str) {
+        Some("hex") => Format::Hex,
+        Some("json") => Format::Json,
+        _ => Format::Text,
+    };
+
+    let groups = args
+        .get_many::<String>("group")
+        .unwrap_or_default()
+        .map(|group| group.parse().map_err(|_| Error::AddressInvalid))
+        .collect::<Result<Vec<Group>, Error>>()?;
+
+    // Server and peer sockets reply to whichever peer sent the last message.
+    let route = Arc::new(Mutex::new(None));
+
+    if listen {
+        let endpoint = each!(&*socket, socket => socket.listen(endpoint))?;
+        eprintln!("rmq: listening on {}", endpoint);
+    } else {
+        let peer = each!(&*socket, socket => socket.connect(endpoint))?;
+        route.lock().unwrap().replace(peer);
+    }
+
+    if let Socket::Dish(dish) = &*socket {
+        for &group in &groups {
+            dish.join(group);
+        }
+    }
+
+    let sender = if socket.can_send() {
+        let (socket, route) = (socket.clone(), route.clone());
+        let group = groups.first().copied().unwrap_or_default();
+        Some(thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(..) => break,
+                };
+
+                let peer = *route.lock().unwrap();
+                if let Err(err) = socket.send(line, peer, group) {
+                    eprintln!("rmq: failed to send: {}", err);
+                }
+            }
+        }))
+    } else {
+        None
+    };
+
+    if socket.can_recv() {
+        loop {
+            let envelope = socket.recv()?;
+            route.lock().unwrap().replace(envelope.route);
+            println!("{}", Printed(&envelope, format));
+        }
+    }
+
+    if let Some(sender) = sender {
+        let _ = sender.join();
+        let linger = *args.get_one::<u64>("linger").unwrap();
+        thread::sleep(Duration::from_millis(linger));
+    }
+
+    Ok(())
+}
+
+fn probe(args: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let endpoint = args.get_one::<String>("endpoint").unwrap();
+    let socket_type = args.get_one::<String>("type").unwrap();
+    let handshake = match blocking::probe(endpoint, socket_type) {
+        // The socket type is validated by clap, so the transport is at fault.
+        Err(Error::Unsupported) => {
+            return Err(format!(
+                "cannot probe {}: only tcp:// endpoints are supported",
+                endpoint
+            )
+            .into())
+        }
+        result => result?,
+    };
+
+    println!("Socket-Type: {}", handshake.socket_type);
+    for (key, value) in &handshake.properties {
+        println!("{}: {}", key, String::from_utf8_lossy(value));
+    }
+
+    Ok(())
+}
+
+fn trace(args: &ArgMatches) -> Result<(), tracer::Error> {
+    let reader: Box<dyn Read> = match args.get_one::<String>("file").unwrap().as_str() {
+        "-" => Box::new(io::stdin()),
+        path => Box::new(File::open(path)?),
+    };
+
+    for event in tracer::trace(io::BufReader::new(reader))? {
+        match event {
+            Ok(event) => println!("{}", event),
+
+            // Keep tracing other connections in the capture.
+            Err(err @ tracer::Error::InvalidStream(..)) => eprintln!("rmq: {}", err),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+struct Printed<'a>(&'a Envelope<Message>, Format);
+
+impl fmt::Display for Printed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Printed(envelope, format) = self;
+        let group = envelope.message.group().as_bytes();
+
+        if let Format::Json = format {
+            let mut metadata = Map::new();
+            for (key, value) in properties(envelope) {
+                metadata.insert(key.to_owned(), text(value).into());
+            }
+
+            let mut object = json!({
+                "route": envelope.route.to_string(),
+                "peer": envelope.peer_address().map(|addr| addr.to_string()),
+                "group": text(group),
+                "metadata": metadata,
+                "hex": hex(envelope.as_bytes()),
+            });
+
+            if let Ok(payload) = std::str::from_utf8(envelope.as_bytes()) {
+                object["payload"] = Value::from(payload);
+            }
+
+            return write!(f, "{}", object);
+        }
+
+        write!(f, "route={}", envelope.route)?;
+        if let Some(addr) = envelope.peer_address() {
+            write!(f, " peer={}", addr)?;
+        }
+
+        if !group.is_empty() {
+            write!(f, " group={}", text(group))?;
+        }
+
+        for (key, value) in properties(envelope) {
+            write!(f, " {}={}", key, text(value))?;
+        }
+
+        match format {
+            Format::Hex => write!(f, "\t{}", hex(envelope.as_bytes())),
+            _ => write!(f, "\t{}", text(envelope.as_bytes())),
+        }
+    }
+}
+
+fn properties(envelope: &Envelope<Message>) -> Vec<(&str, &[u8])> {
+    let mut properties: Vec<_> = envelope.metadata().collect();
+    if let Some(identity) = envelope.peer_identity() {
+        properties.push(("Identity", identity));
+    }
+
+    if let Some(resource) = envelope.resource() {
+        properties.push(("Resource", resource.as_bytes()));
+    }
+
+    properties.sort();
+    properties
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
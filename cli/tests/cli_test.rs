@@ -0,0 +1,102 @@
+use rmq::blocking::{Scatter, Server};
+use rmq::Options;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+#[test]
+fn listen_prints_received_messages() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rmq"))
+        .args(["listen", "gather", "tcp://127.0.0.1:0", "--format", "json"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let endpoint = line.trim().rsplit(' ').next().unwrap().to_owned();
+
+    let scatter = Scatter::new().unwrap();
+    scatter.connect(&endpoint).unwrap();
+    scatter.send("hello").unwrap();
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    child.kill().unwrap();
+
+    let message: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(message["payload"], "hello");
+    assert_eq!(message["hex"], "68656c6c6f");
+}
+
+#[test]
+fn probe_prints_ready_properties() {
+    let server = Server::with_options(Options {
+        metadata: vec![("X-Color".to_owned(), b"blue".to_vec())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let endpoint = server.listen("tcp://127.0.0.1:0").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rmq"))
+        .args(["probe", &endpoint.to_string(), "--type", "client"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Socket-Type: SERVER\nX-Color: blue\n"
+    );
+}
+
+#[test]
+fn probe_rejects_unknown_socket_type() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rmq"))
+        .args(["probe", "tcp://127.0.0.1:1", "--type", "bogus"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+}
+
+#[test]
+fn probe_rejects_other_transports() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rmq"))
+        .args(["probe", "inproc://probe"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "rmq: cannot probe inproc://probe: only tcp:// endpoints are supported\n"
+    );
+}
+
+#[test]
+fn trace_prints_frames_of_raw_stream() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rmq"))
+        .args(["trace", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"\xff\0\0\0\0\0\0\0\0\x7f\x03\x01NULL\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0").unwrap();
+    stdin.write_all(b"\x00\x05hello").unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "GREETING version=3.1 mechanism=NULL\nMESSAGE size=5\n"
+    );
+}
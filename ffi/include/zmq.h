@@ -0,0 +1,83 @@
+/* Generated by cbindgen from src/lib.rs; do not edit. */
+
+#ifndef RMQ_ZMQ_H
+#define RMQ_ZMQ_H
+
+#include <stddef.h>
+#include <stdint.h>
+/* Opaque; only access messages through the zmq_msg_* functions. */
+typedef struct zmq_msg_t {
+#if defined(_MSC_VER)
+  __declspec(align(8)) unsigned char _[64];
+#else
+  unsigned char _[64] __attribute__((aligned(sizeof(void *))));
+#endif
+} zmq_msg_t;
+
+#define ZMQ_SERVER 12
+
+#define ZMQ_CLIENT 13
+
+#define ZMQ_RADIO 14
+
+#define ZMQ_DISH 15
+
+#define ZMQ_GATHER 16
+
+#define ZMQ_SCATTER 17
+
+#define ZMQ_PEER 19
+
+#define ZMQ_DONTWAIT 1
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+int zmq_errno(void);
+
+const char *zmq_strerror(int errnum);
+
+void *zmq_ctx_new(void);
+
+int zmq_ctx_term(void *context);
+
+void *zmq_socket(void *context, int kind);
+
+int zmq_close(void *socket);
+
+int zmq_bind(void *socket, const char *endpoint);
+
+int zmq_connect(void *socket, const char *endpoint);
+
+int zmq_join(void *socket, const char *group);
+
+int zmq_leave(void *socket, const char *group);
+
+int zmq_msg_init(zmq_msg_t *msg);
+
+int zmq_msg_init_size(zmq_msg_t *msg, size_t size);
+
+int zmq_msg_close(zmq_msg_t *msg);
+
+void *zmq_msg_data(zmq_msg_t *msg);
+
+size_t zmq_msg_size(const zmq_msg_t *msg);
+
+int zmq_msg_send(zmq_msg_t *msg, void *socket, int flags);
+
+int zmq_msg_recv(zmq_msg_t *msg, void *socket, int flags);
+
+uint32_t zmq_msg_routing_id(zmq_msg_t *msg);
+
+int zmq_msg_set_routing_id(zmq_msg_t *msg, uint32_t routing_id);
+
+const char *zmq_msg_group(zmq_msg_t *msg);
+
+int zmq_msg_set_group(zmq_msg_t *msg, const char *group);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* RMQ_ZMQ_H */
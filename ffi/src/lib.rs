@@ -0,0 +1,430 @@
+#![warn(rust_2018_idioms)]
+#![allow(non_camel_case_types)]
+#![allow(clippy::missing_safety_doc)]
+
+use bytes::Bytes;
+use libc::{c_char, c_int, c_void, size_t};
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::time::Duration;
+use std::{mem, ptr};
+
+use rmq::{blocking, Envelope, Error, Group, Message, Route};
+
+pub const ZMQ_SERVER: c_int = 12;
+pub const ZMQ_CLIENT: c_int = 13;
+pub const ZMQ_RADIO: c_int = 14;
+pub const ZMQ_DISH: c_int = 15;
+pub const ZMQ_GATHER: c_int = 16;
+pub const ZMQ_SCATTER: c_int = 17;
+pub const ZMQ_PEER: c_int = 19;
+
+pub const ZMQ_DONTWAIT: c_int = 1;
+
+// Same size and alignment as the opaque zmq_msg_t that cbindgen.toml puts in
+// zmq.h, so callers can keep allocating messages on the stack. The actual
+// message lives on the heap.
+#[repr(C)]
+pub struct zmq_msg_t {
+    inner: *mut Msg,
+    _reserved: [u8; 64 - mem::size_of::<*mut Msg>()],
+}
+
+const _: () = assert!(mem::size_of::<zmq_msg_t>() == 64);
+
+#[derive(Default)]
+struct Msg {
+    data: Vec<u8>,
+    routing_id: u32,
+    group: CString,
+}
+
+struct Context;
+
+enum Socket {
+    Server(blocking::Server),
+    Client(blocking::Client),
+    Radio(blocking::Radio),
+    Dish(blocking::Dish),
+    Gather(blocking::Gather),
+    Scatter(blocking::Scatter),
+    Peer(blocking::Peer),
+}
+
+macro_rules! each {
+    ($socket:expr, $name:ident => $body:expr) => {
+        match $socket {
+            Socket::Server($name) => $body,
+            Socket::Client($name) => $body,
+            Socket::Radio($name) => $body,
+            Socket::Dish($name) => $body,
+            Socket::Gather($name) => $body,
+            Socket::Scatter($name) => $body,
+            Socket::Peer($name) => $body,
+        }
+    };
+}
+
+impl Socket {
+    fn new(kind: c_int) -> Result<Self, c_int> {
+        let socket = match kind {
+            ZMQ_SERVER => blocking::Server::new().map(Self::Server),
+            ZMQ_CLIENT => blocking::Client::new().map(Self::Client),
+            ZMQ_RADIO => blocking::Radio::new().map(Self::Radio),
+            ZMQ_DISH => blocking::Dish::new().map(Self::Dish),
+            ZMQ_GATHER => blocking::Gather::new().map(Self::Gather),
+            ZMQ_SCATTER => blocking::Scatter::new().map(Self::Scatter),
+            ZMQ_PEER => blocking::Peer::new().map(Self::Peer),
+            _ => return Err(libc::EINVAL),
+        };
+
+        socket.map_err(errno)
+    }
+
+    fn send(&self, msg: &Msg, data: Bytes, timeout: Option<Duration>) -> Result<(), c_int> {
+        let route = Route::from(msg.routing_id);
+        let result = match (self, timeout) {
+            (Self::Client(socket), None) => socket.send(data),
+            (Self::Client(socket), Some(timeout)) => socket.send_timeout(data, timeout),
+            (Self::Scatter(socket), None) => socket.send(data),
+            (Self::Scatter(socket), Some(timeout)) => socket.send_timeout(data, timeout),
+            (Self::Server(socket), None) => socket.route(data, route),
+            (Self::Server(socket), Some(timeout)) => socket.route_timeout(data, route, timeout),
+            (Self::Peer(socket), None) => socket.route(data, route),
+            (Self::Peer(socket), Some(timeout)) => socket.route_timeout(data, route, timeout),
+            (Self::Radio(socket), _) => {
+                let group = Group::try_from(msg.group.as_bytes()).map_err(|_| libc::EINVAL)?;
+                socket.broadcast(data, group)
+            }
+            _ => return Err(libc::ENOTSUP),
+        };
+
+        result.map_err(errno)
+    }
+
+    fn recv(&self, timeout: Option<Duration>) -> Result<Envelope<Message>, c_int> {
+        let result = match (self, timeout) {
+            (Self::Server(socket), None) => socket.recv(),
+            (Self::Server(socket), Some(timeout)) => socket.recv_timeout(timeout),
+            (Self::Client(socket), None) => socket.recv(),
+            (Self::Client(socket), Some(timeout)) => socket.recv_timeout(timeout),
+            (Self::Dish(socket), None) => socket.recv(),
+            (Self::Dish(socket), Some(timeout)) => socket.recv_timeout(timeout),
+            (Self::Gather(socket), None) => socket.recv(),
+            (Self::Gather(socket), Some(timeout)) => socket.recv_timeout(timeout),
+            (Self::Peer(socket), None) => socket.recv(),
+            (Self::Peer(socket), Some(timeout)) => socket.recv_timeout(timeout),
+            _ => return Err(libc::ENOTSUP),
+        };
+
+        result.map_err(errno)
+    }
+}
+
+thread_local! {
+    static ERRNO: Cell<c_int> = const { Cell::new(0) };
+}
+
+fn fail<T>(errno: c_int, result: T) -> T {
+    ERRNO.with(|cell| cell.set(errno));
+    result
+}
+
+fn errno(err: Error) -> c_int {
+    match err {
+        Error::RoutingError => libc::EHOSTUNREACH,
+        Error::PermissionDenied => libc::EACCES,
+        Error::TransportUnknown => libc::EPROTONOSUPPORT,
+        Error::TransportUnavailable => libc::EPROTONOSUPPORT,
+        Error::AddressInUse => libc::EADDRINUSE,
+        Error::AddressNotFound => libc::EADDRNOTAVAIL,
+        Error::Timeout => libc::EAGAIN,
+        Error::Unsupported => libc::ENOTSUP,
+        Error::ConnectionRefused => libc::ECONNREFUSED,
+        Error::HandshakeFailed => libc::EPROTO,
+        Error::Io(..) => libc::EIO,
+        _ => libc::EINVAL,
+    }
+}
+
+// Like libzmq, reports sizes that do not fit an int as INT_MAX rather than as
+// negative numbers, which callers would take for errors.
+fn clamp(size: usize) -> c_int {
+    c_int::try_from(size).unwrap_or(c_int::MAX)
+}
+
+fn timeout(flags: c_int) -> Option<Duration> {
+    if flags & ZMQ_DONTWAIT != 0 {
+        Some(Duration::from_secs(0))
+    } else {
+        None
+    }
+}
+
+unsafe fn socket<'a>(socket: *mut c_void) -> Result<&'a Socket, c_int> {
+    (socket as *const Socket).as_ref().ok_or(libc::EFAULT)
+}
+
+unsafe fn msg<'a>(msg: *const zmq_msg_t) -> Result<&'a mut Msg, c_int> {
+    msg.as_ref()
+        .and_then(|msg| msg.inner.as_mut())
+        .ok_or(libc::EFAULT)
+}
+
+unsafe fn string<'a>(string: *const c_char) -> Result<&'a str, c_int> {
+    if string.is_null() {
+        return Err(libc::EFAULT);
+    }
+
+    CStr::from_ptr(string).to_str().map_err(|_| libc::EINVAL)
+}
+
+#[no_mangle]
+pub extern "C" fn zmq_errno() -> c_int {
+    ERRNO.with(Cell::get)
+}
+
+#[no_mangle]
+pub extern "C" fn zmq_strerror(errnum: c_int) -> *const c_char {
+    unsafe { libc::strerror(errnum) }
+}
+
+#[no_mangle]
+pub extern "C" fn zmq_ctx_new() -> *mut c_void {
+    Box::into_raw(Box::new(Context)) as *mut c_void
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_ctx_term(context: *mut c_void) -> c_int {
+    if context.is_null() {
+        return fail(libc::EFAULT, -1);
+    }
+
+    drop(Box::from_raw(context as *mut Context));
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_socket(context: *mut c_void, kind: c_int) -> *mut c_void {
+    if context.is_null() {
+        return fail(libc::EFAULT, ptr::null_mut());
+    }
+
+    match Socket::new(kind) {
+        Ok(socket) => Box::into_raw(Box::new(socket)) as *mut c_void,
+        Err(errno) => fail(errno, ptr::null_mut()),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_close(socket: *mut c_void) -> c_int {
+    if socket.is_null() {
+        return fail(libc::ENOTSOCK, -1);
+    }
+
+    drop(Box::from_raw(socket as *mut Socket));
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_bind(socket: *mut c_void, endpoint: *const c_char) -> c_int {
+    let result = (|| {
+        let (socket, endpoint) = (self::socket(socket)?, string(endpoint)?);
+        each!(socket, socket => socket.listen(endpoint)).map_err(errno)
+    })();
+
+    match result {
+        Ok(_) => 0,
+        Err(errno) => fail(errno, -1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_connect(socket: *mut c_void, endpoint: *const c_char) -> c_int {
+    let result = (|| {
+        let (socket, endpoint) = (self::socket(socket)?, string(endpoint)?);
+        each!(socket, socket => socket.connect(endpoint)).map_err(errno)
+    })();
+
+    match result {
+        Ok(_) => 0,
+        Err(errno) => fail(errno, -1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_join(socket: *mut c_void, group: *const c_char) -> c_int {
+    let result = (|| match self::socket(socket)? {
+        Socket::Dish(dish) => {
+            dish.join(string(group)?.parse().map_err(|_| libc::EINVAL)?);
+            Ok(())
+        }
+        _ => Err(libc::ENOTSUP),
+    })();
+
+    match result {
+        Ok(()) => 0,
+        Err(errno) => fail(errno, -1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_leave(socket: *mut c_void, group: *const c_char) -> c_int {
+    let result = (|| match self::socket(socket)? {
+        Socket::Dish(dish) => {
+            dish.leave(string(group)?.parse().map_err(|_| libc::EINVAL)?);
+            Ok(())
+        }
+        _ => Err(libc::ENOTSUP),
+    })();
+
+    match result {
+        Ok(()) => 0,
+        Err(errno) => fail(errno, -1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_init(msg: *mut zmq_msg_t) -> c_int {
+    zmq_msg_init_size(msg, 0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_init_size(msg: *mut zmq_msg_t, size: size_t) -> c_int {
+    if msg.is_null() {
+        return fail(libc::EFAULT, -1);
+    }
+
+    let inner = Msg {
+        data: vec![0; size],
+        ..Default::default()
+    };
+
+    (*msg).inner = Box::into_raw(Box::new(inner));
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_close(msg: *mut zmq_msg_t) -> c_int {
+    if msg.is_null() || (*msg).inner.is_null() {
+        return fail(libc::EFAULT, -1);
+    }
+
+    drop(Box::from_raw((*msg).inner));
+    (*msg).inner = ptr::null_mut();
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_data(msg: *mut zmq_msg_t) -> *mut c_void {
+    match self::msg(msg) {
+        Ok(msg) => msg.data.as_mut_ptr() as *mut c_void,
+        Err(errno) => fail(errno, ptr::null_mut()),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_size(msg: *const zmq_msg_t) -> size_t {
+    match self::msg(msg) {
+        Ok(msg) => msg.data.len(),
+        Err(errno) => fail(errno, 0),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_send(
+    msg: *mut zmq_msg_t,
+    socket: *mut c_void,
+    flags: c_int,
+) -> c_int {
+    let result = (|| {
+        let (msg, socket) = (self::msg(msg)?, self::socket(socket)?);
+        let data = Bytes::from(mem::take(&mut msg.data));
+        let size = data.len();
+
+        // The message is only released once it has been sent successfully.
+        match socket.send(msg, data.clone(), timeout(flags)) {
+            Ok(()) => {
+                *msg = Msg::default();
+                Ok(size)
+            }
+            Err(errno) => {
+                msg.data = data.to_vec();
+                Err(errno)
+            }
+        }
+    })();
+
+    match result {
+        Ok(size) => clamp(size),
+        Err(errno) => fail(errno, -1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_recv(
+    msg: *mut zmq_msg_t,
+    socket: *mut c_void,
+    flags: c_int,
+) -> c_int {
+    let result = (|| {
+        let (msg, socket) = (self::msg(msg)?, self::socket(socket)?);
+        let envelope = socket.recv(timeout(flags))?;
+
+        *msg = Msg {
+            data: envelope.as_bytes().to_vec(),
+            routing_id: envelope.route.id(),
+            group: CString::new(envelope.message.group().as_bytes()).unwrap_or_default(),
+        };
+
+        Ok(msg.data.len())
+    })();
+
+    match result {
+        Ok(size) => clamp(size),
+        Err(errno) => fail(errno, -1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_routing_id(msg: *mut zmq_msg_t) -> u32 {
+    match self::msg(msg) {
+        Ok(msg) => msg.routing_id,
+        Err(errno) => fail(errno, 0),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_set_routing_id(msg: *mut zmq_msg_t, routing_id: u32) -> c_int {
+    match self::msg(msg) {
+        Ok(msg) => {
+            msg.routing_id = routing_id;
+            0
+        }
+        Err(errno) => fail(errno, -1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_group(msg: *mut zmq_msg_t) -> *const c_char {
+    match self::msg(msg) {
+        Ok(msg) => msg.group.as_ptr(),
+        Err(errno) => fail(errno, ptr::null()),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn zmq_msg_set_group(msg: *mut zmq_msg_t, group: *const c_char) -> c_int {
+    let result = (|| {
+        let (msg, group) = (self::msg(msg)?, string(group)?);
+        group.parse::<Group>().map_err(|_| libc::EINVAL)?;
+        msg.group = CString::new(group).map_err(|_| libc::EINVAL)?;
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => 0,
+        Err(errno) => fail(errno, -1),
+    }
+}
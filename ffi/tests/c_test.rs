@@ -0,0 +1,159 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn library_dir() -> PathBuf {
+    // Integration tests live in target/<profile>/deps, next to the cdylib.
+    let exe = env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn c_harness() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let libs = library_dir();
+    let harness = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rmq-ffi-harness");
+
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".into()))
+        .arg(root.join("tests/harness.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(&libs)
+        .arg("-lrmq_ffi")
+        .arg(format!("-Wl,-rpath,{}", libs.display()))
+        .arg("-o")
+        .arg(&harness)
+        .status()
+        .expect("failed to run C compiler");
+    assert!(status.success());
+
+    // Cargo's library path may also contain an older copy of the library.
+    let output = Command::new(&harness)
+        .env("LD_LIBRARY_PATH", &libs)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"ok\n");
+}
+
+// Spells out a Rust FFI type as it appears in the header, followed by the
+// declared name.
+fn declare(ty: &str, name: &str) -> String {
+    let ty = ty.trim();
+    if let Some(ty) = ty.strip_prefix("*mut ") {
+        return format!("{} *{}", c_type(ty), name);
+    }
+    if let Some(ty) = ty.strip_prefix("*const ") {
+        return format!("const {} *{}", c_type(ty), name);
+    }
+    format!("{} {}", c_type(ty), name)
+}
+
+fn c_type(ty: &str) -> &str {
+    match ty {
+        "c_void" => "void",
+        "c_char" => "char",
+        "c_int" => "int",
+        "size_t" => "size_t",
+        "u32" => "uint32_t",
+        "zmq_msg_t" => "zmq_msg_t",
+        _ => panic!("no C type for {}", ty),
+    }
+}
+
+// Derives the header declarations of all constants and functions exported by
+// src/lib.rs.
+fn declarations(source: &str) -> Vec<String> {
+    let mut declarations = Vec::new();
+
+    for line in source.lines() {
+        if let Some(rest) = line.strip_prefix("pub const ") {
+            let (name, value) = rest.split_at(rest.find(':').unwrap());
+            let value = value.split(" = ").nth(1).unwrap().trim_end_matches(';');
+            declarations.push(format!("#define {} {}", name, value));
+        }
+    }
+
+    for item in source.split("extern \"C\" fn ").skip(1) {
+        let signature = &item[..item.find('{').unwrap()];
+        let signature = signature.split_whitespace().collect::<Vec<_>>().join(" ");
+        let (name, rest) = signature.split_at(signature.find('(').unwrap());
+        let (params, ret) = rest[1..].split_at(rest.rfind(')').unwrap() - 1);
+
+        let params = params
+            .split(',')
+            .map(str::trim)
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (name, ty) = param.split_at(param.find(':').unwrap());
+                declare(&ty[1..], name)
+            })
+            .collect::<Vec<_>>();
+
+        let params = if params.is_empty() {
+            "void".to_owned()
+        } else {
+            params.join(", ")
+        };
+
+        let function = format!("{}({})", name, params);
+        let declaration = match ret[1..].trim().strip_prefix("-> ") {
+            Some(ty) => declare(ty, &function),
+            None => format!("void {}", function),
+        };
+
+        declarations.push(format!("{};", declaration));
+    }
+
+    declarations
+}
+
+// Checks that include/zmq.h is what cbindgen generates from the current
+// exports. Skipped where cbindgen is not installed; header_matches_exports
+// still catches missing or changed declarations there.
+#[test]
+fn header_is_generated() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = match Command::new("cbindgen")
+        .current_dir(root)
+        .args(["--config", "cbindgen.toml", "--crate", "rmq-ffi", "--quiet"])
+        .output()
+    {
+        Ok(output) => output,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("cbindgen is not installed; not checking include/zmq.h");
+            return;
+        }
+        Err(err) => panic!("failed to run cbindgen: {}", err),
+    };
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let header = std::fs::read_to_string(root.join("include/zmq.h")).unwrap();
+    assert!(
+        String::from_utf8_lossy(&output.stdout) == header,
+        "include/zmq.h is out of date; regenerate it as described in cbindgen.toml"
+    );
+}
+
+#[test]
+fn header_matches_exports() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let source = std::fs::read_to_string(root.join("src/lib.rs")).unwrap();
+    let header = std::fs::read_to_string(root.join("include/zmq.h")).unwrap();
+
+    let expected = declarations(&source);
+    let declared = header
+        .lines()
+        .filter(|line| {
+            (line.starts_with("#define ZMQ_") || line.ends_with(");")) && !line.starts_with(' ')
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(declared, expected);
+}
@@ -0,0 +1,107 @@
+#include <assert.h>
+#include <errno.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+#include "zmq.h"
+
+_Static_assert(sizeof(zmq_msg_t) == 64, "zmq_msg_t must match libzmq");
+
+static void send_str(void *socket, const char *data, uint32_t routing_id, const char *group) {
+  zmq_msg_t msg;
+  assert(zmq_msg_init_size(&msg, strlen(data)) == 0);
+  memcpy(zmq_msg_data(&msg), data, strlen(data));
+  if (routing_id) assert(zmq_msg_set_routing_id(&msg, routing_id) == 0);
+  if (group) assert(zmq_msg_set_group(&msg, group) == 0);
+  assert(zmq_msg_send(&msg, socket, 0) == (int)strlen(data));
+  assert(zmq_msg_size(&msg) == 0);
+  zmq_msg_close(&msg);
+}
+
+static void test_client_server(void *ctx) {
+  void *server = zmq_socket(ctx, ZMQ_SERVER);
+  void *client = zmq_socket(ctx, ZMQ_CLIENT);
+  assert(server && client);
+
+  assert(zmq_bind(server, "inproc://ffi-client-server") == 0);
+  assert(zmq_connect(client, "inproc://ffi-client-server") == 0);
+
+  send_str(client, "hello", 0, NULL);
+
+  zmq_msg_t msg;
+  zmq_msg_init(&msg);
+  assert(zmq_msg_recv(&msg, server, 0) == 5);
+  assert(memcmp(zmq_msg_data(&msg), "hello", 5) == 0);
+  uint32_t routing_id = zmq_msg_routing_id(&msg);
+
+  send_str(server, "world", routing_id, NULL);
+
+  assert(zmq_msg_recv(&msg, client, 0) == 5);
+  assert(memcmp(zmq_msg_data(&msg), "world", 5) == 0);
+
+  assert(zmq_msg_recv(&msg, client, ZMQ_DONTWAIT) == -1);
+  assert(zmq_errno() == EAGAIN);
+
+  zmq_msg_close(&msg);
+  assert(zmq_close(client) == 0);
+  assert(zmq_close(server) == 0);
+}
+
+static void test_radio_dish(void *ctx) {
+  void *radio = zmq_socket(ctx, ZMQ_RADIO);
+  void *dish = zmq_socket(ctx, ZMQ_DISH);
+  assert(radio && dish);
+
+  assert(zmq_bind(radio, "inproc://ffi-radio-dish") == 0);
+  assert(zmq_connect(dish, "inproc://ffi-radio-dish") == 0);
+  assert(zmq_join(dish, "weather") == 0);
+  assert(zmq_join(radio, "weather") == -1);
+  assert(zmq_errno() == ENOTSUP);
+
+  usleep(50 * 1000);
+
+  send_str(radio, "sunny", 0, "weather");
+
+  zmq_msg_t msg;
+  zmq_msg_init(&msg);
+  assert(zmq_msg_recv(&msg, dish, 0) == 5);
+  assert(memcmp(zmq_msg_data(&msg), "sunny", 5) == 0);
+  assert(strcmp(zmq_msg_group(&msg), "weather") == 0);
+  zmq_msg_close(&msg);
+
+  assert(zmq_leave(dish, "weather") == 0);
+  assert(zmq_close(dish) == 0);
+  assert(zmq_close(radio) == 0);
+}
+
+static void test_errors(void *ctx) {
+  assert(zmq_socket(ctx, 0) == NULL);
+  assert(zmq_errno() == EINVAL);
+
+  void *gather = zmq_socket(ctx, ZMQ_GATHER);
+  assert(zmq_bind(gather, "bogus://address") == -1);
+  assert(zmq_errno() == EPROTONOSUPPORT);
+  assert(zmq_strerror(zmq_errno()) != NULL);
+
+  zmq_msg_t msg;
+  zmq_msg_init(&msg);
+  assert(zmq_msg_send(&msg, gather, 0) == -1);
+  assert(zmq_errno() == ENOTSUP);
+  zmq_msg_close(&msg);
+
+  assert(zmq_close(gather) == 0);
+}
+
+int main(void) {
+  void *ctx = zmq_ctx_new();
+  assert(ctx);
+
+  test_client_server(ctx);
+  test_radio_dish(ctx);
+  test_errors(ctx);
+
+  assert(zmq_ctx_term(ctx) == 0);
+  printf("ok\n");
+  return 0;
+}
@@ -1,6 +1,11 @@
 #![no_main]
+use bytes::BytesMut;
 use libfuzzer_sys::fuzz_target;
+use rmq::zmtp::Zmtp;
+use tokio_util::codec::Decoder;
 
 fuzz_target!(|data: &[u8]| {
-    let _ = rmq::decode(data);
+    let mut codec = Zmtp::default();
+    let mut buffer = BytesMut::from(data);
+    while let Ok(Some(_frame)) = codec.decode(&mut buffer) {}
 });
@@ -0,0 +1,214 @@
+use futures::Future;
+use std::fmt;
+use std::time::Duration;
+
+use crate::runtime::{self, Rt, Runtime};
+#[cfg(feature = "tcp")]
+use crate::Handshake;
+use crate::{
+    socket, Endpoint, Envelope, Error, Group, IntoMessage, Message, Request, Route, Stats,
+    ToEndpoint,
+};
+
+struct Executor(<Rt as Runtime>::Executor);
+
+impl Executor {
+    fn new() -> Result<Self, Error> {
+        Ok(Self(Rt::executor()?))
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        Rt::block_on(&self.0, future)
+    }
+
+    fn block_on_timeout<F, T>(&self, future: F, timeout: Duration) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        match self.block_on(runtime::timeout(timeout, future)) {
+            Some(result) => result,
+            None => Err(Error::Timeout),
+        }
+    }
+}
+
+impl fmt::Debug for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Executor").finish()
+    }
+}
+
+macro_rules! define_socket {
+    ($name:ident) => {
+        #[derive(Debug)]
+        pub struct $name {
+            // Dropped before the executor, so sessions shut down while it runs.
+            inner: crate::$name,
+            executor: Executor,
+        }
+
+        impl $name {
+            // Fails if the thread that runs the socket cannot be started.
+            pub fn new() -> Result<Self, Error> {
+                Self::with_options(Default::default())
+            }
+
+            pub fn with_options(options: socket::Options) -> Result<Self, Error> {
+                let executor = Executor::new()?;
+                let inner = crate::$name::with_options(options);
+                Ok(Self { inner, executor })
+            }
+
+            pub fn listen(&self, addr: impl ToEndpoint) -> Result<Endpoint, Error> {
+                self.executor.block_on(self.inner.listen(addr))
+            }
+
+            pub fn connect(&self, addr: impl ToEndpoint) -> Result<Route, Error> {
+                self.executor.block_on(self.inner.connect(addr))
+            }
+
+            pub fn stats(&self) -> Stats {
+                self.inner.stats()
+            }
+        }
+    };
+}
+
+macro_rules! define_recv {
+    ($name:ident) => {
+        impl $name {
+            pub fn recv(&self) -> Result<Envelope<Message>, Error> {
+                self.executor.block_on(self.inner.recv())
+            }
+
+            pub fn recv_timeout(&self, timeout: Duration) -> Result<Envelope<Message>, Error> {
+                self.executor.block_on_timeout(self.inner.recv(), timeout)
+            }
+
+            pub fn recv_batch(
+                &self,
+                batch: &mut Vec<Envelope<Message>>,
+                max: usize,
+            ) -> Result<usize, Error> {
+                self.executor.block_on(self.inner.recv_batch(batch, max))
+            }
+        }
+    };
+}
+
+macro_rules! define_send {
+    ($name:ident) => {
+        impl $name {
+            pub fn send(&self, message: impl IntoMessage) -> Result<(), Error> {
+                self.executor.block_on(self.inner.send(message))
+            }
+
+            pub fn send_timeout(
+                &self,
+                message: impl IntoMessage,
+                timeout: Duration,
+            ) -> Result<(), Error> {
+                self.executor
+                    .block_on_timeout(self.inner.send(message), timeout)
+            }
+
+            pub fn send_batch<I>(&self, messages: I) -> Result<(), Error>
+            where
+                I: IntoIterator,
+                I::Item: IntoMessage,
+            {
+                self.executor.block_on(self.inner.send_batch(messages))
+            }
+        }
+    };
+}
+
+macro_rules! define_route {
+    ($name:ident) => {
+        impl $name {
+            pub fn route(&self, message: impl IntoMessage, route: Route) -> Result<(), Error> {
+                self.executor.block_on(self.inner.route(message, route))
+            }
+
+            pub fn route_timeout(
+                &self,
+                message: impl IntoMessage,
+                route: Route,
+                timeout: Duration,
+            ) -> Result<(), Error> {
+                self.executor
+                    .block_on_timeout(self.inner.route(message, route), timeout)
+            }
+        }
+    };
+}
+
+define_socket!(Server);
+define_recv!(Server);
+define_route!(Server);
+impl Server {
+    pub fn recv_request(&self) -> Result<Request, Error> {
+        self.executor.block_on(self.inner.recv_request())
+    }
+
+    pub fn recv_request_timeout(&self, timeout: Duration) -> Result<Request, Error> {
+        self.executor
+            .block_on_timeout(self.inner.recv_request(), timeout)
+    }
+
+    pub fn respond(&self, request: &Request, message: impl IntoMessage) -> Result<(), Error> {
+        self.executor.block_on(self.inner.respond(request, message))
+    }
+}
+
+define_socket!(Client);
+define_recv!(Client);
+define_send!(Client);
+impl Client {
+    pub fn request(&self, message: impl IntoMessage) -> Result<Envelope<Message>, Error> {
+        self.executor.block_on(self.inner.request(message))
+    }
+
+    pub fn request_timeout(
+        &self,
+        message: impl IntoMessage,
+        timeout: Duration,
+    ) -> Result<Envelope<Message>, Error> {
+        self.executor
+            .block_on(self.inner.request_timeout(message, timeout))
+    }
+}
+
+define_socket!(Scatter);
+define_send!(Scatter);
+
+define_socket!(Gather);
+define_recv!(Gather);
+
+define_socket!(Radio);
+impl Radio {
+    pub fn broadcast(&self, message: impl IntoMessage, group: Group) -> Result<(), Error> {
+        self.inner.broadcast(message, group)
+    }
+}
+
+define_socket!(Dish);
+define_recv!(Dish);
+impl Dish {
+    pub fn join(&self, group: Group) {
+        self.inner.join(group)
+    }
+
+    pub fn leave(&self, group: Group) {
+        self.inner.leave(group)
+    }
+}
+
+define_socket!(Peer);
+define_recv!(Peer);
+define_route!(Peer);
+
+#[cfg(feature = "tcp")]
+pub fn probe(addr: impl ToEndpoint, socket_type: &str) -> Result<Handshake, Error> {
+    Executor::new()?.block_on(crate::probe(addr, socket_type))
+}
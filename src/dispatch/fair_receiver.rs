@@ -1,117 +1,188 @@
 use futures::future::poll_fn;
-use futures::task::AtomicWaker;
 use std::collections::HashSet;
 use std::task::{Context, Poll};
 
-use super::{Delivery, Receiver, Register};
-use crate::message::Info;
+use super::{Delivery, Peers, Receiver, Register};
+use crate::message;
+use crate::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use crate::sync::{AtomicWaker, TryMutex};
 use crate::{Envelope, Error, Group, Message, Route};
 
 #[derive(Debug, Default)]
 pub(crate) struct FairReceiver {
-    pub(super) peers: Vec<(Route, Receiver)>,
-    next: usize,
+    pub(super) peers: Peers<TryMutex<Receiver>>,
+    next: AtomicUsize,
     waker: AtomicWaker,
 }
 
 impl Register<Receiver> for FairReceiver {
-    fn insert(&mut self, id: Route, peer: Receiver) {
-        self.peers.push((id, peer));
+    fn insert(&self, id: Route, peer: Receiver) {
+        self.peers.insert(id, TryMutex::new(peer));
         self.waker.wake();
     }
 
-    fn remove(&mut self, ref id: Route) {
-        for (idx, (key, ..)) in self.peers.iter().enumerate() {
-            if key == id {
-                self.peers.swap_remove(idx);
-                return;
-            }
-        }
-
-        panic!("removing unknown peer");
+    fn remove(&self, id: Route) {
+        self.peers.remove(id);
     }
 }
 
-lazy_static::lazy_static! {
-    static ref NO_INFO: crate::sync::Arc<Info> = Default::default();
-}
-
 impl FairReceiver {
-    pub(crate) fn poll_recv(
-        &mut self,
+    pub(crate) fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<Envelope<Message>, Error>> {
+        let mut received = None;
+        futures::ready!(self.poll_each(cx, |envelope| {
+            received = Some(envelope);
+            false
+        }));
+
+        Poll::Ready(Ok(received.expect("no message received")))
+    }
+
+    // Adds at most max messages that are ready to the batch.
+    pub(crate) fn poll_recv_batch(
+        &self,
+        batch: &mut Vec<Envelope<Message>>,
+        max: usize,
         cx: &mut Context<'_>,
-    ) -> Poll<Result<Envelope<Message>, Error>> {
-        let mut i = 0;
-
-        while i != self.peers.len() {
-            let idx = (self.next + i) % self.peers.len();
-            let (id, ref mut peer) = self.peers[idx];
-
-            match peer.rx.poll_recv(cx) {
-                Poll::Ready(Some(delivery)) => {
-                    self.next = idx + 1;
-
-                    return Poll::Ready(Ok(match delivery {
-                        Delivery::Message(message) => Envelope {
-                            info: NO_INFO.clone(),
-                            route: id,
-                            message,
-                        },
-
-                        Delivery::Envelope(envelope) => envelope,
-                    }));
-                }
+    ) -> Poll<Result<(), Error>> {
+        if max == 0 {
+            return Poll::Ready(Ok(()));
+        }
 
-                Poll::Ready(None) => {
-                    panic!("session was dropped");
-                }
+        let mut remaining = max;
+        futures::ready!(self.poll_each(cx, |envelope| {
+            batch.push(envelope);
+            remaining -= 1;
+            remaining > 0
+        }));
+
+        Poll::Ready(Ok(()))
+    }
 
-                _ => {}
+    // Passes received messages on until the closure returns false or no more
+    // messages are ready. Pending if none were.
+    fn poll_each(
+        &self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut(Envelope<Message>) -> bool,
+    ) -> Poll<()> {
+        let peers = self.peers.load();
+        let next = self.next.load(Relaxed);
+        let mut received = false;
+
+        for i in 0..peers.len() {
+            let idx = (next + i) % peers.len();
+
+            // Another task is receiving from this peer; it wakes us up once it
+            // is done, in case nothing is ready elsewhere.
+            if let Poll::Ready(mut peer) = peers[idx].1.poll_lock(cx) {
+                if !self.receive(peers[idx].0, idx, &mut peer, cx, &mut f, &mut received) {
+                    return Poll::Ready(());
+                }
             }
+        }
 
-            i += 1;
+        if received {
+            return Poll::Ready(());
         }
 
-        // Request to be woken up if new peers are added.
+        // Request to be woken up if new peers are added, and check again if
+        // that happened while we were looking.
         self.waker.register(cx.waker());
+        if !self.peers.is_current(&peers) {
+            cx.waker().wake_by_ref();
+        }
 
         Poll::Pending
     }
+
+    // Passes on the messages of one peer. Returns false once the closure does.
+    fn receive(
+        &self,
+        id: Route,
+        idx: usize,
+        peer: &mut Receiver,
+        cx: &mut Context<'_>,
+        f: &mut impl FnMut(Envelope<Message>) -> bool,
+        received: &mut bool,
+    ) -> bool {
+        let mut poll = poll_peer(id, peer, cx);
+        while let Poll::Ready(Some(envelope)) = poll {
+            *received = true;
+            if !f(envelope) {
+                self.next.store(idx + 1, Relaxed);
+                return false;
+            }
+
+            poll = poll_peer(id, peer, cx);
+        }
+
+        // Sessions close the queue when they end. Messages that were
+        // received before are still passed on, then the peer is dropped.
+        if let Poll::Ready(None) = poll {
+            self.peers.discard(id);
+        }
+
+        if *received {
+            self.next.store(idx + 1, Relaxed);
+        }
+
+        true
+    }
+}
+
+fn poll_peer(
+    id: Route,
+    peer: &mut Receiver,
+    cx: &mut Context<'_>,
+) -> Poll<Option<Envelope<Message>>> {
+    let message = match futures::ready!(peer.rx.poll_recv(cx)) {
+        Some(Delivery::Message(message)) => message,
+
+        Some(Delivery::Envelope(envelope)) => {
+            peer.counters.received(envelope.message.payload.len());
+            return Poll::Ready(Some(envelope));
+        }
+
+        None => return Poll::Ready(None),
+    };
+
+    peer.counters.received(message.payload.len());
+    let received = message::received(&mut peer.info);
+    Poll::Ready(Some(Envelope {
+        info: peer.info.clone(),
+        route: id,
+        message,
+        received,
+    }))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::dispatch::Peer;
-    use crate::socket::Options;
+    use crate::socket::test_options;
     use crate::sync::Arc;
     use crate::zmtp;
     use claim::*;
     use futures::{Future, FutureExt};
+    use futures_test::task::new_count_waker;
     use std::net::IpAddr;
 
-    const OPTIONS: Options = Options {
-        outgoing_queue_size: 2,
-        incoming_queue_size: 2,
-        max_reconnect_interval: std::time::Duration::from_micros(1),
-        heartbeat_timeout: std::time::Duration::from_micros(1),
-    };
-
     #[test]
     fn recv_queues_messages_fairly() {
-        let mut receiver = FairReceiver::default();
-        let (peer1, mut pipe1) = Peer::create(&OPTIONS);
-        let (peer2, mut pipe2) = Peer::create(&OPTIONS);
+        let receiver = FairReceiver::default();
+        let (peer1, mut pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
         receiver.insert(peer1.id, peer1.rx);
         receiver.insert(peer2.id, peer2.rx);
 
         assert_eq!(receiver.poll_recv(cx!()), Poll::Pending);
 
-        pipe1.tx.try_send(delivery!(1)).unwrap();
-        pipe1.tx.try_send(delivery!(2)).unwrap();
+        pipe1.try_send(delivery!(1)).unwrap();
+        pipe1.try_send(delivery!(2)).unwrap();
 
-        pipe2.tx.try_send(delivery!(3)).unwrap();
-        pipe2.tx.try_send(delivery!(4)).unwrap();
+        pipe2.try_send(delivery!(3)).unwrap();
+        pipe2.try_send(delivery!(4)).unwrap();
 
         assert_ready_eq!(receiver.poll_recv(cx!()), Ok(envelope!(1, pipe1.id)));
         assert_ready_eq!(receiver.poll_recv(cx!()), Ok(envelope!(3, pipe2.id)));
@@ -120,21 +191,97 @@ mod tests {
 
         receiver.remove(peer2.id);
 
-        pipe1.tx.try_send(delivery!(5)).unwrap();
-        pipe1.tx.try_send(delivery!(6)).unwrap();
+        pipe1.try_send(delivery!(5)).unwrap();
+        pipe1.try_send(delivery!(6)).unwrap();
 
         assert_ready_eq!(receiver.poll_recv(cx!()), Ok(envelope!(5, pipe1.id)));
         assert_ready_eq!(receiver.poll_recv(cx!()), Ok(envelope!(6, pipe1.id)));
     }
 
     #[test]
-    #[should_panic(expected = "session was dropped")]
-    fn recv_panics_if_session_queue_is_dropped() {
-        let mut receiver = FairReceiver::default();
-        let (peer, pipe) = Peer::create(&OPTIONS);
+    fn recv_batch_takes_ready_messages() {
+        let receiver = FairReceiver::default();
+        let (peer1, mut pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
+        receiver.insert(peer1.id, peer1.rx);
+        receiver.insert(peer2.id, peer2.rx);
+
+        let mut batch = Vec::new();
+        assert_pending!(receiver.poll_recv_batch(&mut batch, 3, cx!()));
+
+        pipe1.try_send(delivery!(1)).unwrap();
+        pipe1.try_send(delivery!(2)).unwrap();
+        pipe2.try_send(delivery!(3)).unwrap();
+        pipe2.try_send(delivery!(4)).unwrap();
+
+        assert_ready_eq!(receiver.poll_recv_batch(&mut batch, 3, cx!()), Ok(()));
+        assert_eq!(
+            batch,
+            vec![
+                envelope!(1, pipe1.id),
+                envelope!(2, pipe1.id),
+                envelope!(3, pipe2.id)
+            ]
+        );
+
+        batch.clear();
+        assert_ready_eq!(receiver.poll_recv_batch(&mut batch, 3, cx!()), Ok(()));
+        assert_eq!(batch, vec![envelope!(4, pipe2.id)]);
+    }
+
+    #[test]
+    fn recv_skips_peers_with_dropped_sessions() {
+        let receiver = FairReceiver::default();
+        let (peer1, pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
+        drop(pipe1);
+
+        receiver.insert(peer1.id, peer1.rx);
+        receiver.insert(peer2.id, peer2.rx);
+        assert_pending!(receiver.poll_recv(cx!()));
+        assert_eq!(receiver.peers.load().len(), 1);
+
+        pipe2.try_send(delivery!(1)).unwrap();
+        assert_ready_eq!(receiver.poll_recv(cx!()), Ok(envelope!(1, pipe2.id)));
+    }
+
+    #[test]
+    fn recv_drains_peers_with_ended_sessions() {
+        let receiver = FairReceiver::default();
+        let (peer, mut pipe) = Peer::create(&test_options());
+        receiver.insert(peer.id, peer.rx);
+
+        pipe.try_send(delivery!(1)).unwrap();
         let id = pipe.id;
         drop(pipe);
-        receiver.insert(peer.id, peer.rx);
-        let _ = receiver.poll_recv(cx!());
+
+        assert_ready_eq!(receiver.poll_recv(cx!()), Ok(envelope!(1, id)));
+        assert_pending!(receiver.poll_recv(cx!()));
+        assert!(receiver.peers.load().is_empty());
+    }
+
+    #[test]
+    fn recv_skips_peers_in_use() {
+        let receiver = FairReceiver::default();
+        let (peer1, mut pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
+        receiver.insert(peer1.id, peer1.rx);
+        receiver.insert(peer2.id, peer2.rx);
+
+        pipe1.try_send(delivery!(1)).unwrap();
+        pipe2.try_send(delivery!(2)).unwrap();
+
+        let peers = receiver.peers.load();
+        let guard = peers[0].1.try_lock().unwrap();
+        let (waker, count) = new_count_waker();
+        let cx = &mut Context::from_waker(&waker);
+        assert_ready_eq!(receiver.poll_recv(cx), Ok(envelope!(2, pipe2.id)));
+
+        // Nothing else is ready, so this waits for the busy peer.
+        assert_pending!(receiver.poll_recv(cx));
+        let woken = count.get();
+        drop(guard);
+        assert_eq!(count.get(), woken + 1);
+        assert_ready_eq!(receiver.poll_recv(cx), Ok(envelope!(1, pipe1.id)));
     }
 }
@@ -1,88 +1,158 @@
 use futures::future::poll_fn;
-use futures::task::AtomicWaker;
+use std::collections::VecDeque;
 use std::task::{Context, Poll};
 
-use super::{Delivery, Register, Sender};
-use crate::sync::{Arc, Mutex};
+use super::{Delivery, Peers, Register, Sender};
+use crate::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use crate::sync::{Arc, AtomicWaker, TryMutex};
 use crate::{Error, Message, Route};
 
 #[derive(Debug, Default)]
 pub(crate) struct FairSender {
-    peers: Vec<(Route, Sender)>,
-    next: usize,
+    peers: Peers<TryMutex<Sender>>,
+    next: AtomicUsize,
     waker: AtomicWaker,
 }
 
 impl Register<Sender> for FairSender {
-    fn insert(&mut self, id: Route, peer: Sender) {
-        self.peers.push((id, peer));
+    fn insert(&self, id: Route, peer: Sender) {
+        self.peers.insert(id, TryMutex::new(peer));
         self.waker.wake();
     }
 
-    fn remove(&mut self, ref id: Route) {
-        for (idx, (key, ..)) in self.peers.iter().enumerate() {
-            if key == id {
-                self.peers.swap_remove(idx);
-                return;
-            }
-        }
-
-        panic!("removing unknown peer");
+    fn remove(&self, id: Route) {
+        self.peers.remove(id);
     }
 }
 
 impl FairSender {
     pub(crate) fn poll_send(
-        &mut self,
+        &self,
         message: &mut Option<Message>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), Error>> {
-        let mut i = 0;
+        self.poll_deliver(cx, |peer, cx| {
+            // Peers that are being removed may have closed their queue already.
+            if let Poll::Ready(Ok(())) = peer.tx.poll_ready(cx) {
+                queue(peer, message.take().expect("message taken before send"));
+                return Poll::Ready(true);
+            }
 
-        while i != self.peers.len() {
-            let idx = (self.next + i) % self.peers.len();
-            let (_, ref mut peer) = self.peers[idx];
+            Poll::Ready(false)
+        })
+    }
 
-            if peer.tx.poll_ready(cx).is_ready() {
-                let message = message.take().expect("message taken before send");
-                if let Err(..) = peer.tx.try_send(Delivery::Message(message)) {
-                    panic!("session was dropped")
-                }
+    // Queues the messages to the same peer while it has room. Each message
+    // takes its own place in the queue, so that batches are bound by the
+    // queue size too. The rest waits for room, and only goes to other peers
+    // if the peer is still full once the task is woken up.
+    pub(crate) fn poll_send_batch(
+        &self,
+        batch: &mut VecDeque<Message>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        if batch.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
 
-                self.next = idx + 1;
-                return Poll::Ready(Ok(()));
+        self.poll_deliver(cx, |peer, cx| {
+            let mut queued = false;
+            while let Some(message) = batch.pop_front() {
+                match peer.tx.poll_ready(cx) {
+                    Poll::Ready(Ok(())) => {
+                        queue(peer, message);
+                        queued = true;
+                    }
+
+                    poll => {
+                        batch.push_front(message);
+                        return match poll {
+                            Poll::Pending if queued => Poll::Pending,
+                            _ => Poll::Ready(false),
+                        };
+                    }
+                }
             }
 
-            i += 1;
+            Poll::Ready(true)
+        })
+    }
+
+    // Offers the messages to each peer in turn, until one has taken all of
+    // them. The closure returns false if the peer took none, and is pending
+    // if the peer took some and has to make room for the rest.
+    fn poll_deliver(
+        &self,
+        cx: &mut Context<'_>,
+        mut deliver: impl FnMut(&mut Sender, &mut Context<'_>) -> Poll<bool>,
+    ) -> Poll<Result<(), Error>> {
+        let peers = self.peers.load();
+        let next = self.next.load(Relaxed);
+
+        for i in 0..peers.len() {
+            let idx = (next + i) % peers.len();
+
+            // Another task is sending to this peer; it wakes us up once it is
+            // done, in case the others are full.
+            let poll = match peers[idx].1.poll_lock(cx) {
+                Poll::Ready(mut peer) => deliver(&mut peer, cx),
+                Poll::Pending => continue,
+            };
+
+            if let Some(poll) = self.delivered(idx, poll) {
+                return poll;
+            }
         }
 
-        // Request to be woken up if new peers are added.
+        // Request to be woken up if new peers are added, and check again if
+        // that happened while we were looking.
         self.waker.register(cx.waker());
+        if !self.peers.is_current(&peers) {
+            cx.waker().wake_by_ref();
+        }
 
         Poll::Pending
     }
+
+    // Moves on to the next peer once the peer has taken everything, and stays
+    // with it while it makes room for the rest. None if it took nothing.
+    fn delivered(&self, idx: usize, poll: Poll<bool>) -> Option<Poll<Result<(), Error>>> {
+        match poll {
+            Poll::Ready(true) => {
+                self.next.store(idx + 1, Relaxed);
+                Some(Poll::Ready(Ok(())))
+            }
+            Poll::Ready(false) => None,
+            Poll::Pending => {
+                self.next.store(idx, Relaxed);
+                Some(Poll::Pending)
+            }
+        }
+    }
+}
+
+fn queue(peer: &mut Sender, message: Message) {
+    peer.counters.outgoing.push();
+    peer.counters.sent(message.payload.len());
+    if let Err(..) = peer.tx.try_send(Delivery::Message(message)) {
+        panic!("session was dropped")
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::dispatch::Peer;
-    use crate::socket::Options;
+    use crate::socket::test_options;
     use claim::*;
     use futures::{Future, FutureExt};
-
-    const OPTIONS: Options = Options {
-        outgoing_queue_size: 2,
-        incoming_queue_size: 2,
-        max_reconnect_interval: std::time::Duration::from_micros(1),
-        heartbeat_timeout: std::time::Duration::from_micros(1),
-    };
+    use futures_test::task::new_count_waker;
 
     #[test]
     fn send_queues_messages_to_next_peer() {
-        let mut sender = FairSender::default();
-        let (peer1, mut pipe1) = Peer::create(&OPTIONS);
-        let (peer2, mut pipe2) = Peer::create(&OPTIONS);
+        let sender = FairSender::default();
+        let (peer1, mut pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
 
         assert_pending!(sender.poll_send(&mut Some(msg!(1)), cx!()));
 
@@ -110,15 +180,124 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "session was dropped")]
-    fn send_panics_if_session_queue_is_dropped() {
-        let mut sender = FairSender::default();
-        let (peer, pipe) = Peer::create(&OPTIONS);
+    fn send_batch_queues_messages_to_next_peer() {
+        let sender = FairSender::default();
+        let (peer1, mut pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
+        sender.insert(peer1.id, peer1.tx);
+        sender.insert(peer2.id, peer2.tx);
+
+        let mut batch = vec![msg!(1), msg!(2)].into();
+        assert_ready_eq!(sender.poll_send_batch(&mut batch, cx!()), Ok(()));
+        assert!(batch.is_empty());
+        assert_ready_eq!(sender.poll_send(&mut Some(msg!(3)), cx!()), Ok(()));
+
+        assert_ok_eq!(pipe1.rx.try_recv(), delivery!(1));
+        assert_ok_eq!(pipe1.rx.try_recv(), delivery!(2));
+        assert_ok_eq!(pipe2.rx.try_recv(), delivery!(3));
+    }
+
+    #[test]
+    fn send_batch_waits_for_room_at_same_peer() {
+        let sender = FairSender::default();
+        let (peer1, mut pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
+        sender.insert(peer1.id, peer1.tx);
+        sender.insert(peer2.id, peer2.tx);
+
+        let mut batch = vec![msg!(1), msg!(2), msg!(3)].into();
+        assert_pending!(sender.poll_send_batch(&mut batch, cx!()));
+        assert_eq!(batch, vec![msg!(3)]);
+
+        assert_ok_eq!(pipe1.rx.try_recv(), delivery!(1));
+        assert_ready_eq!(sender.poll_send_batch(&mut batch, cx!()), Ok(()));
+        assert_ok_eq!(pipe1.rx.try_recv(), delivery!(2));
+        assert_ok_eq!(pipe1.rx.try_recv(), delivery!(3));
+        assert_err!(pipe2.rx.try_recv());
+    }
+
+    #[test]
+    fn send_batch_does_not_exceed_queue_size() {
+        let sender = FairSender::default();
+        let (peer1, mut pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
+        sender.insert(peer1.id, peer1.tx);
+        sender.insert(peer2.id, peer2.tx);
 
-        let id = pipe.id;
-        drop(pipe);
+        let mut batch = (1..=5).map(|i| msg!(i)).collect();
+        assert_pending!(sender.poll_send_batch(&mut batch, cx!()));
+        assert_pending!(sender.poll_send_batch(&mut batch, cx!()));
+        assert_pending!(sender.poll_send_batch(&mut batch, cx!()));
+        assert_eq!(batch, vec![msg!(5)]);
 
-        sender.insert(peer.id, peer.tx);
-        let _ = sender.poll_send(&mut Some(msg!(1)), cx!());
+        assert_ok_eq!(pipe1.rx.try_recv(), delivery!(1));
+        assert_ok_eq!(pipe1.rx.try_recv(), delivery!(2));
+        assert_err!(pipe1.rx.try_recv());
+        assert_ok_eq!(pipe2.rx.try_recv(), delivery!(3));
+        assert_ok_eq!(pipe2.rx.try_recv(), delivery!(4));
+        assert_err!(pipe2.rx.try_recv());
+    }
+
+    #[test]
+    fn send_skips_peers_with_dropped_sessions() {
+        let sender = FairSender::default();
+        let (peer1, pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
+        drop(pipe1);
+
+        sender.insert(peer1.id, peer1.tx);
+        sender.insert(peer2.id, peer2.tx);
+
+        assert_ready_eq!(sender.poll_send(&mut Some(msg!(1)), cx!()), Ok(()));
+        assert_ready_eq!(sender.poll_send(&mut Some(msg!(2)), cx!()), Ok(()));
+        assert_ok_eq!(pipe2.rx.try_recv(), delivery!(1));
+        assert_ok_eq!(pipe2.rx.try_recv(), delivery!(2));
+    }
+
+    #[test]
+    fn send_skips_peers_in_use() {
+        let sender = FairSender::default();
+        let (peer1, mut pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
+        sender.insert(peer1.id, peer1.tx);
+        sender.insert(peer2.id, peer2.tx);
+
+        let peers = sender.peers.load();
+        let guard = peers[0].1.try_lock().unwrap();
+
+        assert_ready_eq!(sender.poll_send(&mut Some(msg!(1)), cx!()), Ok(()));
+        assert_ready_eq!(sender.poll_send(&mut Some(msg!(2)), cx!()), Ok(()));
+        assert_ok_eq!(pipe2.rx.try_recv(), delivery!(1));
+        assert_ok_eq!(pipe2.rx.try_recv(), delivery!(2));
+        assert_err!(pipe1.rx.try_recv());
+        drop(guard);
+    }
+
+    #[test]
+    fn send_waits_for_peers_in_use_once_others_are_full() {
+        let sender = FairSender::default();
+        let (peer1, mut pipe1) = Peer::create(&test_options());
+        let (peer2, mut pipe2) = Peer::create(&test_options());
+        sender.insert(peer1.id, peer1.tx);
+        sender.insert(peer2.id, peer2.tx);
+
+        let peers = sender.peers.load();
+        let guard = peers[0].1.try_lock().unwrap();
+        let (waker, count) = new_count_waker();
+        let cx = &mut Context::from_waker(&waker);
+
+        assert_ready_eq!(sender.poll_send(&mut Some(msg!(1)), cx), Ok(()));
+        assert_ready_eq!(sender.poll_send(&mut Some(msg!(2)), cx), Ok(()));
+
+        // The queue of the free peer is full, so this waits for the busy one.
+        assert_pending!(sender.poll_send(&mut Some(msg!(3)), cx));
+        let woken = count.get();
+        drop(guard);
+        assert_eq!(count.get(), woken + 1);
+        assert_ready_eq!(sender.poll_send(&mut Some(msg!(3)), cx), Ok(()));
+
+        assert_ok_eq!(pipe2.rx.try_recv(), delivery!(1));
+        assert_ok_eq!(pipe2.rx.try_recv(), delivery!(2));
+        assert_ok_eq!(pipe1.rx.try_recv(), delivery!(3));
     }
 }
@@ -2,6 +2,7 @@ use std::collections::{HashMap, HashSet};
 use tokio::sync::watch;
 
 mod peer;
+mod peers;
 
 mod fair_receiver;
 mod fair_sender;
@@ -14,56 +15,71 @@ pub(super) use publisher::Publisher;
 pub(super) use router::Router;
 
 pub(crate) use peer::{Delivery, Peer, Pipe, Receiver, Sender};
+pub(crate) use peers::Peers;
 
-use crate::message::{Info, Route, Group};
+use crate::message::{Group, Info, Route};
 use crate::socket::Options;
-use crate::sync::{Arc, Mutex, MutexGuard};
+use crate::stats::Counters;
+use crate::sync::Arc;
+use crate::zmtp::SocketType;
 
+// Peers are registered while other tasks send and receive, so registers
+// update their peers without requiring exclusive access.
 pub(crate) trait Register<T>: std::fmt::Debug + Send + Sync + 'static {
-    fn insert(&mut self, id: Route, item: T);
-    fn remove(&mut self, id: Route);
+    fn insert(&self, id: Route, item: T);
+    fn remove(&self, id: Route);
 }
 
 impl<T> Register<T> for () {
-    fn insert(&mut self, id: Route, peer: T) {}
-    fn remove(&mut self, id: Route) {}
+    fn insert(&self, id: Route, peer: T) {}
+    fn remove(&self, id: Route) {}
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct Registry {
-    tx: Arc<Mutex<dyn Register<Sender>>>,
-    rx: Arc<Mutex<dyn Register<Receiver>>>,
+    tx: std::sync::Arc<dyn Register<Sender>>,
+    rx: std::sync::Arc<dyn Register<Receiver>>,
+    counters: Arc<Counters>,
 }
 
 impl Registry {
     pub(crate) fn create(&self, options: &Options) -> Pipe {
-        let (peer, pipe) = Peer::create(options);
+        let (peer, pipe) = Peer::create_with(options, self.counters.clone());
         self.insert(peer);
         pipe
     }
 
     pub(crate) fn attach(&self, pipe: Pipe) {
-        let peer = Peer::attach(pipe);
+        let peer = Peer::attach(pipe, self.counters.clone());
         self.insert(peer);
     }
 
+    // Called once the session of the peer has ended. Receivers drop the peer
+    // themselves once they have passed on what it sent.
     pub(crate) fn remove(&self, id: Route) {
-        self.tx.lock().remove(id);
-        self.rx.lock().remove(id);
+        self.tx.remove(id);
+        self.counters.remove(id);
         debug!("dispatch", "peer removed; id={}", id);
     }
 
+    pub(crate) fn counters(&self) -> Arc<Counters> {
+        self.counters.clone()
+    }
+
     fn insert(&self, peer: Peer) {
-        self.tx.lock().insert(peer.id, peer.tx);
-        self.rx.lock().insert(peer.id, peer.rx);
+        self.counters.insert(peer.id, peer.counters);
+        self.tx.insert(peer.id, peer.tx);
+        self.rx.insert(peer.id, peer.rx);
         debug!("dispatch", "peer inserted; id={}", peer.id);
     }
 }
 
 #[derive(Default)]
 pub(crate) struct Dispatcher<S, R> {
-    pub(crate) tx: Arc<Mutex<S>>,
-    pub(crate) rx: Arc<Mutex<R>>,
+    // Shared with registries as trait objects, which loom's Arc does not support.
+    pub(crate) tx: std::sync::Arc<S>,
+    pub(crate) rx: std::sync::Arc<R>,
+    pub(crate) counters: Arc<Counters>,
 }
 
 #[derive(Debug)]
@@ -84,18 +100,31 @@ where
     S: Register<Sender>,
     R: Register<Receiver>,
 {
+    pub(crate) fn new(socket_type: SocketType) -> Self
+    where
+        S: Default,
+        R: Default,
+    {
+        Self {
+            tx: Default::default(),
+            rx: Default::default(),
+            counters: Arc::new(Counters::new(socket_type)),
+        }
+    }
+
     pub(crate) fn registry(&self) -> Registry {
         Registry {
             tx: self.tx.clone(),
             rx: self.rx.clone(),
+            counters: self.counters.clone(),
         }
     }
 
-    pub(crate) fn tx(&self) -> MutexGuard<'_, S> {
-        self.tx.lock()
+    pub(crate) fn tx(&self) -> &S {
+        &self.tx
     }
 
-    pub(crate) fn rx(&self) -> MutexGuard<'_, R> {
-        self.rx.lock()
+    pub(crate) fn rx(&self) -> &R {
+        &self.rx
     }
 }
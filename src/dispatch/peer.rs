@@ -4,6 +4,7 @@ use tokio::sync::{broadcast, mpsc, watch};
 
 use crate::message::Info;
 use crate::socket::Options;
+use crate::stats::{Counters, Depth, PeerCounters};
 use crate::sync::Arc;
 use crate::util;
 use crate::{Envelope, Error, Group, Message, Route};
@@ -35,12 +36,17 @@ pub(crate) enum Delivery {
 pub(crate) struct Sender {
     pub(crate) tx: mpsc::Sender<Delivery>,
     pub(crate) groups: Arc<Exchange<HashSet<Group>>>,
+    pub(crate) counters: Arc<PeerCounters>,
 }
 
 #[derive(Debug)]
 pub(crate) struct Receiver {
     pub(crate) rx: mpsc::Receiver<Delivery>,
+
+    // Attached to messages that are received without an envelope.
+    pub(crate) info: Arc<Info>,
     pub(crate) groups: Arc<Exchange<HashSet<Group>>>,
+    pub(crate) counters: Arc<PeerCounters>,
 }
 
 #[derive(Debug)]
@@ -48,6 +54,7 @@ pub(crate) struct Peer {
     pub(crate) id: Route,
     pub(crate) tx: Sender,
     pub(crate) rx: Receiver,
+    pub(crate) counters: Arc<PeerCounters>,
 }
 
 #[derive(Debug)]
@@ -57,27 +64,46 @@ pub(crate) struct Pipe {
     pub(crate) rx: mpsc::Receiver<Delivery>,
 
     pub(crate) groups: Arc<Exchange<HashSet<Group>>>,
+
+    // Depth of the queues, seen from the peer.
+    pub(crate) incoming: Arc<Depth>,
+    pub(crate) outgoing: Arc<Depth>,
 }
 
 impl Peer {
-    pub(super) fn create(options: &Options) -> (Peer, Pipe) {
+    pub(crate) fn create(options: &Options) -> (Peer, Pipe) {
+        Self::create_with(options, Default::default())
+    }
+
+    pub(super) fn create_with(options: &Options, socket: Arc<Counters>) -> (Peer, Pipe) {
         let id = SEQUENCE.next();
 
         let (outgoing_tx, outgoing_rx) = mpsc::channel(options.outgoing_queue_size);
         let (incoming_tx, incoming_rx) = mpsc::channel(options.incoming_queue_size);
 
         let groups: Arc<Exchange<HashSet<Group>>> = Default::default();
+        let (incoming, outgoing): (Arc<Depth>, Arc<Depth>) = Default::default();
+        let counters = Arc::new(PeerCounters::new(
+            socket,
+            Route { id },
+            incoming.clone(),
+            outgoing.clone(),
+        ));
 
         let peer = Peer {
             id: Route { id },
             tx: Sender {
                 tx: outgoing_tx,
                 groups: groups.clone(),
+                counters: counters.clone(),
             },
             rx: Receiver {
                 rx: incoming_rx,
+                info: Default::default(),
                 groups: groups.clone(),
+                counters: counters.clone(),
             },
+            counters,
         };
 
         let pipe = Pipe {
@@ -85,28 +111,56 @@ impl Peer {
             tx: incoming_tx,
             rx: outgoing_rx,
             groups: groups.clone(),
+            incoming,
+            outgoing,
         };
 
         (peer, pipe)
     }
 
-    pub(super) fn attach(pipe: Pipe) -> Peer {
+    // Attaches a pipe of another socket directly, so that the queues of the
+    // pipe become the queues of this peer in the opposite direction.
+    pub(super) fn attach(pipe: Pipe, socket: Arc<Counters>) -> Peer {
         let id = SEQUENCE.next();
+        let counters = Arc::new(PeerCounters::new(
+            socket,
+            Route { id },
+            pipe.outgoing,
+            pipe.incoming,
+        ));
 
         Peer {
             id: Route { id },
             tx: Sender {
                 tx: pipe.tx,
                 groups: pipe.groups.clone(),
+                counters: counters.clone(),
             },
             rx: Receiver {
                 rx: pipe.rx,
+                info: Default::default(),
                 groups: pipe.groups,
+                counters: counters.clone(),
             },
+            counters,
         }
     }
 }
 
+impl Pipe {
+    // Queues a delivery for the dispatcher. It is counted before it is queued,
+    // so that the dispatcher cannot take it off the count first.
+    pub(crate) fn try_send(
+        &mut self,
+        delivery: Delivery,
+    ) -> Result<(), mpsc::error::TrySendError<Delivery>> {
+        self.incoming.push();
+        self.tx
+            .try_send(delivery)
+            .inspect_err(|_| self.incoming.pop())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,61 @@
+use crate::message::Route;
+use crate::sync::{Arc, Snapshot};
+
+pub(crate) type List<T> = Arc<Vec<(Route, Arc<T>)>>;
+
+// Peers of a dispatcher. Sending and receiving work on a snapshot of the list,
+// so registering peers never blocks them. Each peer is locked while a task
+// queues to or takes from it; tasks that need the same peer are woken up once
+// it is released rather than blocking their thread.
+#[derive(Debug)]
+pub(crate) struct Peers<T> {
+    list: Snapshot<Vec<(Route, Arc<T>)>>,
+}
+
+impl<T> Default for Peers<T> {
+    fn default() -> Self {
+        Self {
+            list: Snapshot::new(Vec::new()),
+        }
+    }
+}
+
+impl<T> Peers<T> {
+    pub(crate) fn load(&self) -> List<T> {
+        self.list.load()
+    }
+
+    // Returns false if peers were inserted or removed since the list was loaded.
+    pub(crate) fn is_current(&self, list: &List<T>) -> bool {
+        self.list.is_current(list)
+    }
+
+    pub(crate) fn insert(&self, id: Route, peer: T) {
+        let peer = Arc::new(peer);
+        self.list.update(move |list| {
+            let mut list = list.clone();
+            list.push((id, peer));
+            list
+        });
+    }
+
+    pub(crate) fn remove(&self, id: Route) {
+        self.list.update(|list| {
+            let mut list = list.clone();
+            match list.iter().position(|(key, ..)| *key == id) {
+                Some(idx) => list.swap_remove(idx),
+                None => panic!("removing unknown peer"),
+            };
+            list
+        });
+    }
+
+    // Removes the peer, unless another task has done so already.
+    pub(crate) fn discard(&self, id: Route) {
+        self.list.update(|list| {
+            let mut list = list.clone();
+            list.retain(|(key, ..)| *key != id);
+            list
+        });
+    }
+}
@@ -1,38 +1,43 @@
 use std::ops::DerefMut;
 use tokio::sync::mpsc;
 
-use super::{Delivery, Register, Sender};
+use super::{Delivery, Peers, Register, Sender};
 use crate::sync::Mutex;
 use crate::{Error, Group, Message, Route};
 
 #[derive(Debug, Default)]
 pub(crate) struct Publisher {
-    peers: Vec<(Route, Sender)>,
+    peers: Peers<Sender>,
 }
 
 impl Register<Sender> for Publisher {
-    fn insert(&mut self, id: Route, peer: Sender) {
-        self.peers.push((id, peer));
+    fn insert(&self, id: Route, peer: Sender) {
+        self.peers.insert(id, peer);
     }
 
-    fn remove(&mut self, ref id: Route) {
-        for (idx, (key, ..)) in self.peers.iter().enumerate() {
-            if key == id {
-                self.peers.swap_remove(idx);
-                return;
-            }
-        }
-
-        panic!("removing unknown peer");
+    fn remove(&self, id: Route) {
+        self.peers.remove(id);
     }
 }
 
 impl Publisher {
-    pub(crate) fn publish(&mut self, message: Message) {
-        for (_, peer) in self.peers.iter_mut() {
+    pub(crate) fn publish(&self, message: Message) {
+        let len = message.payload.len();
+        for (_, peer) in self.peers.load().iter() {
+            // A clone of the sender has a queue slot of its own to fill, so
+            // that concurrent publishers do not need to take turns.
             let message = Delivery::Message(message.clone());
-            if let Err(mpsc::error::TrySendError::Closed(..)) = peer.tx.try_send(message) {
-                panic!("session was dropped");
+            peer.counters.outgoing.push();
+            match peer.tx.clone().try_send(message) {
+                Ok(()) => peer.counters.sent(len),
+
+                // Slow peers miss out on messages; peers that are being
+                // removed may have closed their queue already.
+                Err(mpsc::error::TrySendError::Full(..)) => {
+                    peer.counters.outgoing.pop();
+                    peer.counters.dropped();
+                }
+                Err(mpsc::error::TrySendError::Closed(..)) => peer.counters.outgoing.pop(),
             }
         }
     }
@@ -42,23 +47,16 @@ impl Publisher {
 // mod tests {
 //     use super::*;
 //     use crate::dispatch::Peer;
-//     use crate::socket::Options;
+//     use crate::socket::test_options;
 //     use claim::*;
 //     use futures::{Future, FutureExt};
 //     use std::collections::HashSet;
 
-//     const OPTIONS: Options = Options {
-//         outgoing_queue_size: 2,
-//         incoming_queue_size: 2,
-//         max_reconnect_interval: std::time::Duration::from_micros(1),
-//         heartbeat_timeout: std::time::Duration::from_micros(1),
-//     };
-
 //     #[test]
 //     fn broadcast_queues_messages_by_group() {
 //         let mut publisher = Publisher::default();
-//         let (peer1, mut pipe1) = Peer::create(&OPTIONS);
-//         let (peer2, mut pipe2) = Peer::create(&OPTIONS);
+//         let (peer1, mut pipe1) = Peer::create(&test_options());
+//         let (peer2, mut pipe2) = Peer::create(&test_options());
 //         publisher.insert(peer1.id, peer1.tx);
 //         publisher.insert(peer2.id, peer2.tx);
 
@@ -95,7 +93,7 @@ impl Publisher {
 //     #[should_panic(expected = "session was dropped")]
 //     fn broadcast_panics_if_session_queue_is_dropped() {
 //         let mut router = Publisher::default();
-//         let (peer, pipe) = Peer::create(&OPTIONS);
+//         let (peer, pipe) = Peer::create(&test_options());
 
 //         let group: Group = "A".parse().unwrap();
 //         pipe.groups.tx.broadcast(set!(group)).unwrap();
@@ -4,43 +4,59 @@ use std::ops::DerefMut;
 use std::task::{Context, Poll};
 
 use super::{Delivery, Register, Sender};
-use crate::sync::{Arc, Mutex};
+use crate::sync::{Arc, Snapshot, TryMutex};
 use crate::{Error, Message, Route};
 
 #[derive(Debug, Default)]
 pub(crate) struct Router {
-    peers: HashMap<Route, Sender>,
+    peers: Snapshot<HashMap<Route, Arc<TryMutex<Sender>>>>,
 }
 
 impl Register<Sender> for Router {
-    fn insert(&mut self, id: Route, peer: Sender) {
-        self.peers.insert(id, peer);
+    fn insert(&self, id: Route, peer: Sender) {
+        let peer = Arc::new(TryMutex::new(peer));
+        self.peers.update(move |peers| {
+            let mut peers = peers.clone();
+            peers.insert(id, peer);
+            peers
+        });
     }
 
-    fn remove(&mut self, id: Route) {
-        if self.peers.remove(&id).is_none() {
-            panic!("removing unknown peer");
-        }
+    fn remove(&self, id: Route) {
+        self.peers.update(|peers| {
+            let mut peers = peers.clone();
+            if peers.remove(&id).is_none() {
+                panic!("removing unknown peer");
+            }
+            peers
+        });
     }
 }
 
 impl Router {
     pub(crate) fn poll_route(
-        &mut self,
+        &self,
         message: &mut Option<Message>,
         id: Route,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), Error>> {
-        let peer = match self.peers.get_mut(&id) {
-            Some(tx) => tx,
+        let peer = match self.peers.load().get(&id) {
+            Some(peer) => peer.clone(),
             None => return Poll::Ready(Err(Error::RoutingError)),
         };
 
+        // Tasks that send to the same peer take turns. They only hold the
+        // lock while they queue the message, and wake the next one up after.
+        let mut peer = futures::ready!(peer.poll_lock(cx));
+
+        // The peer is being removed and has closed its queue already.
         if let Err(..) = futures::ready!(peer.tx.poll_ready(cx)) {
-            panic!("session was dropped")
+            return Poll::Ready(Err(Error::RoutingError));
         }
 
         let message = message.take().expect("message taken before send");
+        peer.counters.outgoing.push();
+        peer.counters.sent(message.payload.len());
         if let Err(..) = peer.tx.try_send(Delivery::Message(message)) {
             panic!("session was dropped")
         }
@@ -53,41 +69,40 @@ impl Router {
 mod tests {
     use super::*;
     use crate::dispatch::Peer;
-    use crate::socket::Options;
+    use crate::socket::test_options;
     use claim::*;
     use futures::{Future, FutureExt};
-
-    const OPTIONS: Options = Options {
-        outgoing_queue_size: 1,
-        incoming_queue_size: 1,
-        max_reconnect_interval: std::time::Duration::from_micros(1),
-        heartbeat_timeout: std::time::Duration::from_micros(1),
-    };
+    use futures_test::task::new_count_waker;
 
     #[test]
     fn route_queues_messages_by_identity() {
-        let mut router = Router::default();
-        let (peer, mut pipe) = Peer::create(&OPTIONS);
+        let router = Router::default();
+        let (peer, mut pipe) = Peer::create(&test_options());
         router.insert(peer.id, peer.tx);
 
         assert_ready_eq!(
             router.poll_route(&mut Some(msg!(1)), pipe.id, cx!()),
             Ok(())
         );
+        assert_ready_eq!(
+            router.poll_route(&mut Some(msg!(2)), pipe.id, cx!()),
+            Ok(())
+        );
 
-        assert_pending!(router.poll_route(&mut Some(msg!(1)), pipe.id, cx!()));
+        assert_pending!(router.poll_route(&mut Some(msg!(3)), pipe.id, cx!()));
         assert_ok_eq!(pipe.rx.try_recv(), delivery!(1));
 
         assert_ready_eq!(
-            router.poll_route(&mut Some(msg!(2)), pipe.id, cx!()),
+            router.poll_route(&mut Some(msg!(3)), pipe.id, cx!()),
             Ok(())
         );
         assert_ok_eq!(pipe.rx.try_recv(), delivery!(2));
+        assert_ok_eq!(pipe.rx.try_recv(), delivery!(3));
     }
 
     #[test]
     fn route_returns_error_for_unknown_ids() {
-        let mut router = Router::default();
+        let router = Router::default();
         assert_ready_eq!(
             router.poll_route(&mut Some(msg!(1)), Route { id: 1 }, cx!()),
             Err(Error::RoutingError)
@@ -95,15 +110,38 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "session was dropped")]
-    fn route_panics_if_session_queue_is_dropped() {
-        let mut router = Router::default();
-        let (peer, pipe) = Peer::create(&OPTIONS);
+    fn route_returns_error_if_session_queue_is_dropped() {
+        let router = Router::default();
+        let (peer, pipe) = Peer::create(&test_options());
 
         let id = pipe.id;
         drop(pipe);
 
         router.insert(peer.id, peer.tx);
-        let _ = router.poll_route(&mut Some(msg!(1)), id, cx!());
+        assert_ready_eq!(
+            router.poll_route(&mut Some(msg!(1)), id, cx!()),
+            Err(Error::RoutingError)
+        );
+    }
+
+    #[test]
+    fn route_waits_for_peer_in_use() {
+        let router = Router::default();
+        let (peer, mut pipe) = Peer::create(&test_options());
+        router.insert(peer.id, peer.tx);
+
+        let peers = router.peers.load();
+        let guard = peers[&pipe.id].try_lock().unwrap();
+        let (waker, count) = new_count_waker();
+        let cx = &mut Context::from_waker(&waker);
+
+        let mut message = Some(msg!(1));
+        assert_pending!(router.poll_route(&mut message, pipe.id, cx));
+        assert_eq!(count, 0);
+
+        drop(guard);
+        assert_eq!(count, 1);
+        assert_ready_eq!(router.poll_route(&mut message, pipe.id, cx), Ok(()));
+        assert_ok_eq!(pipe.rx.try_recv(), delivery!(1));
     }
 }
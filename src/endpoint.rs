@@ -1,6 +1,7 @@
 use futures::{future, Future, FutureExt};
 use std::{fmt, net, pin};
 
+use crate::runtime::{Rt, Runtime};
 use crate::Error;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -12,6 +13,20 @@ pub enum Endpoint {
     #[cfg(feature = "udp")]
     Udp(net::SocketAddr),
 
+    #[cfg(feature = "tls")]
+    Tls(net::SocketAddr),
+
+    // Address and path of the WebSocket.
+    #[cfg(feature = "ws")]
+    Ws(net::SocketAddr, String),
+
+    #[cfg(all(feature = "ws", feature = "tls"))]
+    Wss(net::SocketAddr, String),
+
+    // Name of the listener on this host.
+    #[cfg(feature = "shm")]
+    Shm(String),
+
     #[cfg(feature = "ipc")]
     Ipc(String),
 
@@ -65,6 +80,66 @@ impl<T: AsRef<str>> ToEndpoint for T {
                 }
             }
 
+            (Some("tls"), Some(addr)) => {
+                #[cfg(feature = "tls")]
+                {
+                    async { Ok(Endpoint::Tls(resolve(addr).await?)) }.boxed()
+                }
+
+                #[cfg(not(feature = "tls"))]
+                {
+                    future::err(Error::TransportUnavailable).boxed()
+                }
+            }
+
+            (Some("ws"), Some(addr)) => {
+                #[cfg(feature = "ws")]
+                {
+                    async {
+                        let (addr, path) = split_path(addr);
+                        Ok(Endpoint::Ws(resolve(addr).await?, path))
+                    }
+                    .boxed()
+                }
+
+                #[cfg(not(feature = "ws"))]
+                {
+                    future::err(Error::TransportUnavailable).boxed()
+                }
+            }
+
+            (Some("wss"), Some(addr)) => {
+                #[cfg(all(feature = "ws", feature = "tls"))]
+                {
+                    async {
+                        let (addr, path) = split_path(addr);
+                        Ok(Endpoint::Wss(resolve(addr).await?, path))
+                    }
+                    .boxed()
+                }
+
+                #[cfg(not(all(feature = "ws", feature = "tls")))]
+                {
+                    future::err(Error::TransportUnavailable).boxed()
+                }
+            }
+
+            (Some("shm"), Some(name)) => {
+                #[cfg(feature = "shm")]
+                {
+                    if crate::shm::valid_name(&name) {
+                        future::ok(Endpoint::Shm(name)).boxed()
+                    } else {
+                        future::err(Error::AddressInvalid).boxed()
+                    }
+                }
+
+                #[cfg(not(feature = "shm"))]
+                {
+                    future::err(Error::TransportUnavailable).boxed()
+                }
+            }
+
             (Some("ipc"), Some(addr)) => {
                 #[cfg(feature = "ipc")]
                 {
@@ -104,6 +179,18 @@ impl fmt::Display for Endpoint {
             #[cfg(feature = "udp")]
             Endpoint::Udp(addr) => write!(f, "udp://{}", addr),
 
+            #[cfg(feature = "tls")]
+            Endpoint::Tls(addr) => write!(f, "tls://{}", addr),
+
+            #[cfg(feature = "ws")]
+            Endpoint::Ws(addr, path) => write!(f, "ws://{}{}", addr, path),
+
+            #[cfg(all(feature = "ws", feature = "tls"))]
+            Endpoint::Wss(addr, path) => write!(f, "wss://{}{}", addr, path),
+
+            #[cfg(feature = "shm")]
+            Endpoint::Shm(name) => write!(f, "shm://{}", name),
+
             #[cfg(feature = "ipc")]
             Endpoint::Ipc(addr) => write!(f, "ipc://{}", addr),
 
@@ -113,9 +200,20 @@ impl fmt::Display for Endpoint {
     }
 }
 
+// Splits "host:port/path" into the address and the path, which defaults to "/".
+fn split_path(mut addr: String) -> (String, String) {
+    match addr.find('/') {
+        Some(start) => {
+            let path = addr.split_off(start);
+            (addr, path)
+        }
+        None => (addr, "/".to_owned()),
+    }
+}
+
 async fn resolve(addr: String) -> Result<net::SocketAddr, Error> {
-    match tokio::net::lookup_host(addr).await {
-        Ok(mut res) => res.next().ok_or(Error::AddressNotFound),
+    match Rt::resolve(addr).await {
+        Ok(res) => res.into_iter().next().ok_or(Error::AddressNotFound),
         Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => Err(Error::AddressInvalid),
         Err(_) => Err(Error::AddressNotFound),
     }
@@ -126,7 +224,8 @@ mod tests {
     use super::*;
 
     #[cfg(feature = "tcp")]
-    #[tokio::test]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
     async fn to_endpoint_returns_resolved_tcp() {
         let resolved = "tcp://localhost:1234".to_endpoint().await.unwrap();
         assert!(
@@ -136,7 +235,8 @@ mod tests {
     }
 
     #[cfg(feature = "udp")]
-    #[tokio::test]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
     async fn to_endpoint_returns_resolved_udp() {
         let resolved = "udp://localhost:1234".to_endpoint().await.unwrap();
         assert!(
@@ -145,21 +245,61 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "tls")]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
+    async fn to_endpoint_returns_resolved_tls() {
+        let resolved = "tls://localhost:1234".to_endpoint().await.unwrap();
+        assert!(
+            resolved == Endpoint::Tls("127.0.0.1:1234".parse().unwrap())
+                || resolved == Endpoint::Tls("[::1]:1234".parse().unwrap())
+        );
+    }
+
+    #[cfg(feature = "ws")]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
+    async fn to_endpoint_returns_resolved_ws_with_path() {
+        let resolved = "ws://127.0.0.1:1234/zmq".to_endpoint().await.unwrap();
+        assert_eq!(
+            resolved,
+            Endpoint::Ws("127.0.0.1:1234".parse().unwrap(), "/zmq".to_owned())
+        );
+
+        let resolved = "ws://127.0.0.1:1234".to_endpoint().await.unwrap();
+        assert_eq!(resolved.to_string(), "ws://127.0.0.1:1234/");
+    }
+
+    #[cfg(feature = "shm")]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
+    async fn to_endpoint_returns_shm() {
+        let resolved = "shm://bench".to_endpoint().await.unwrap();
+        assert_eq!(resolved, Endpoint::Shm("bench".to_owned()));
+        assert_eq!(
+            "shm://../bench".to_endpoint().await.unwrap_err(),
+            Error::AddressInvalid
+        );
+    }
+
     #[cfg(feature = "ipc")]
-    #[tokio::test]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
     async fn to_endpoint_returns_ipc() {
         let resolved = "ipc:///var/tmp/sock".to_endpoint().await.unwrap();
         assert_eq!(resolved, Endpoint::Ipc("/var/tmp/sock".to_owned()));
     }
 
     #[cfg(feature = "inproc")]
-    #[tokio::test]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
     async fn to_endpoint_returns_inproc() {
         let resolved = "inproc://my-endpoint".to_endpoint().await.unwrap();
         assert_eq!(resolved, Endpoint::Inproc("my-endpoint".to_owned()));
     }
 
-    #[tokio::test]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
     async fn to_endpoint_returns_error() {
         assert_eq!(
             Err(Error::TransportUnknown),
@@ -10,24 +10,61 @@ pub enum Error {
     AddressInUse,
     AddressInvalid,
     AddressNotFound,
+    Timeout,
+    Unsupported,
+    ConnectionRefused,
+    HandshakeFailed,
+    OptionInvalid,
+    RequestFailed,
+    Io(io::ErrorKind),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        todo!()
-        // match self {
-        //     Self::PermissionDenied => write!(f, "permission denied"),
-        //     _ => write!(f, "other error"),
-        // }
+        match self {
+            Self::RoutingError => write!(f, "no peer with the given route"),
+            Self::PermissionDenied => write!(f, "permission denied"),
+            Self::TransportUnknown => write!(f, "unknown transport"),
+            Self::TransportUnavailable => write!(f, "transport not enabled"),
+            Self::AddressInUse => write!(f, "address in use"),
+            Self::AddressInvalid => write!(f, "invalid address"),
+            Self::AddressNotFound => write!(f, "address not found"),
+            Self::Timeout => write!(f, "operation timed out"),
+            Self::Unsupported => write!(f, "operation not supported by socket type"),
+            Self::ConnectionRefused => write!(f, "connection refused"),
+            Self::HandshakeFailed => write!(f, "handshake failed"),
+            Self::OptionInvalid => write!(f, "invalid socket option"),
+            Self::RequestFailed => write!(f, "request could not be handled"),
+            Self::Io(kind) => write!(f, "I/O error: {}", kind),
+        }
     }
 }
 
+impl std::error::Error for Error {}
+
 impl From<io::Error> for Error {
     fn from(cause: io::Error) -> Self {
         match cause.kind() {
             io::ErrorKind::PermissionDenied => Self::PermissionDenied,
             io::ErrorKind::AddrInUse => Self::AddressInUse,
-            err => panic!("io error {:?}", err),
+            io::ErrorKind::ConnectionRefused => Self::ConnectionRefused,
+            io::ErrorKind::AddrNotAvailable => Self::AddressNotFound,
+            io::ErrorKind::TimedOut => Self::Timeout,
+            kind => Self::Io(kind),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_io_errors_of_any_kind() {
+        let err = io::Error::from(io::ErrorKind::TimedOut);
+        assert_eq!(Error::from(err), Error::Timeout);
+
+        let err = io::Error::from(io::ErrorKind::BrokenPipe);
+        assert_eq!(Error::from(err), Error::Io(io::ErrorKind::BrokenPipe));
+    }
+}
@@ -1,5 +1,5 @@
 #![warn(rust_2018_idioms)]
-#![forbid(unsafe_code)]
+#![deny(unsafe_code)]
 #![allow(dead_code)] // todo
 #![allow(unused_imports)] // todo
 #![allow(unused_variables)] // todo
@@ -15,31 +15,76 @@ mod dispatch;
 mod endpoint;
 mod error;
 mod message;
+#[cfg(feature = "tcp")]
+mod probe;
+pub mod proxy;
+mod rpc;
+mod runtime;
 mod session;
+// Maps memory shared with other processes.
+#[cfg(feature = "shm")]
+#[allow(unsafe_code)]
+mod shm;
 mod socket;
+mod stats;
 mod sync;
+#[cfg(feature = "tls")]
+mod tls;
+pub mod tracer;
+// Submits socket operations to the kernel through a shared ring.
+#[cfg(feature = "io-uring")]
+#[allow(unsafe_code)]
+mod uring;
 mod util;
-mod zmtp;
+pub mod zmtp;
+
+pub mod blocking;
+
+#[cfg(feature = "serde")]
+pub mod typed;
+
+#[cfg(feature = "tower")]
+pub mod tower;
 
 pub use endpoint::{Endpoint, ToEndpoint};
 pub use error::Error;
 pub use message::{Envelope, Group, IntoMessage, Message, Route};
+#[cfg(feature = "tcp")]
+pub use probe::{probe, Handshake};
+pub use proxy::{proxy, proxy_rpc, proxy_rpc_steerable, proxy_steerable};
+pub use rpc::Request;
+pub use socket::Options;
+pub use stats::{PeerStats, Stats};
+
+#[cfg(feature = "tls")]
+pub use tls::TlsOptions;
 
 use futures::future::poll_fn;
-use futures::{Future, FutureExt};
+use futures::{Future, FutureExt, Sink, Stream};
 use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
+// Sockets that are a Sink keep the item that waits to be sent.
 macro_rules! define_socket {
     ($name:ident) => {
+        define_socket!(@socket $name {});
+    };
+    ($name:ident, $item:ty) => {
+        define_socket!(@socket $name { outgoing: Option<$item> });
+    };
+    (@socket $name:ident { $($field:ident: $ty:ty)? }) => {
         #[derive(Default, Debug)]
         pub struct $name {
             inner: socket::Socket<socket::$name>,
+            $($field: $ty,)?
         }
 
         impl $name {
             pub fn with_options(options: socket::Options) -> Self {
                 Self {
                     inner: socket::Socket::with_options(options),
+                    $($field: None,)?
                 }
             }
 
@@ -50,6 +95,10 @@ macro_rules! define_socket {
             pub async fn connect(&self, addr: impl ToEndpoint) -> Result<Route, Error> {
                 self.inner.connect(addr).await
             }
+
+            pub fn stats(&self) -> Stats {
+                self.inner.stats()
+            }
         }
     };
 }
@@ -60,6 +109,26 @@ macro_rules! define_recv {
             pub async fn recv(&self) -> Result<Envelope<Message>, Error> {
                 poll_fn(|cx| self.inner.rx().poll_recv(cx)).await
             }
+
+            // Waits for a message, then adds up to max messages that are ready
+            // to the batch. Returns how many were added.
+            pub async fn recv_batch(
+                &self,
+                batch: &mut Vec<Envelope<Message>>,
+                max: usize,
+            ) -> Result<usize, Error> {
+                let len = batch.len();
+                poll_fn(|cx| self.inner.rx().poll_recv_batch(batch, max, cx)).await?;
+                Ok(batch.len() - len)
+            }
+        }
+
+        impl Stream for $name {
+            type Item = Result<Envelope<Message>, Error>;
+
+            fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                self.inner.rx().poll_recv(cx).map(Some)
+            }
         }
     };
 }
@@ -71,6 +140,47 @@ macro_rules! define_send {
                 let mut message = Some(message.into_message());
                 poll_fn(|cx| self.inner.tx().poll_send(&mut message, cx)).await
             }
+
+            // The messages are queued to the same peer while it has room, so
+            // that it writes them out together.
+            pub async fn send_batch<I>(&self, messages: I) -> Result<(), Error>
+            where
+                I: IntoIterator,
+                I::Item: IntoMessage,
+            {
+                let mut batch = messages
+                    .into_iter()
+                    .map(IntoMessage::into_message)
+                    .collect();
+                poll_fn(|cx| self.inner.tx().poll_send_batch(&mut batch, cx)).await
+            }
+        }
+
+        impl Sink<Message> for $name {
+            type Error = Error;
+
+            fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                let this = self.get_mut();
+                if this.outgoing.is_some() {
+                    futures::ready!(this.inner.tx().poll_send(&mut this.outgoing, cx))?;
+                }
+
+                Poll::Ready(Ok(()))
+            }
+
+            fn start_send(self: Pin<&mut Self>, message: Message) -> Result<(), Error> {
+                debug_assert!(self.outgoing.is_none());
+                self.get_mut().outgoing.replace(message);
+                Ok(())
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                self.poll_ready(cx)
+            }
+
+            fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                self.poll_flush(cx)
+            }
         }
     };
 }
@@ -87,18 +197,90 @@ macro_rules! define_route {
                 poll_fn(|cx| self.inner.tx().poll_route(&mut message, route, cx)).await
             }
         }
+
+        impl Sink<(Message, Route)> for $name {
+            type Error = Error;
+
+            fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                let this = self.get_mut();
+                if let Some((message, route)) = this.outgoing.take() {
+                    let mut message = Some(message);
+                    let poll = this.inner.tx().poll_route(&mut message, route, cx);
+
+                    // Routing errors consume the message; the sink remains usable.
+                    if poll.is_pending() {
+                        this.outgoing = message.map(|message| (message, route));
+                    }
+
+                    futures::ready!(poll)?;
+                }
+
+                Poll::Ready(Ok(()))
+            }
+
+            fn start_send(
+                self: Pin<&mut Self>,
+                (message, route): (Message, Route),
+            ) -> Result<(), Error> {
+                debug_assert!(self.outgoing.is_none());
+                self.get_mut().outgoing.replace((message, route));
+                Ok(())
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                self.poll_ready(cx)
+            }
+
+            fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                self.poll_flush(cx)
+            }
+        }
     };
 }
 
-define_socket!(Server);
+define_socket!(Server, (Message, Route));
 define_recv!(Server);
 define_route!(Server);
+impl Server {
+    pub async fn recv_request(&self) -> Result<Request, Error> {
+        loop {
+            if let Some(request) = rpc::request(self.recv().await?) {
+                return Ok(request);
+            }
+
+            trace!("rpc", "discarding request without correlation id");
+        }
+    }
 
-define_socket!(Client);
+    pub async fn respond(&self, request: &Request, message: impl IntoMessage) -> Result<(), Error> {
+        let message = rpc::reply(request, message.into_message());
+        self.route(message, request.route()).await
+    }
+}
+
+define_socket!(Client, Message);
 define_recv!(Client);
 define_send!(Client);
+impl Client {
+    pub async fn request(&self, message: impl IntoMessage) -> Result<Envelope<Message>, Error> {
+        let pending = self.inner.base().requests.register();
+        self.send(pending.encode(message.into_message())).await?;
+        poll_fn(|cx| pending.poll_reply(self.inner.rx(), cx)).await
+    }
+
+    pub async fn request_timeout(
+        &self,
+        message: impl IntoMessage,
+        timeout: Duration,
+    ) -> Result<Envelope<Message>, Error> {
+        match runtime::timeout(timeout, self.request(message)).await {
+            Some(result) => result,
+            None => Err(Error::Timeout),
+        }
+    }
+}
 
-define_socket!(Scatter);
+define_socket!(Scatter, Message);
 define_send!(Scatter);
 
 define_socket!(Gather);
@@ -107,11 +289,32 @@ define_recv!(Gather);
 define_socket!(Radio);
 impl Radio {
     pub fn broadcast(&self, message: impl IntoMessage, group: Group) -> Result<(), Error> {
-        self.inner.tx().publish(message.into_message());
+        self.inner.tx().publish(message.into_message_with_group(group));
         Ok(())
     }
 }
 
+impl Sink<(Message, Group)> for Radio {
+    type Error = Error;
+
+    // Broadcasting never waits for peers.
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, (message, group): (Message, Group)) -> Result<(), Error> {
+        self.broadcast(message, group)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
 define_socket!(Dish);
 define_recv!(Dish);
 impl Dish {
@@ -124,6 +327,6 @@ impl Dish {
     }
 }
 
-define_socket!(Peer);
+define_socket!(Peer, (Message, Route));
 define_recv!(Peer);
 define_route!(Peer);
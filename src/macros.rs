@@ -11,3 +11,16 @@ macro_rules! trace {
         tracing::trace!(target: concat!("rmq::", $scope), $($arg)+)
     }
 }
+
+// Creates a span, or a placeholder if tracing is disabled.
+macro_rules! span {
+    ($scope:expr, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(target: concat!("rmq::", $scope), $($arg)+);
+
+        #[cfg(not(feature = "tracing"))]
+        let span = crate::util::Span;
+
+        span
+    }}
+}
@@ -6,9 +6,11 @@ use std::fmt;
 use std::net::SocketAddr;
 use std::num::NonZeroU8;
 use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::sync::Arc;
 use crate::zmtp::SocketType;
+use crate::Endpoint;
 
 #[derive(Default, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Payload {
@@ -53,7 +55,7 @@ pub struct Group {
 pub struct GroupError;
 
 impl Group {
-    fn as_bytes(&self) -> &[u8] {
+    pub fn as_bytes(&self) -> &[u8] {
         &self.buffer[..self.length as usize]
     }
 }
@@ -124,6 +126,19 @@ pub struct Route {
     pub(crate) id: u32,
 }
 
+impl Route {
+    // Identifies the peer, for example across a C API.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl From<u32> for Route {
+    fn from(id: u32) -> Self {
+        Self { id }
+    }
+}
+
 impl fmt::Display for Route {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:08x}", self.id)
@@ -156,6 +171,11 @@ impl Message {
     pub fn into_bytes(self) -> bytes::Bytes {
         self.payload.into_bytes()
     }
+
+    // Empty unless the message was broadcast to a group.
+    pub fn group(&self) -> &Group {
+        &self.group
+    }
 }
 
 pub trait IntoMessage: Sized {
@@ -179,22 +199,101 @@ impl<T: Into<Payload>> IntoMessage for T {
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
+impl IntoMessage for Message {
+    fn into_payload(self) -> Payload {
+        self.payload
+    }
+
+    fn into_message(self) -> Message {
+        self
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub(crate) struct Info {
     pub(crate) peer_address: Option<SocketAddr>,
+    pub(crate) local_endpoint: Option<Endpoint>,
+    pub(crate) socket_type: Option<SocketType>,
     pub(crate) identity: Option<Vec<u8>>,
     pub(crate) resource: Option<Vec<u8>>,
     pub(crate) custom: HashMap<Cow<'static, str>, Vec<u8>>,
+
+    // Only set by security mechanisms that authenticate the peer.
+    pub(crate) user_id: Option<Vec<u8>>,
+    pub(crate) peer_subject: Option<String>,
+
+    pub(crate) epoch: Epoch,
+}
+
+// Connections are the same regardless of the epoch, which only dates the
+// messages received on them.
+impl PartialEq for Info {
+    fn eq(&self, other: &Self) -> bool {
+        let Info {
+            peer_address,
+            local_endpoint,
+            socket_type,
+            identity,
+            resource,
+            custom,
+            user_id,
+            peer_subject,
+            epoch: _,
+        } = self;
+
+        *peer_address == other.peer_address
+            && *local_endpoint == other.local_endpoint
+            && *socket_type == other.socket_type
+            && *identity == other.identity
+            && *resource == other.resource
+            && *custom == other.custom
+            && *user_id == other.user_id
+            && *peer_subject == other.peer_subject
+    }
 }
 
-#[derive(Clone, PartialEq, Eq)]
+impl Eq for Info {}
+
+// Receive times are relative to this, in nanoseconds since the Unix epoch.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Epoch(u64);
+
+#[derive(Clone)]
 pub struct Envelope<T> {
     pub(crate) info: Arc<Info>,
     pub route: Route,
     pub message: T,
+
+    // Microseconds since the epoch of the info plus one, or zero if unknown.
+    // Fits in the padding after the route.
+    pub(crate) received: u32,
 }
 
-impl fmt::Debug for Envelope<Message> {
+// Returns the receive time of a message that is received now, moving the
+// epoch forward once the time no longer fits.
+pub(crate) fn received(info: &mut Arc<Info>) -> u32 {
+    let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_nanos() as u64,
+        Err(..) => return 0,
+    };
+
+    let Epoch(epoch) = info.epoch;
+    if epoch != 0 && now >= epoch {
+        let micros = (now - epoch) / 1000 + 1;
+        if micros <= u32::MAX as u64 {
+            return micros as u32;
+        }
+    }
+
+    *info = Arc::new(Info {
+        epoch: Epoch(now),
+        ..Info::clone(info)
+    });
+
+    1
+}
+
+impl<T: fmt::Debug> fmt::Debug for Envelope<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Envelope")
             .field("route", &self.route)
@@ -203,11 +302,47 @@ impl fmt::Debug for Envelope<Message> {
     }
 }
 
-impl Envelope<Message> {
+// Envelopes are equal regardless of when they were received.
+impl<T: PartialEq> PartialEq for Envelope<T> {
+    fn eq(&self, other: &Self) -> bool {
+        *self.info == *other.info && self.route == other.route && self.message == other.message
+    }
+}
+
+impl<T: Eq> Eq for Envelope<T> {}
+
+impl<T> Envelope<T> {
     pub fn peer_address(&self) -> Option<&SocketAddr> {
         self.info.peer_address.as_ref()
     }
 
+    pub fn peer_socket_type(&self) -> Option<SocketType> {
+        self.info.socket_type
+    }
+
+    pub fn local_endpoint(&self) -> Option<&Endpoint> {
+        self.info.local_endpoint.as_ref()
+    }
+
+    pub fn user_id(&self) -> Option<&[u8]> {
+        self.info.user_id.as_ref().map(Vec::as_ref)
+    }
+
+    pub fn peer_subject(&self) -> Option<&str> {
+        self.info.peer_subject.as_deref()
+    }
+
+    pub fn received_at(&self) -> Option<SystemTime> {
+        let Epoch(epoch) = self.info.epoch;
+        match self.received {
+            0 => None,
+            micros => {
+                let since = Duration::from_micros(u64::from(micros) - 1);
+                Some(UNIX_EPOCH + Duration::from_nanos(epoch) + since)
+            }
+        }
+    }
+
     pub fn peer_identity(&self) -> Option<&[u8]> {
         self.info.identity.as_ref().map(Vec::as_ref)
     }
@@ -221,6 +356,24 @@ impl Envelope<Message> {
         self.info.custom.get(key).map(Vec::as_ref)
     }
 
+    pub fn metadata(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.info
+            .custom
+            .iter()
+            .map(|(key, value)| (key.as_ref(), value.as_ref()))
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Envelope<U> {
+        Envelope {
+            info: self.info,
+            route: self.route,
+            message: f(self.message),
+            received: self.received,
+        }
+    }
+}
+
+impl Envelope<Message> {
     pub fn as_bytes(&self) -> &[u8] {
         self.message.as_bytes()
     }
@@ -246,6 +399,42 @@ mod tests {
         assert_le!(std::mem::size_of::<Envelope<Message>>(), 64);
     }
 
+    #[test]
+    fn received_moves_epoch_forward_when_needed() {
+        let mut info = Arc::new(Info::default());
+        assert_eq!(received(&mut info), 1);
+
+        let epoch = info.epoch.0;
+        assert_ne!(epoch, 0);
+        assert_ge!(received(&mut info), 1);
+        assert_eq!(info.epoch.0, epoch);
+
+        // More than 2^32 microseconds ago.
+        Arc::get_mut(&mut info).unwrap().epoch = Epoch(epoch - (5000 << 30));
+        assert_eq!(received(&mut info), 1);
+        assert_ge!(info.epoch.0, epoch);
+    }
+
+    #[test]
+    fn info_eq_ignores_epoch() {
+        let info = Info {
+            identity: Some(b"peer".to_vec()),
+            ..Default::default()
+        };
+
+        let later = Info {
+            epoch: Epoch(1),
+            ..info.clone()
+        };
+        assert_eq!(info, later);
+
+        let other = Info {
+            identity: None,
+            ..later.clone()
+        };
+        assert_ne!(later, other);
+    }
+
     #[test]
     fn group_parse_parses_valid_group() {
         let group = "hello world!".parse::<Group>();
@@ -271,4 +460,11 @@ mod tests {
         let group = "hello\x01world!".parse::<Group>().expect("parse");
         assert_eq!("b\"hello\\x01world!\"", group.to_string());
     }
+
+    #[test]
+    fn route_from_id() {
+        let route = Route::from(0xdead_beef);
+        assert_eq!(route.id(), 0xdead_beef);
+        assert_eq!("deadbeef", route.to_string());
+    }
 }
@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+use std::str;
+use std::time::Duration;
+
+use crate::runtime::{self, Rt, Runtime};
+use crate::{zmtp, Endpoint, Error, ToEndpoint};
+
+// Remotes that accept the connection but never complete the handshake are
+// given up on after this long.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    pub socket_type: String,
+    pub properties: BTreeMap<String, Vec<u8>>,
+}
+
+// Connects to a remote socket, presenting ourselves as the given socket type,
+// and returns the properties the remote sent in its READY command. Only TCP
+// endpoints are supported; others fail with Error::Unsupported.
+pub async fn probe(addr: impl ToEndpoint, socket_type: &str) -> Result<Handshake, Error> {
+    let socket_type = zmtp::SocketType::from_bytes(socket_type.to_uppercase().as_bytes())
+        .ok_or(Error::Unsupported)?;
+
+    let params = zmtp::Params {
+        socket_type,
+        security: zmtp::Security::Null,
+        properties: Default::default(),
+    };
+
+    let addr = match addr.to_endpoint().await? {
+        Endpoint::Tcp(addr) => addr,
+
+        // Other transports do not perform a ZMTP handshake.
+        #[allow(unreachable_patterns)]
+        _ => return Err(Error::Unsupported),
+    };
+
+    let handshake = async {
+        let mut transport = zmtp::frame(Rt::tcp_connect(addr).await?);
+        zmtp::connect(&mut transport, &params)
+            .await
+            .map_err(|_| Error::HandshakeFailed)
+    };
+
+    let info = runtime::timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .unwrap_or(Err(Error::Timeout))?;
+
+    Ok(Handshake {
+        socket_type: String::from_utf8_lossy(info.socket_type.as_bytes()).into_owned(),
+        properties: info
+            .properties
+            .into_iter()
+            .map(|(key, value)| (key.into_owned(), value))
+            .collect(),
+    })
+}
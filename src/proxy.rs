@@ -0,0 +1,427 @@
+use futures::future::poll_fn;
+use std::collections::HashMap;
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{rpc, Envelope, Error, Message, Route};
+use crate::{Client, Dish, Gather, Peer, Radio, Scatter, Server};
+
+const BUDGET: usize = 64;
+const MAX_ROUTES: u32 = 1 << 16;
+
+pub trait Forward: Sync {
+    // Routed sockets need to know which peer to send each message to.
+    fn routed(&self) -> bool {
+        false
+    }
+
+    fn poll_recv(&self, _cx: &mut Context<'_>) -> Poll<Result<Envelope<Message>, Error>> {
+        Poll::Pending
+    }
+
+    fn poll_send(
+        &self,
+        _message: &mut Option<Message>,
+        _route: Route,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        Poll::Ready(Err(Error::Unsupported))
+    }
+}
+
+macro_rules! forward_recv {
+    () => {
+        fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<Envelope<Message>, Error>> {
+            self.inner.rx().poll_recv(cx)
+        }
+    };
+}
+
+macro_rules! forward_send {
+    () => {
+        fn poll_send(
+            &self,
+            message: &mut Option<Message>,
+            _route: Route,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Error>> {
+            self.inner.tx().poll_send(message, cx)
+        }
+    };
+}
+
+macro_rules! forward_route {
+    () => {
+        fn routed(&self) -> bool {
+            true
+        }
+
+        fn poll_send(
+            &self,
+            message: &mut Option<Message>,
+            route: Route,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Error>> {
+            self.inner.tx().poll_route(message, route, cx)
+        }
+    };
+}
+
+impl Forward for Server {
+    forward_recv!();
+    forward_route!();
+}
+
+impl Forward for Client {
+    forward_recv!();
+    forward_send!();
+}
+
+impl Forward for Scatter {
+    forward_send!();
+}
+
+impl Forward for Gather {
+    forward_recv!();
+}
+
+impl Forward for Radio {
+    fn poll_send(
+        &self,
+        message: &mut Option<Message>,
+        _route: Route,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        if let Some(message) = message.take() {
+            self.inner.tx().publish(message);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Forward for Dish {
+    forward_recv!();
+}
+
+impl Forward for Peer {
+    forward_recv!();
+    forward_route!();
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    pub messages_in: u64,
+    pub bytes_in: u64,
+    pub messages_out: u64,
+    pub bytes_out: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Statistics {
+    pub frontend: Counters,
+    pub backend: Counters,
+}
+
+#[derive(Debug)]
+enum Command {
+    Pause,
+    Resume,
+    Terminate,
+    Statistics(oneshot::Sender<Statistics>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Controller {
+    tx: mpsc::UnboundedSender<Command>,
+}
+
+impl Controller {
+    pub fn pause(&self) {
+        let _ = self.tx.send(Command::Pause);
+    }
+
+    pub fn resume(&self) {
+        let _ = self.tx.send(Command::Resume);
+    }
+
+    pub fn terminate(&self) {
+        let _ = self.tx.send(Command::Terminate);
+    }
+
+    // Returns nothing if the proxy has already terminated.
+    pub async fn statistics(&self) -> Option<Statistics> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(Command::Statistics(tx)).ok()?;
+        rx.await.ok()
+    }
+}
+
+#[derive(Debug)]
+pub struct Control {
+    rx: mpsc::UnboundedReceiver<Command>,
+}
+
+pub fn control() -> (Controller, Control) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (Controller { tx }, Control { rx })
+}
+
+// Maps correlation ids of requests received on a routed socket to their
+// origin, so that replies can be routed back to the requesting peer.
+#[derive(Debug, Default)]
+struct Routes {
+    sequence: u32,
+    pending: HashMap<u32, (Route, u32)>,
+}
+
+impl Routes {
+    fn request(&mut self, envelope: Envelope<Message>) -> Message {
+        let (id, envelope) = match rpc::decode(envelope.clone()) {
+            Some(request) => request,
+            None => {
+                trace!("proxy", "forwarding request without correlation id");
+                return envelope.message;
+            }
+        };
+
+        let proxy_id = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1) & !rpc::FAILED;
+
+        // Forget requests that are unlikely to ever receive a reply.
+        self.pending
+            .remove(&(proxy_id.wrapping_sub(MAX_ROUTES) & !rpc::FAILED));
+        self.pending.insert(proxy_id, (envelope.route, id));
+        rpc::encode(proxy_id, envelope.message)
+    }
+
+    fn reply(&mut self, envelope: Envelope<Message>) -> Option<(Message, Route)> {
+        let (proxy_id, envelope) = rpc::decode(envelope)?;
+        let (route, id) = self.pending.remove(&(proxy_id & !rpc::FAILED))?;
+        Some((
+            rpc::encode(id | proxy_id & rpc::FAILED, envelope.message),
+            route,
+        ))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pipe {
+    outgoing: Option<Message>,
+    route: Option<Route>,
+    captured: Option<Message>,
+    routes: Routes,
+}
+
+struct Proxy<'a> {
+    frontend: &'a dyn Forward,
+    backend: &'a dyn Forward,
+    capture: Option<&'a dyn Forward>,
+    control: Option<Control>,
+    paused: bool,
+    statistics: Statistics,
+    upstream: Pipe,
+    downstream: Pipe,
+}
+
+impl Proxy<'_> {
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.poll_control(cx).is_ready() {
+            return Poll::Ready(Ok(()));
+        }
+
+        if self.paused {
+            return Poll::Pending;
+        }
+
+        for _ in 0..BUDGET {
+            let upstream = Self::poll_pipe(
+                self.frontend,
+                self.backend,
+                self.capture,
+                &mut self.upstream,
+                &mut self.downstream.routes,
+                &mut self.statistics.frontend,
+                &mut self.statistics.backend,
+                cx,
+            )?;
+
+            let downstream = Self::poll_pipe(
+                self.backend,
+                self.frontend,
+                self.capture,
+                &mut self.downstream,
+                &mut self.upstream.routes,
+                &mut self.statistics.backend,
+                &mut self.statistics.frontend,
+                cx,
+            )?;
+
+            if upstream.is_pending() && downstream.is_pending() {
+                return Poll::Pending;
+            }
+        }
+
+        // Yield to other tasks when both sockets are continuously busy.
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+
+    fn poll_control(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        while let Some(control) = &mut self.control {
+            match control.rx.poll_recv(cx) {
+                Poll::Ready(Some(Command::Pause)) => self.paused = true,
+                Poll::Ready(Some(Command::Resume)) => self.paused = false,
+                Poll::Ready(Some(Command::Terminate)) => return Poll::Ready(()),
+                Poll::Ready(Some(Command::Statistics(tx))) => {
+                    let _ = tx.send(self.statistics);
+                }
+
+                // Keep running after all controllers have been dropped.
+                Poll::Ready(None) => self.control = None,
+                Poll::Pending => break,
+            }
+        }
+
+        Poll::Pending
+    }
+
+    // Forwards a single message from one socket to the other. Returns pending
+    // if no progress could be made.
+    #[allow(clippy::too_many_arguments)]
+    fn poll_pipe(
+        from: &dyn Forward,
+        to: &dyn Forward,
+        capture: Option<&dyn Forward>,
+        pipe: &mut Pipe,
+        reply_routes: &mut Routes,
+        from_counters: &mut Counters,
+        to_counters: &mut Counters,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        if let (Some(capture), Some(..)) = (capture, &pipe.captured) {
+            futures::ready!(capture.poll_send(&mut pipe.captured, Route { id: 0 }, cx))?;
+            pipe.captured.take();
+        }
+
+        if let Some(message) = &pipe.outgoing {
+            let route = pipe.route.unwrap_or(Route { id: 0 });
+            let len = message.as_bytes().len() as u64;
+            match futures::ready!(to.poll_send(&mut pipe.outgoing, route, cx)) {
+                Ok(()) => {
+                    to_counters.messages_out += 1;
+                    to_counters.bytes_out += len;
+                }
+
+                // The peer has disconnected; the message is discarded.
+                Err(Error::RoutingError) => {
+                    trace!(
+                        "proxy",
+                        "discarding message to unknown peer; route={}",
+                        route
+                    );
+                }
+
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+
+            pipe.outgoing.take();
+            pipe.route.take();
+            return Poll::Ready(Ok(()));
+        }
+
+        let envelope = futures::ready!(from.poll_recv(cx))?;
+        from_counters.messages_in += 1;
+        from_counters.bytes_in += envelope.as_bytes().len() as u64;
+
+        if capture.is_some() {
+            pipe.captured.replace(envelope.message.clone());
+        }
+
+        if to.routed() {
+            if let Some((message, route)) = reply_routes.reply(envelope) {
+                pipe.outgoing.replace(message);
+                pipe.route.replace(route);
+            } else {
+                trace!("proxy", "discarding reply to unknown request");
+            }
+        } else if from.routed() {
+            pipe.outgoing.replace(pipe.routes.request(envelope));
+        } else {
+            pipe.route.replace(envelope.route);
+            pipe.outgoing.replace(envelope.message);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Forwards messages as they are. Routed sockets need proxy_rpc to route
+// replies back to their peers.
+pub async fn proxy(
+    frontend: &dyn Forward,
+    backend: &dyn Forward,
+    capture: Option<&dyn Forward>,
+) -> Result<(), Error> {
+    run(frontend, backend, capture, None, false).await
+}
+
+pub async fn proxy_steerable(
+    frontend: &dyn Forward,
+    backend: &dyn Forward,
+    capture: Option<&dyn Forward>,
+    control: Control,
+) -> Result<(), Error> {
+    run(frontend, backend, capture, Some(control), false).await
+}
+
+// Forwards requests made with Client::request, rewriting their correlation ids
+// so that replies can be routed back to the peer of a routed socket.
+pub async fn proxy_rpc(
+    frontend: &dyn Forward,
+    backend: &dyn Forward,
+    capture: Option<&dyn Forward>,
+) -> Result<(), Error> {
+    run(frontend, backend, capture, None, true).await
+}
+
+pub async fn proxy_rpc_steerable(
+    frontend: &dyn Forward,
+    backend: &dyn Forward,
+    capture: Option<&dyn Forward>,
+    control: Control,
+) -> Result<(), Error> {
+    run(frontend, backend, capture, Some(control), true).await
+}
+
+async fn run(
+    frontend: &dyn Forward,
+    backend: &dyn Forward,
+    capture: Option<&dyn Forward>,
+    control: Option<Control>,
+    rpc: bool,
+) -> Result<(), Error> {
+    // Captured messages do not belong to any particular peer.
+    if capture.is_some_and(|capture| capture.routed()) {
+        return Err(Error::Unsupported);
+    }
+
+    // Without correlation ids there is no way to tell which peer a message
+    // is meant for, so replies could never reach a routed socket.
+    if !rpc && (frontend.routed() || backend.routed()) {
+        return Err(Error::Unsupported);
+    }
+
+    let mut proxy = Proxy {
+        frontend,
+        backend,
+        capture,
+        control,
+        paused: false,
+        statistics: Statistics::default(),
+        upstream: Pipe::default(),
+        downstream: Pipe::default(),
+    };
+
+    poll_fn(|cx| proxy.poll(cx)).await
+}
@@ -0,0 +1,256 @@
+use bytes::{BufMut, BytesMut};
+use std::collections::HashMap;
+use std::task::{Context, Poll, Waker};
+
+use crate::dispatch::FairReceiver;
+use crate::message::Payload;
+use crate::sync::Mutex;
+use crate::util::Sequence;
+use crate::{Envelope, Error, Message, Route};
+
+const ID_LEN: usize = 4;
+
+// Set in the correlation id of replies to requests that could not be handled;
+// ids of requests never have it set.
+pub(crate) const FAILED: u32 = 1 << 31;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    id: u32,
+    pub envelope: Envelope<Message>,
+}
+
+impl Request {
+    pub fn route(&self) -> Route {
+        self.envelope.route
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.envelope.as_bytes()
+    }
+
+    pub fn into_envelope(self) -> Envelope<Message> {
+        self.envelope
+    }
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    reply: Option<Result<Envelope<Message>, Error>>,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Correlator {
+    sequence: Sequence,
+    pending: Mutex<HashMap<u32, Slot>>,
+}
+
+impl Correlator {
+    pub(crate) fn register(&self) -> Pending<'_> {
+        let id = self.sequence.next() & !FAILED;
+        self.pending.lock().insert(id, Slot::default());
+        Pending {
+            correlator: self,
+            id,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Pending<'a> {
+    correlator: &'a Correlator,
+    id: u32,
+}
+
+impl Pending<'_> {
+    pub(crate) fn encode(&self, message: Message) -> Message {
+        encode(self.id, message)
+    }
+
+    pub(crate) fn poll_reply(
+        &self,
+        receiver: &FairReceiver,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Envelope<Message>, Error>> {
+        {
+            let mut pending = self.correlator.pending.lock();
+            let slot = pending.get_mut(&self.id).expect("request not registered");
+            if let Some(reply) = slot.reply.take() {
+                return Poll::Ready(reply);
+            }
+
+            slot.waker.replace(cx.waker().clone());
+        }
+
+        // Only the task that polled the receiver last is woken when a reply
+        // arrives, so replies to other requests are handed off to their slots.
+        loop {
+            let (id, envelope) = match decode(futures::ready!(receiver.poll_recv(cx))?) {
+                Some(reply) => reply,
+                None => {
+                    trace!("rpc", "discarding reply without correlation id");
+                    continue;
+                }
+            };
+
+            let reply = match id & FAILED {
+                0 => Ok(envelope),
+                _ => Err(Error::RequestFailed),
+            };
+
+            let id = id & !FAILED;
+            if id == self.id {
+                return Poll::Ready(reply);
+            }
+
+            if let Some(slot) = self.correlator.pending.lock().get_mut(&id) {
+                slot.reply.replace(reply);
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            } else {
+                trace!("rpc", "discarding reply to unknown request; id={:08x}", id);
+            }
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.correlator.pending.lock();
+        pending.remove(&self.id);
+
+        // This request may have been the one registered with the receiver;
+        // wake another so it takes over receiving replies.
+        if let Some(waker) = pending.values_mut().find_map(|slot| slot.waker.take()) {
+            waker.wake();
+        }
+    }
+}
+
+pub(crate) fn encode(id: u32, message: Message) -> Message {
+    let mut payload = BytesMut::with_capacity(ID_LEN + message.payload.len());
+    payload.put_u32(id);
+    payload.put_slice(message.as_bytes());
+
+    Message {
+        payload: Payload::from(payload.freeze()),
+        group: message.group,
+    }
+}
+
+pub(crate) fn decode(envelope: Envelope<Message>) -> Option<(u32, Envelope<Message>)> {
+    let id = correlation_id(&envelope.message)?;
+    Some((
+        id,
+        envelope.map(|message| Message {
+            payload: Payload::from(message.payload.into_bytes().slice(ID_LEN..)),
+            group: message.group,
+        }),
+    ))
+}
+
+pub(crate) fn correlation_id(message: &Message) -> Option<u32> {
+    if message.payload.len() < ID_LEN {
+        return None;
+    }
+
+    let mut id = [0u8; ID_LEN];
+    id.copy_from_slice(&message.as_bytes()[..ID_LEN]);
+    Some(u32::from_be_bytes(id))
+}
+
+pub(crate) fn request(envelope: Envelope<Message>) -> Option<Request> {
+    let (id, envelope) = decode(envelope)?;
+    Some(Request { id, envelope })
+}
+
+pub(crate) fn reply(request: &Request, message: Message) -> Message {
+    encode(request.id, message)
+}
+
+pub(crate) fn failure(request: &Request) -> Message {
+    encode(request.id | FAILED, Message::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::{Delivery, Dispatcher};
+    use crate::socket::test_options;
+    use claim::*;
+
+    fn reply_to(id: u32, payload: u8) -> Delivery {
+        Delivery::Message(encode(id, msg!(payload)))
+    }
+
+    #[test]
+    fn decode_strips_correlation_id() {
+        let envelope = Envelope {
+            info: Default::default(),
+            route: Route { id: 1 },
+            message: encode(0x01020304, msg!(5)),
+            received: 0,
+        };
+
+        assert_eq!(envelope.as_bytes(), b"\x01\x02\x03\x04\x05");
+
+        let (id, envelope) = decode(envelope).unwrap();
+        assert_eq!(id, 0x01020304);
+        assert_eq!(envelope, envelope!(5, Route { id: 1 }));
+    }
+
+    #[test]
+    fn decode_ignores_short_messages() {
+        assert_none!(decode(envelope!(5, Route { id: 1 })));
+    }
+
+    #[test]
+    fn poll_reply_matches_out_of_order_replies() {
+        let dispatcher = Dispatcher::<(), FairReceiver>::default();
+        let mut pipe = dispatcher.registry().create(&test_options());
+        let receiver = dispatcher.rx();
+
+        let correlator = Correlator::default();
+        let request1 = correlator.register();
+        let request2 = correlator.register();
+
+        assert_pending!(request1.poll_reply(receiver, cx!()));
+        assert_pending!(request2.poll_reply(receiver, cx!()));
+
+        pipe.try_send(reply_to(request2.id, 2)).unwrap();
+        pipe.try_send(reply_to(request1.id, 1)).unwrap();
+
+        assert_ready_eq!(
+            request1.poll_reply(receiver, cx!()),
+            Ok(envelope!(1, pipe.id))
+        );
+        assert_ready_eq!(
+            request2.poll_reply(receiver, cx!()),
+            Ok(envelope!(2, pipe.id))
+        );
+    }
+
+    #[test]
+    fn poll_reply_discards_replies_to_cancelled_requests() {
+        let dispatcher = Dispatcher::<(), FairReceiver>::default();
+        let mut pipe = dispatcher.registry().create(&test_options());
+        let receiver = dispatcher.rx();
+
+        let correlator = Correlator::default();
+        let cancelled = correlator.register();
+        let cancelled_id = cancelled.id;
+        drop(cancelled);
+
+        let request = correlator.register();
+        pipe.try_send(reply_to(cancelled_id, 1)).unwrap();
+        assert_pending!(request.poll_reply(receiver, cx!()));
+
+        pipe.try_send(reply_to(request.id, 2)).unwrap();
+        assert_ready_eq!(
+            request.poll_reply(receiver, cx!()),
+            Ok(envelope!(2, pipe.id))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use async_std::net::ToSocketAddrs;
+use futures::executor::{LocalPool, LocalSpawner};
+use futures::future::BoxFuture;
+use futures::task::LocalSpawnExt;
+use futures::{Future, FutureExt};
+use std::cell::RefCell;
+use std::time::Duration;
+use std::{io, net, path::PathBuf, thread};
+use tokio::sync::mpsc;
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt};
+
+use super::{start_thread, Runtime};
+
+#[derive(Debug)]
+pub(crate) struct AsyncStd;
+
+// The global async-std runtime already runs on its own threads. I/O threads
+// poll their tasks themselves; async-std still drives the I/O.
+#[derive(Debug, Default)]
+pub(crate) struct Executor {
+    tasks: Option<mpsc::UnboundedSender<BoxFuture<'static, ()>>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.tasks.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+thread_local! {
+    // Set on I/O threads, so that tasks spawned there stay there.
+    static IO_THREAD: RefCell<Option<LocalSpawner>> = const { RefCell::new(None) };
+}
+
+impl Runtime for AsyncStd {
+    #[cfg(feature = "tcp")]
+    type TcpListener = async_std::net::TcpListener;
+
+    #[cfg(feature = "tcp")]
+    type TcpStream = Compat<async_std::net::TcpStream>;
+
+    #[cfg(feature = "udp")]
+    type UdpSocket = async_std::net::UdpSocket;
+
+    #[cfg(feature = "shm")]
+    type UnixListener = async_std::os::unix::net::UnixListener;
+
+    #[cfg(feature = "shm")]
+    type UnixStream = Compat<async_std::os::unix::net::UnixStream>;
+
+    type Executor = Executor;
+
+    fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+        match IO_THREAD.with(|spawner| spawner.borrow().clone()) {
+            Some(spawner) => {
+                let _ = spawner.spawn_local(future);
+            }
+            None => {
+                async_std::task::spawn(future);
+            }
+        }
+    }
+
+    fn spawn_on<F: Future<Output = ()> + Send + 'static>(executor: &Executor, future: F) {
+        match &executor.tasks {
+            Some(tasks) => {
+                let _ = tasks.send(future.boxed());
+            }
+            None => Self::spawn(future),
+        }
+    }
+
+    fn executor() -> io::Result<Executor> {
+        Ok(Executor::default())
+    }
+
+    fn io_thread(affinity: Vec<usize>) -> io::Result<Executor> {
+        let (tasks, mut incoming) = mpsc::unbounded_channel::<BoxFuture<'static, ()>>();
+        let thread = start_thread("rmq-io", affinity, move || {
+            let mut pool = LocalPool::new();
+            let spawner = pool.spawner();
+            IO_THREAD.with(|current| current.replace(Some(spawner.clone())));
+
+            pool.run_until(async move {
+                while let Some(task) = incoming.recv().await {
+                    let _ = spawner.spawn_local(task);
+                }
+            });
+
+            // Give sessions a chance to write out messages that were queued
+            // just before shutdown.
+            pool.run_until_stalled();
+        })?;
+
+        Ok(Executor {
+            tasks: Some(tasks),
+            thread: Some(thread),
+        })
+    }
+
+    fn block_on<F: Future>(_executor: &Executor, future: F) -> F::Output {
+        async_std::task::block_on(future)
+    }
+
+    fn delay_for(duration: Duration) -> BoxFuture<'static, ()> {
+        async_std::task::sleep(duration).boxed()
+    }
+
+    fn resolve(addr: String) -> BoxFuture<'static, io::Result<Vec<net::SocketAddr>>> {
+        async move { Ok(addr.to_socket_addrs().await?.collect()) }.boxed()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_bind(addr: net::SocketAddr) -> BoxFuture<'static, io::Result<Self::TcpListener>> {
+        async move { async_std::net::TcpListener::bind(addr).await }.boxed()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_local_addr(listener: &Self::TcpListener) -> io::Result<net::SocketAddr> {
+        listener.local_addr()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_stream_local_addr(stream: &Self::TcpStream) -> io::Result<net::SocketAddr> {
+        stream.get_ref().local_addr()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_accept(
+        listener: &mut Self::TcpListener,
+    ) -> BoxFuture<'_, io::Result<(Self::TcpStream, net::SocketAddr)>> {
+        async move {
+            let (stream, addr) = listener.accept().await?;
+            Ok((stream.compat(), addr))
+        }
+        .boxed()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_connect(addr: net::SocketAddr) -> BoxFuture<'static, io::Result<Self::TcpStream>> {
+        async move { Ok(async_std::net::TcpStream::connect(addr).await?.compat()) }.boxed()
+    }
+
+    #[cfg(feature = "udp")]
+    fn udp_bind(addr: net::SocketAddr) -> BoxFuture<'static, io::Result<Self::UdpSocket>> {
+        async move { async_std::net::UdpSocket::bind(addr).await }.boxed()
+    }
+
+    #[cfg(feature = "udp")]
+    fn udp_local_addr(socket: &Self::UdpSocket) -> io::Result<net::SocketAddr> {
+        socket.local_addr()
+    }
+
+    #[cfg(feature = "udp")]
+    fn udp_send_to<'a>(
+        socket: &'a Self::UdpSocket,
+        buf: &'a [u8],
+        addr: net::SocketAddr,
+    ) -> BoxFuture<'a, io::Result<usize>> {
+        socket.send_to(buf, addr).boxed()
+    }
+
+    #[cfg(feature = "udp")]
+    fn udp_recv_from<'a>(
+        socket: &'a Self::UdpSocket,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, net::SocketAddr)>> {
+        socket.recv_from(buf).boxed()
+    }
+
+    #[cfg(feature = "shm")]
+    fn unix_bind(path: PathBuf) -> BoxFuture<'static, io::Result<Self::UnixListener>> {
+        async move { async_std::os::unix::net::UnixListener::bind(path).await }.boxed()
+    }
+
+    #[cfg(feature = "shm")]
+    fn unix_accept(
+        listener: &mut Self::UnixListener,
+    ) -> BoxFuture<'_, io::Result<Self::UnixStream>> {
+        async move { Ok(listener.accept().await?.0.compat()) }.boxed()
+    }
+
+    #[cfg(feature = "shm")]
+    fn unix_connect(path: PathBuf) -> BoxFuture<'static, io::Result<Self::UnixStream>> {
+        async move {
+            Ok(async_std::os::unix::net::UnixStream::connect(path)
+                .await?
+                .compat())
+        }
+        .boxed()
+    }
+}
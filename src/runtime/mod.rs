@@ -0,0 +1,190 @@
+use futures::future::{self, BoxFuture, Either};
+use futures::Future;
+use std::time::Duration;
+use std::{fmt, io, net, path::PathBuf, thread};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[cfg(not(any(feature = "tokio", feature = "async-std")))]
+compile_error!("either the `tokio` or the `async-std` feature must be enabled");
+
+#[cfg(all(feature = "tokio", feature = "async-std"))]
+compile_error!("the `tokio` and `async-std` features are mutually exclusive");
+
+#[cfg(all(feature = "tokio", not(feature = "async-std")))]
+mod tokio_rt;
+
+#[cfg(all(feature = "tokio", not(feature = "async-std")))]
+pub(crate) use tokio_rt::Tokio as Rt;
+
+#[cfg(feature = "async-std")]
+mod async_std_rt;
+
+#[cfg(feature = "async-std")]
+pub(crate) use async_std_rt::AsyncStd as Rt;
+
+pub(crate) trait Runtime {
+    #[cfg(feature = "tcp")]
+    type TcpListener: Send + 'static;
+
+    #[cfg(feature = "tcp")]
+    type TcpStream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    #[cfg(feature = "udp")]
+    type UdpSocket: Send + Sync + 'static;
+
+    #[cfg(feature = "shm")]
+    type UnixListener: Send + 'static;
+
+    #[cfg(feature = "shm")]
+    type UnixStream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    type Executor: fmt::Debug + Send + Sync + 'static;
+
+    fn spawn<F: Future<Output = ()> + Send + 'static>(future: F);
+
+    fn spawn_on<F: Future<Output = ()> + Send + 'static>(executor: &Self::Executor, future: F);
+
+    fn executor() -> io::Result<Self::Executor>;
+
+    // Like an executor, but tasks spawned from it also run on its thread.
+    fn io_thread(affinity: Vec<usize>) -> io::Result<Self::Executor>;
+
+    fn block_on<F: Future>(executor: &Self::Executor, future: F) -> F::Output;
+
+    fn delay_for(duration: Duration) -> BoxFuture<'static, ()>;
+
+    fn resolve(addr: String) -> BoxFuture<'static, io::Result<Vec<net::SocketAddr>>>;
+
+    #[cfg(feature = "tcp")]
+    fn tcp_bind(addr: net::SocketAddr) -> BoxFuture<'static, io::Result<Self::TcpListener>>;
+
+    #[cfg(feature = "tcp")]
+    fn tcp_local_addr(listener: &Self::TcpListener) -> io::Result<net::SocketAddr>;
+
+    #[cfg(feature = "tcp")]
+    fn tcp_stream_local_addr(stream: &Self::TcpStream) -> io::Result<net::SocketAddr>;
+
+    #[cfg(feature = "tcp")]
+    fn tcp_accept(
+        listener: &mut Self::TcpListener,
+    ) -> BoxFuture<'_, io::Result<(Self::TcpStream, net::SocketAddr)>>;
+
+    #[cfg(feature = "tcp")]
+    fn tcp_connect(addr: net::SocketAddr) -> BoxFuture<'static, io::Result<Self::TcpStream>>;
+
+    #[cfg(feature = "udp")]
+    fn udp_bind(addr: net::SocketAddr) -> BoxFuture<'static, io::Result<Self::UdpSocket>>;
+
+    #[cfg(feature = "udp")]
+    fn udp_local_addr(socket: &Self::UdpSocket) -> io::Result<net::SocketAddr>;
+
+    #[cfg(feature = "udp")]
+    fn udp_send_to<'a>(
+        socket: &'a Self::UdpSocket,
+        buf: &'a [u8],
+        addr: net::SocketAddr,
+    ) -> BoxFuture<'a, io::Result<usize>>;
+
+    #[cfg(feature = "udp")]
+    fn udp_recv_from<'a>(
+        socket: &'a Self::UdpSocket,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, net::SocketAddr)>>;
+
+    #[cfg(feature = "shm")]
+    fn unix_bind(path: PathBuf) -> BoxFuture<'static, io::Result<Self::UnixListener>>;
+
+    #[cfg(feature = "shm")]
+    fn unix_accept(
+        listener: &mut Self::UnixListener,
+    ) -> BoxFuture<'_, io::Result<Self::UnixStream>>;
+
+    #[cfg(feature = "shm")]
+    fn unix_connect(path: PathBuf) -> BoxFuture<'static, io::Result<Self::UnixStream>>;
+}
+
+// Where a socket spawns its listeners, connectors and sessions, if not on the
+// runtime that it is used from.
+#[derive(Debug)]
+pub(crate) enum Spawner {
+    Thread(<Rt as Runtime>::Executor),
+
+    #[cfg(all(feature = "tokio", not(feature = "async-std")))]
+    Handle(tokio::runtime::Handle),
+}
+
+impl Spawner {
+    pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(&self, future: F) {
+        match self {
+            Self::Thread(executor) => Rt::spawn_on(executor, future),
+
+            #[cfg(all(feature = "tokio", not(feature = "async-std")))]
+            Self::Handle(handle) => {
+                handle.spawn(future);
+            }
+        }
+    }
+}
+
+// Starts a thread that calls run once it has been pinned to the given CPUs.
+fn start_thread<F>(name: &str, affinity: Vec<usize>, run: F) -> io::Result<thread::JoinHandle<()>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let (pinned, result) = std::sync::mpsc::channel();
+    let thread = thread::Builder::new().name(name.into()).spawn(move || {
+        let affinity = set_affinity(&affinity);
+        let failed = affinity.is_err();
+        let _ = pinned.send(affinity);
+        if !failed {
+            run();
+        }
+    })?;
+
+    match result.recv() {
+        Ok(Ok(())) => Ok(thread),
+        Ok(Err(err)) => Err(err),
+        Err(..) => Err(io::Error::other("thread panicked")),
+    }
+}
+
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+#[allow(unsafe_code)]
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    if cpus.is_empty() {
+        return Ok(());
+    }
+
+    let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+    for &cpu in cpus {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+
+    // Fails with EINVAL if none of the CPUs are available.
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    if cpus.is_empty() {
+        Ok(())
+    } else {
+        Err(io::ErrorKind::InvalidInput.into())
+    }
+}
+
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    futures::pin_mut!(future);
+    match future::select(future, Rt::delay_for(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(..) => None,
+    }
+}
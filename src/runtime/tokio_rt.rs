@@ -0,0 +1,181 @@
+use futures::future::{self, BoxFuture};
+use futures::{Future, FutureExt};
+use std::time::Duration;
+use std::{io, net, path::PathBuf, thread};
+use tokio::runtime;
+use tokio::sync::oneshot;
+
+use super::{start_thread, Runtime};
+
+#[derive(Debug)]
+pub(crate) struct Tokio;
+
+// Drives a single threaded runtime in the background, so that futures can be
+// blocked on from threads that are not part of any runtime.
+#[derive(Debug)]
+pub(crate) struct Executor {
+    handle: runtime::Handle,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Executor {
+    fn start(name: &str, affinity: Vec<usize>) -> io::Result<Self> {
+        let mut runtime = runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()?;
+
+        let handle = runtime.handle().clone();
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let thread = start_thread(name, affinity, move || {
+            let _ = runtime.block_on(stopped);
+
+            // Give sessions a chance to write out messages that were
+            // queued just before shutdown.
+            runtime.block_on(async {
+                for _ in 0..8 {
+                    let _ = tokio::task::yield_now().await;
+                }
+            });
+        })?;
+
+        Ok(Executor {
+            handle,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.shutdown.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Runtime for Tokio {
+    #[cfg(feature = "tcp")]
+    type TcpListener = tokio::net::TcpListener;
+
+    #[cfg(feature = "tcp")]
+    type TcpStream = tokio::net::TcpStream;
+
+    #[cfg(feature = "udp")]
+    type UdpSocket = tokio::net::UdpSocket;
+
+    #[cfg(feature = "shm")]
+    type UnixListener = tokio::net::UnixListener;
+
+    #[cfg(feature = "shm")]
+    type UnixStream = tokio::net::UnixStream;
+
+    type Executor = Executor;
+
+    fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+        tokio::spawn(future);
+    }
+
+    fn spawn_on<F: Future<Output = ()> + Send + 'static>(executor: &Executor, future: F) {
+        executor.handle.spawn(future);
+    }
+
+    fn executor() -> io::Result<Executor> {
+        Executor::start("rmq-runtime", Vec::new())
+    }
+
+    // Tasks spawn onto the runtime they run on already.
+    fn io_thread(affinity: Vec<usize>) -> io::Result<Executor> {
+        Executor::start("rmq-io", affinity)
+    }
+
+    fn block_on<F: Future>(executor: &Executor, future: F) -> F::Output {
+        executor
+            .handle
+            .enter(|| futures::executor::block_on(future))
+    }
+
+    fn delay_for(duration: Duration) -> BoxFuture<'static, ()> {
+        tokio::time::delay_for(duration).boxed()
+    }
+
+    fn resolve(addr: String) -> BoxFuture<'static, io::Result<Vec<net::SocketAddr>>> {
+        async move { Ok(tokio::net::lookup_host(addr).await?.collect()) }.boxed()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_bind(addr: net::SocketAddr) -> BoxFuture<'static, io::Result<Self::TcpListener>> {
+        tokio::net::TcpListener::bind(addr).boxed()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_local_addr(listener: &Self::TcpListener) -> io::Result<net::SocketAddr> {
+        listener.local_addr()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_stream_local_addr(stream: &Self::TcpStream) -> io::Result<net::SocketAddr> {
+        stream.local_addr()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_accept(
+        listener: &mut Self::TcpListener,
+    ) -> BoxFuture<'_, io::Result<(Self::TcpStream, net::SocketAddr)>> {
+        listener.accept().boxed()
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_connect(addr: net::SocketAddr) -> BoxFuture<'static, io::Result<Self::TcpStream>> {
+        tokio::net::TcpStream::connect(addr).boxed()
+    }
+
+    #[cfg(feature = "udp")]
+    fn udp_bind(addr: net::SocketAddr) -> BoxFuture<'static, io::Result<Self::UdpSocket>> {
+        tokio::net::UdpSocket::bind(addr).boxed()
+    }
+
+    #[cfg(feature = "udp")]
+    fn udp_local_addr(socket: &Self::UdpSocket) -> io::Result<net::SocketAddr> {
+        socket.local_addr()
+    }
+
+    // The async methods of the socket take it mutably, although sending and
+    // receiving only need a shared reference.
+    #[cfg(feature = "udp")]
+    fn udp_send_to<'a>(
+        socket: &'a Self::UdpSocket,
+        buf: &'a [u8],
+        addr: net::SocketAddr,
+    ) -> BoxFuture<'a, io::Result<usize>> {
+        future::poll_fn(move |cx| socket.poll_send_to(cx, buf, &addr)).boxed()
+    }
+
+    #[cfg(feature = "udp")]
+    fn udp_recv_from<'a>(
+        socket: &'a Self::UdpSocket,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, net::SocketAddr)>> {
+        future::poll_fn(move |cx| socket.poll_recv_from(cx, buf)).boxed()
+    }
+
+    #[cfg(feature = "shm")]
+    fn unix_bind(path: PathBuf) -> BoxFuture<'static, io::Result<Self::UnixListener>> {
+        async move { tokio::net::UnixListener::bind(path) }.boxed()
+    }
+
+    #[cfg(feature = "shm")]
+    fn unix_accept(
+        listener: &mut Self::UnixListener,
+    ) -> BoxFuture<'_, io::Result<Self::UnixStream>> {
+        async move { Ok(listener.accept().await?.0) }.boxed()
+    }
+
+    #[cfg(feature = "shm")]
+    fn unix_connect(path: PathBuf) -> BoxFuture<'static, io::Result<Self::UnixStream>> {
+        tokio::net::UnixStream::connect(path).boxed()
+    }
+}
@@ -1,28 +1,35 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::net;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::collections::HashSet;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::mpsc;
 
 use crate::{
-    dispatch::{Pipe, Registry, Exchange},
+    dispatch::{Exchange, Pipe, Registry},
     message::Info,
     socket::Options,
     sync::Arc,
-    zmtp::SocketType,
-    Endpoint, Error, Message, Group, Route, ToEndpoint,
+    util::Span,
+    zmtp::{self, SocketType},
+    Endpoint, Error, Group, Message, Route, ToEndpoint,
 };
 
 use super::Session;
 
+#[derive(Clone)]
 pub(crate) struct Engine {
     pub(crate) socket_type: SocketType,
     pub(crate) remote_type: SocketType,
     pub(crate) peers: Registry,
     pub(crate) groups: Option<tokio::sync::watch::Receiver<Vec<Group>>>,
     pub(crate) options: Options,
+    pub(crate) properties: HashMap<Cow<'static, str>, Vec<u8>>,
+
+    // Span of the socket, replaced by that of the listener or connector.
+    pub(crate) span: Span,
 }
 
 impl fmt::Debug for Engine {
@@ -35,17 +42,33 @@ impl fmt::Debug for Engine {
 }
 
 impl Engine {
-    pub(crate) async fn listen(self, addr: impl ToEndpoint) -> Result<Endpoint, Error> {
+    pub(crate) async fn listen(mut self, addr: impl ToEndpoint) -> Result<Endpoint, Error> {
         let addr = addr.to_endpoint().await?;
-        debug!("engine", "starting listener; addr={}", addr);
+        self.span = span!("engine", "listener", endpoint = %addr);
+        debug!("engine", parent: &self.span, "starting listener");
 
         match addr {
+            #[cfg(feature = "io-uring")]
+            Endpoint::Tcp(addr) if self.options.io_uring => self.uring_listen(addr).await,
+
             #[cfg(feature = "tcp")]
             Endpoint::Tcp(addr) => self.tcp_listen(addr).await,
 
             #[cfg(feature = "udp")]
             Endpoint::Udp(addr) => self.udp_listen(addr).await,
 
+            #[cfg(feature = "tls")]
+            Endpoint::Tls(addr) => self.tls_listen(addr).await,
+
+            #[cfg(feature = "ws")]
+            Endpoint::Ws(addr, path) => self.ws_listen(addr, path).await,
+
+            #[cfg(all(feature = "ws", feature = "tls"))]
+            Endpoint::Wss(addr, path) => self.wss_listen(addr, path).await,
+
+            #[cfg(feature = "shm")]
+            Endpoint::Shm(name) => self.shm_listen(name).await,
+
             #[cfg(feature = "ipc")]
             Endpoint::Ipc(addr) => self.ipc_listen(addr).await,
 
@@ -54,20 +77,36 @@ impl Engine {
         }
     }
 
-    pub(crate) async fn connect(self, addr: impl ToEndpoint) -> Result<Route, Error> {
+    pub(crate) async fn connect(mut self, addr: impl ToEndpoint) -> Result<Route, Error> {
         let addr = addr.to_endpoint().await?;
-        debug!("engine", "starting connector; addr={}", addr);
-
         let pipe = self.create_pipe();
         let id = pipe.id;
 
+        self.span = span!("engine", "connector", endpoint = %addr, route = %id);
+        debug!("engine", parent: &self.span, "starting connector");
+
         match addr {
+            #[cfg(feature = "io-uring")]
+            Endpoint::Tcp(addr) if self.options.io_uring => self.uring_connect(addr, pipe).await?,
+
             #[cfg(feature = "tcp")]
             Endpoint::Tcp(addr) => self.tcp_connect(addr, pipe).await?,
 
             #[cfg(feature = "udp")]
             Endpoint::Udp(addr) => self.udp_connect(addr, pipe).await?,
 
+            #[cfg(feature = "tls")]
+            Endpoint::Tls(addr) => self.tls_connect(addr, pipe).await?,
+
+            #[cfg(feature = "ws")]
+            Endpoint::Ws(addr, path) => self.ws_connect(addr, path, pipe).await?,
+
+            #[cfg(all(feature = "ws", feature = "tls"))]
+            Endpoint::Wss(addr, path) => self.wss_connect(addr, path, pipe).await?,
+
+            #[cfg(feature = "shm")]
+            Endpoint::Shm(name) => self.shm_connect(name, pipe).await?,
+
             #[cfg(feature = "ipc")]
             Endpoint::Ipc(addr) => self.ipc_connect(addr, pipe).await?,
 
@@ -78,6 +117,21 @@ impl Engine {
         Ok(id)
     }
 
+    pub(super) fn codec(&self) -> zmtp::Zmtp {
+        zmtp::Zmtp {
+            max_message_size: self.options.max_message_size,
+            read_buffer_size: self.options.read_buffer_size,
+        }
+    }
+
+    pub(super) fn params(&self) -> zmtp::Params {
+        zmtp::Params {
+            socket_type: self.socket_type,
+            security: zmtp::Security::Null,
+            properties: self.properties.clone(),
+        }
+    }
+
     pub(super) fn create_pipe(&self) -> Pipe {
         self.peers.create(&self.options)
     }
@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 
 use super::{Engine, Info, Pipe, Session};
+use crate::runtime::{Rt, Runtime};
 use crate::sync::{Arc, RwLock};
+use crate::util;
 use crate::{Endpoint, Error, Route};
 
 lazy_static::lazy_static! {
@@ -20,15 +22,18 @@ impl Engine {
             return Ok(());
         }
 
-        tokio::spawn(async move {
+        let span = self.span.clone();
+        let attach = async move {
             loop {
-                tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+                Rt::delay_for(std::time::Duration::from_millis(10)).await;
                 if let Some(engine) = ENDPOINTS.read().get(&addr) {
                     engine.peers.attach(pipe);
                     return;
                 }
             }
-        });
+        };
+
+        Rt::spawn(util::instrument(attach, span));
 
         Ok(())
     }
@@ -1,9 +1,7 @@
-#![cfg(not(loom))]
-
 use futures::{Future, FutureExt, Sink, SinkExt, Stream, StreamExt};
 use smallvec::SmallVec;
 use std::borrow::Cow;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
 use std::net::SocketAddr;
 use std::pin::Pin;
@@ -13,8 +11,10 @@ use tokio::sync::mpsc;
 
 use crate::{
     dispatch::{Delivery, Exchange, Pipe, Registry},
-    message::{Envelope, Info, Payload},
-    zmtp, Group, Message,
+    message::{self, Envelope, Info, Payload},
+    stats::Counters,
+    util::{self, Span},
+    zmtp, Endpoint, Group, Message,
 };
 
 use crate::sync::Arc;
@@ -27,12 +27,24 @@ mod inproc;
 // #[cfg(feature = "ipc")]
 // mod ipc;
 
+#[cfg(feature = "shm")]
+mod shm;
+
 #[cfg(feature = "tcp")]
 mod tcp;
 
+#[cfg(feature = "tls")]
+mod tls;
+
 #[cfg(feature = "udp")]
 mod udp;
 
+#[cfg(feature = "io-uring")]
+mod uring;
+
+#[cfg(feature = "ws")]
+mod ws;
+
 pub(crate) use engine::Engine;
 
 #[must_use = "futures do nothing unless polled"]
@@ -44,7 +56,63 @@ pub(crate) struct Session<T> {
     groups: Option<tokio::sync::watch::Receiver<Vec<Group>>>,
     peer_groups: Vec<Group>,
     next_group: Option<Group>,
-    next_frame: Option<zmtp::Frame>,
+    send_groups: bool,
+
+    // Frames that could not be written yet; sent before anything else.
+    frames: VecDeque<zmtp::Frame>,
+
+    // Removed from the socket once the session ends.
+    peers: Registry,
+    counters: Arc<Counters>,
+}
+
+// Time allowed for the transport handshake of an accepted connection, before
+// the ZMTP handshake.
+#[cfg(any(feature = "tls", feature = "ws", feature = "shm"))]
+pub(crate) const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Span of a single connection; the route is recorded once the handshake has
+// completed.
+pub(crate) fn session_span(engine: &Engine, address: Option<SocketAddr>) -> Span {
+    let peer = address
+        .as_ref()
+        .map(SocketAddr::to_string)
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    span!(
+        "session",
+        "session",
+        peer = %peer,
+        route = tracing::field::Empty,
+        socket_type = ?engine.socket_type,
+        remote_type = ?engine.remote_type,
+    )
+}
+
+// Details of the underlying connection that are known before the handshake.
+#[derive(Debug, Default)]
+pub(crate) struct Connection {
+    pub(crate) peer_address: Option<SocketAddr>,
+    pub(crate) local_endpoint: Option<Endpoint>,
+    pub(crate) peer_subject: Option<String>,
+}
+
+impl Connection {
+    fn info(self, mut remote: zmtp::Info) -> Info {
+        Info {
+            peer_address: self.peer_address,
+            local_endpoint: self.local_endpoint,
+            socket_type: Some(remote.socket_type),
+
+            // A verified certificate authenticates the peer.
+            user_id: self.peer_subject.clone().map(String::into_bytes),
+            peer_subject: self.peer_subject,
+            identity: remote.properties.remove(zmtp::tag::IDENTITY),
+            resource: remote.properties.remove(zmtp::tag::RESOURCE),
+            custom: remote.properties,
+            epoch: Default::default(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -56,58 +124,57 @@ pub(crate) enum Error {
     UnexpectedFrame,
     InvalidGroup,
     MissingGroup,
+    MessageTooLarge,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> Session<T> {
+    // Performs the handshake; the pipe is handed back if it fails.
     async fn establish(
         engine: &mut Engine,
         transport: T,
         pipe: Option<Pipe>,
-        address: Option<SocketAddr>,
-    ) -> Self {
-        let params = zmtp::Params {
-            socket_type: engine.socket_type,
-            security: zmtp::Security::Null,
+        connection: Connection,
+    ) -> Result<Self, Option<Pipe>> {
+        let params = engine.params();
+        let counters = engine.peers.counters();
+        let mut transport = zmtp::Framed::new(transport, engine.codec());
+        let remote = match zmtp::connect(&mut transport, &params).await {
+            Ok(remote) if remote.socket_type == engine.remote_type => remote,
+
+            // Peer failed handshake or is not of correct type
+            _ => {
+                debug!("session", "handshake failed");
+                counters.handshake_failed();
+                return Err(pipe);
+            }
         };
 
-        let mut transport = zmtp::frame(transport);
-        let mut remote = zmtp::connect(&mut transport, &params).await.unwrap();
+        let pipe = match pipe {
+            Some(pipe) => pipe,
+            None => engine.peers.create(&engine.options),
+        };
 
-        if remote.socket_type != engine.remote_type {
-            // Peer not of correct type
-            return Err(Error::InvalidPeerSocket).unwrap();
-        }
+        util::record("route", pipe.id);
+        debug!("session", "established");
 
-        debug!(
-            "session",
-            "established; peer={}",
-            address
-                .as_ref()
-                .map(SocketAddr::to_string)
-                .unwrap_or("unknown".to_owned())
-        );
-
-        Session {
+        counters.connected();
+        Ok(Session {
             transport,
-            pipe: match pipe {
-                Some(pipe) => pipe,
-                None => engine.peers.create(&engine.options),
-            },
-            info: Arc::new(Info {
-                peer_address: address,
-                identity: remote.properties.remove(zmtp::tag::IDENTITY),
-                resource: remote.properties.remove(zmtp::tag::RESOURCE),
-                custom: remote.properties,
-            }),
+            pipe,
+            info: Arc::new(connection.info(remote)),
             groups: engine.groups.clone(),
             peer_groups: Default::default(),
             next_group: None,
-            next_frame: None,
-        }
+            send_groups: engine.socket_type == zmtp::SocketType::RADIO,
+            frames: VecDeque::new(),
+            peers: engine.peers.clone(),
+            counters,
+        })
     }
 
     async fn run(mut self) -> Pipe {
         futures::future::poll_fn(|cx| self.poll(cx)).await;
+        self.peers.remove(self.pipe.id);
         self.pipe
     }
 }
@@ -115,15 +182,20 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Session<T> {
 impl<T: AsyncRead + Unpin> Session<T> {
     fn poll_incoming(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
         loop {
-            if let Err(_) = futures::ready!(self.pipe.tx.poll_ready(cx)) {
-                return Poll::Ready(Err(Error::QueueClosed));
-            }
+            // Sockets that do not receive close the queue. The transport is
+            // still read, so that the session ends once the peer disconnects.
+            let open = futures::ready!(self.pipe.tx.poll_ready(cx)).is_ok();
 
             tokio::pin! {
                 let reader = &mut self.transport;
             }
 
-            match futures::ready!(reader.poll_next(cx)) {
+            let frame = match reader.poll_next(cx) {
+                Poll::Pending if !open => return Poll::Ready(Err(Error::QueueClosed)),
+                poll => futures::ready!(poll),
+            };
+
+            match frame {
                 Some(Ok(zmtp::Frame::Message {
                     more: true,
                     payload,
@@ -139,12 +211,18 @@ impl<T: AsyncRead + Unpin> Session<T> {
                     }
                 }
 
+                Some(Ok(zmtp::Frame::Message { more: false, .. })) if !open => {
+                    trace!("session", "discarding message");
+                    self.next_group.take();
+                }
+
                 Some(Ok(zmtp::Frame::Message {
                     more: false,
                     payload,
                 })) => {
                     trace!("session", "receiving message; len={}", payload.len());
 
+                    let received = message::received(&mut self.info);
                     let delivery = Delivery::Envelope(Envelope {
                         info: self.info.clone(),
                         route: self.pipe.id,
@@ -152,10 +230,10 @@ impl<T: AsyncRead + Unpin> Session<T> {
                             payload: Payload::from(payload),
                             group: self.next_group.take().unwrap_or_default(),
                         },
+                        received,
                     });
 
-                    if let Err(mpsc::error::TrySendError::Closed(..)) =
-                        self.pipe.tx.try_send(delivery)
+                    if let Err(mpsc::error::TrySendError::Closed(..)) = self.pipe.try_send(delivery)
                     {
                         return Poll::Ready(Err(Error::QueueClosed));
                     }
@@ -186,9 +264,9 @@ impl<T: AsyncRead + Unpin> Session<T> {
                 Some(Ok(zmtp::Frame::Ping { ttl, context })) => {
                     // Reply with PONG unless we're already in the process of
                     // sending out a message to this peer.
-                    if self.next_frame.is_none() {
+                    if self.frames.is_empty() {
                         trace!("session", "ping");
-                        self.next_frame.replace(zmtp::Frame::Pong { context });
+                        self.frames.push_back(zmtp::Frame::Pong { context });
                     }
                 }
 
@@ -196,6 +274,11 @@ impl<T: AsyncRead + Unpin> Session<T> {
                     return Poll::Ready(Err(Error::UnexpectedFrame));
                 }
 
+                Some(Err(zmtp::DecodeError::MessageTooLarge)) => {
+                    self.counters.oversized();
+                    return Poll::Ready(Err(Error::MessageTooLarge));
+                }
+
                 Some(Err(..)) | None => {
                     return Poll::Ready(Err(Error::TransportClosed));
                 }
@@ -205,46 +288,43 @@ impl<T: AsyncRead + Unpin> Session<T> {
 }
 
 impl<T: AsyncWrite + Unpin> Session<T> {
-    fn poll_deliver(
-        &mut self,
-        cx: &mut Context<'_>,
-        frame: zmtp::Frame,
-    ) -> Poll<Result<(), Error>> {
-        debug_assert!(self.next_frame.is_none());
-
-        tokio::pin! {
-            let writer = &mut self.transport;
-        }
-
-        match writer.poll_ready(cx) {
-            Poll::Pending => {
-                trace!("session", "buffered outgoing frame");
-                self.next_frame.replace(frame);
-                Poll::Pending
+    // Writes all queued frames to the transport.
+    fn poll_deliver(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        while !self.frames.is_empty() {
+            tokio::pin! {
+                let writer = &mut self.transport;
             }
 
-            Poll::Ready(Ok(())) => {
-                tokio::pin! {
-                    let writer = &mut self.transport;
+            match writer.poll_ready(cx) {
+                Poll::Pending => {
+                    trace!("session", "buffered outgoing frame");
+                    return Poll::Pending;
                 }
 
-                Poll::Ready(writer.start_send(frame).map_err(|_| Error::TransportClosed))
-            }
+                Poll::Ready(Ok(())) => {
+                    tokio::pin! {
+                        let writer = &mut self.transport;
+                    }
 
-            Poll::Ready(Err(..)) => {
-                return Poll::Ready(Err(Error::TransportClosed));
+                    let frame = self.frames.pop_front().unwrap();
+                    writer
+                        .start_send(frame)
+                        .map_err(|_| Error::TransportClosed)?;
+                }
+
+                Poll::Ready(Err(..)) => {
+                    return Poll::Ready(Err(Error::TransportClosed));
+                }
             }
         }
+
+        Poll::Ready(Ok(()))
     }
 
     fn poll_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
-        if let Some(frame) = self.next_frame.take() {
-            futures::ready!(self.poll_deliver(cx, frame)?);
-        }
-
-        debug_assert!(self.next_frame.is_none());
-
         loop {
+            futures::ready!(self.poll_deliver(cx))?;
+
             if let Some(groups) = self.groups.as_mut() {
                 match groups.poll_recv_ref(cx) {
                     Poll::Ready(Some(groups)) => {
@@ -256,7 +336,7 @@ impl<T: AsyncWrite + Unpin> Session<T> {
                 }
             }
 
-            match self.pipe.rx.poll_recv(cx) {
+            let message = match self.pipe.rx.poll_recv(cx) {
                 Poll::Pending => {
                     tokio::pin! {
                         let writer = &mut self.transport;
@@ -269,63 +349,58 @@ impl<T: AsyncWrite + Unpin> Session<T> {
                 }
 
                 Poll::Ready(Some(Delivery::Message(message)))
-                | Poll::Ready(Some(Delivery::Envelope(Envelope { message, .. }))) => {
-                    trace!("session", "sending message; len={}", message.payload.len());
-
-                    futures::ready!(self.poll_deliver(
-                        cx,
-                        zmtp::Frame::Message {
-                            more: false,
-                            payload: message.payload.into_bytes(),
-                        }
-                    ))?;
-                }
+                | Poll::Ready(Some(Delivery::Envelope(Envelope { message, .. }))) => message,
 
                 Poll::Ready(None) => {
                     return Poll::Ready(Err(Error::QueueClosed));
                 }
+            };
+
+            trace!("session", "sending message; len={}", message.payload.len());
+            self.pipe.outgoing.pop();
+
+            // Every message is preceded by its group.
+            if self.send_groups {
+                self.frames.push_back(zmtp::Frame::Message {
+                    more: true,
+                    payload: message.group.as_bytes().to_vec().into(),
+                });
             }
+
+            self.frames.push_back(zmtp::Frame::Message {
+                more: false,
+                payload: message.payload.into_bytes(),
+            });
         }
     }
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> Session<T> {
+    // Ends once the connection fails or the peer breaks the protocol. Sockets
+    // that only send or only receive close the other queue, which ends that
+    // direction only.
     fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
-        let mut ready = false;
-
-        match self.poll_outgoing(cx) {
-            Poll::Ready(Ok(())) => {
-                debug!("session", "outgoing session ended");
-            }
+        let outgoing = self.poll_outgoing(cx);
+        let incoming = self.poll_incoming(cx);
 
-            Poll::Ready(Err(err)) => {
-                debug!("session", "outgoing session error; err={:?}", err);
-            }
-
-            Poll::Pending => {
-                ready = false;
-            }
-        }
+        let closed =
+            |poll: &Poll<Result<(), Error>>| matches!(poll, Poll::Ready(Err(Error::QueueClosed)));
 
-        match self.poll_incoming(cx) {
-            Poll::Ready(Ok(())) => {
-                debug!("session", "incoming session ended");
-            }
-
-            Poll::Ready(Err(err)) => {
-                debug!("session", "incoming session error; err={:?}", err);
-            }
-
-            Poll::Pending => {
-                ready = false;
-            }
+        if closed(&outgoing) && closed(&incoming) {
+            debug!("session", "queues closed");
+            return Poll::Ready(());
         }
 
-        if ready {
-            return Poll::Ready(());
-        } else {
+        let running = |poll: &Poll<Result<(), Error>>| poll.is_pending() || closed(poll);
+        if running(&outgoing) && running(&incoming) {
             return Poll::Pending;
         }
+
+        debug!(
+            "session",
+            "session ended; outgoing={:?}, incoming={:?}", outgoing, incoming
+        );
+        Poll::Ready(())
     }
 }
 
@@ -337,3 +412,119 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Session<T> {
 //         Poll::Ready((*self).pipe)
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::{Dispatcher, Peer};
+    use crate::socket::Options;
+    use bytes::BytesMut;
+    use claim::*;
+    use std::io;
+    use tokio_util::codec::Decoder;
+
+    // Accepts writes only once it is opened.
+    #[derive(Debug, Default)]
+    struct Stalled {
+        open: bool,
+        written: Vec<u8>,
+    }
+
+    impl AsyncRead for Stalled {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    impl AsyncWrite for Stalled {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if !self.open {
+                return Poll::Pending;
+            }
+
+            self.written.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn session(pipe: Pipe, send_groups: bool) -> Session<Stalled> {
+        Session {
+            transport: zmtp::Framed::new(Stalled::default(), Default::default()),
+            pipe,
+            info: Default::default(),
+            groups: None,
+            peer_groups: Default::default(),
+            next_group: None,
+            send_groups,
+            frames: VecDeque::new(),
+            peers: Dispatcher::<(), ()>::default().registry(),
+            counters: Default::default(),
+        }
+    }
+
+    #[test]
+    fn poll_outgoing_keeps_messages_when_group_cannot_be_written() {
+        let (peer, pipe) = Peer::create(&Options::default());
+        let mut session = session(pipe, true);
+
+        let group = "foo".parse().unwrap();
+        for payload in &[1, 2] {
+            let message = Message {
+                payload: Payload::from(vec![*payload; 10 * 1024]),
+                group,
+            };
+
+            peer.counters.outgoing.push();
+            peer.tx
+                .tx
+                .clone()
+                .try_send(Delivery::Message(message))
+                .unwrap();
+        }
+
+        // The first message fills the write buffer, so the group of the
+        // second message has to wait for the transport.
+        assert_pending!(session.poll_outgoing(cx!()));
+
+        session.transport.get_mut().open = true;
+        assert_pending!(session.poll_outgoing(cx!()));
+
+        let mut codec = zmtp::Zmtp::default();
+        let mut written = BytesMut::from(&session.transport.get_ref().written[..]);
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(&mut written).unwrap() {
+            frames.push(frame);
+        }
+
+        let group = |more| zmtp::Frame::Message {
+            more,
+            payload: b"foo"[..].into(),
+        };
+
+        let payload = |byte| zmtp::Frame::Message {
+            more: false,
+            payload: vec![byte; 10 * 1024].into(),
+        };
+
+        assert_eq!(
+            frames,
+            vec![group(true), payload(1), group(true), payload(2)]
+        );
+    }
+}
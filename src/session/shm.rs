@@ -0,0 +1,471 @@
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+
+use super::{session_span, Connection, Engine, Error, Pipe, HANDSHAKE_TIMEOUT};
+use crate::runtime::{self, Rt, Runtime};
+use crate::shm::{self, Ring, Segment};
+use crate::{
+    dispatch::{Delivery, Registry},
+    message::{self, Envelope, Info, Payload},
+    stats::Counters,
+    sync::Arc,
+    util, zmtp, Endpoint, Message,
+};
+
+type UnixListener = <Rt as Runtime>::UnixListener;
+type UnixStream = <Rt as Runtime>::UnixStream;
+
+// The handshake happens over a Unix socket, after which the connector hands
+// over a segment with a ring for each direction. Messages are copied into the
+// rings without ZMTP framing; the socket only carries wakeups.
+impl Engine {
+    pub(crate) async fn shm_listen(self, name: String) -> Result<Endpoint, crate::Error> {
+        let path = shm::socket_path(&name);
+
+        // Only replace the socket of a listener that has gone away.
+        if Rt::unix_connect(path.clone()).await.is_ok() {
+            return Err(crate::Error::AddressInUse);
+        }
+
+        let _ = std::fs::remove_file(&path);
+        let listener = Rt::unix_bind(path).await?;
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(
+            self.shm_listen_internal(listener, name.clone()),
+            span,
+        ));
+        Ok(Endpoint::Shm(name))
+    }
+
+    pub(crate) async fn shm_connect(self, name: String, pipe: Pipe) -> Result<(), crate::Error> {
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(
+            self.shm_connect_internal(name, pipe),
+            span,
+        ));
+        Ok(())
+    }
+
+    async fn shm_listen_internal(self, mut listener: UnixListener, name: String) {
+        loop {
+            // Accepting can fail for reasons that pass, like a full descriptor table.
+            let stream = match Rt::unix_accept(&mut listener).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!("session", "accept failed: {}", err);
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+            };
+
+            let span = session_span(&self, None);
+
+            // A connector that stalls the handshake must not hold up the next.
+            let accept = self.clone().shm_accept_internal(stream, name.clone());
+            Rt::spawn(util::instrument(accept, span));
+        }
+    }
+
+    async fn shm_accept_internal(self, stream: UnixStream, name: String) {
+        let accept = runtime::timeout(HANDSHAKE_TIMEOUT, self.shm_accept(stream));
+        match accept.await.flatten() {
+            Some((transport, segment, remote)) => {
+                let connection = Connection {
+                    local_endpoint: Some(Endpoint::Shm(name)),
+                    ..Default::default()
+                };
+
+                let pipe = self.create_pipe();
+                let channel = Channel::new(&self, transport, segment, 1, pipe, connection, remote);
+                channel.run().await;
+            }
+
+            None => {
+                debug!("session", "handshake failed");
+                self.peers.counters().handshake_failed();
+            }
+        }
+    }
+
+    async fn shm_connect_internal(self, name: String, pipe: Pipe) {
+        let path = shm::socket_path(&name);
+        loop {
+            let stream = match Rt::unix_connect(path.clone()).await {
+                Ok(stream) => stream,
+                Err(_err) => {
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+            };
+
+            let span = session_span(&self, None);
+            let request = self.shm_request(stream, &name);
+            match util::instrument(request, span.clone()).await {
+                Some((transport, segment, remote)) => {
+                    let connection = Connection {
+                        local_endpoint: Some(Endpoint::Shm(name)),
+                        ..Default::default()
+                    };
+
+                    let channel =
+                        Channel::new(&self, transport, segment, 0, pipe, connection, remote);
+
+                    util::instrument(channel.run(), span).await;
+                    return;
+                }
+
+                // Retry the handshake with a new connection.
+                None => {
+                    debug!("session", parent: &span, "handshake failed");
+                    self.peers.counters().handshake_failed();
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                }
+            }
+        }
+    }
+
+    async fn shm_handshake(&self, transport: &mut zmtp::Framed<UnixStream>) -> Option<zmtp::Info> {
+        match zmtp::connect(transport, &self.params()).await {
+            Ok(remote) if remote.socket_type == self.remote_type => Some(remote),
+            _ => None,
+        }
+    }
+
+    // Maps the segment named by the connector and acknowledges it.
+    async fn shm_accept(
+        &self,
+        stream: UnixStream,
+    ) -> Option<(zmtp::Framed<UnixStream>, Segment, zmtp::Info)> {
+        let mut transport = zmtp::frame(stream);
+        let remote = self.shm_handshake(&mut transport).await?;
+
+        let segment = match transport.next().await {
+            Some(Ok(zmtp::Frame::Message {
+                more: false,
+                payload,
+            })) => Segment::open(PathBuf::from(OsStr::from_bytes(&payload))).ok()?,
+            _ => return None,
+        };
+
+        transport
+            .send(zmtp::Frame::Message {
+                more: false,
+                payload: Bytes::new(),
+            })
+            .await
+            .ok()?;
+
+        Some((transport, segment, remote))
+    }
+
+    // Creates a segment and waits until the listener has mapped it.
+    async fn shm_request(
+        &self,
+        stream: UnixStream,
+        name: &str,
+    ) -> Option<(zmtp::Framed<UnixStream>, Segment, zmtp::Info)> {
+        let mut transport = zmtp::frame(stream);
+        let remote = self.shm_handshake(&mut transport).await?;
+        let segment = Segment::create(name, shm::CAPACITY).ok()?;
+
+        let acknowledged = async {
+            transport
+                .send(zmtp::Frame::Message {
+                    more: false,
+                    payload: Bytes::copy_from_slice(segment.path_bytes()),
+                })
+                .await
+                .ok()?;
+
+            match transport.next().await {
+                Some(Ok(zmtp::Frame::Message { more: false, .. })) => Some(()),
+                _ => None,
+            }
+        };
+
+        let acknowledged = acknowledged.await;
+
+        // The mapping outlives the file, so it is removed in any case.
+        let _ = segment.unlink();
+        acknowledged?;
+
+        Some((transport, segment, remote))
+    }
+}
+
+// Message that is being copied into the ring, preceded by its group.
+#[derive(Debug)]
+struct Outgoing {
+    header: [u8; 16],
+    payload: Bytes,
+    offset: usize,
+}
+
+impl Outgoing {
+    fn new(message: Message) -> Self {
+        let group = message.group.as_bytes();
+        let mut header = [0; 16];
+        header[0] = group.len() as u8;
+        header[1..=group.len()].copy_from_slice(group);
+
+        Self {
+            header,
+            payload: message.payload.into_bytes(),
+            offset: 0,
+        }
+    }
+
+    fn parts(&self) -> [&[u8]; 2] {
+        [&self.header[..=self.header[0] as usize], &self.payload]
+    }
+
+    fn len(&self) -> usize {
+        1 + self.header[0] as usize + self.payload.len()
+    }
+}
+
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+struct Channel {
+    wakeups: UnixStream,
+    tx: Ring,
+    rx: Ring,
+    pipe: Pipe,
+    info: Arc<Info>,
+    outgoing: Option<Outgoing>,
+    incoming: BytesMut,
+    max_message_size: usize,
+
+    // The peer is waiting and must be sent a wakeup.
+    notify: bool,
+    sending: bool,
+    receiving: bool,
+    peers: Registry,
+    counters: Arc<Counters>,
+}
+
+impl Channel {
+    fn new(
+        engine: &Engine,
+        transport: zmtp::Framed<UnixStream>,
+        segment: Segment,
+        index: usize,
+        pipe: Pipe,
+        connection: Connection,
+        remote: zmtp::Info,
+    ) -> Self {
+        // Anything buffered after the handshake can only be a wakeup, and the
+        // rings are checked first anyway.
+        let wakeups = transport.into_inner();
+        let segment = std::sync::Arc::new(segment);
+
+        Self {
+            wakeups,
+            tx: segment.ring(index),
+            rx: segment.ring(1 - index),
+            pipe,
+            info: Arc::new(connection.info(remote)),
+            outgoing: None,
+            incoming: BytesMut::new(),
+            max_message_size: engine.options.max_message_size,
+            notify: false,
+            sending: true,
+            receiving: true,
+            peers: engine.peers.clone(),
+            counters: engine.peers.counters(),
+        }
+    }
+
+    async fn run(mut self) {
+        util::record("route", self.pipe.id);
+        debug!("session", "established");
+        self.counters.connected();
+
+        futures::future::poll_fn(|cx| self.poll(cx)).await;
+        self.peers.remove(self.pipe.id);
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            // Messages that are still in the ring are received before the
+            // session ends.
+            let disconnected = self.poll_wakeups(cx).is_err();
+
+            // Sockets that only send or only receive close the other queue,
+            // which ends that direction only.
+            let mut progress = false;
+            if self.sending {
+                match self.poll_outgoing(cx) {
+                    Ok(made) => progress |= made,
+                    Err(Error::QueueClosed) => self.sending = false,
+                    Err(err) => {
+                        debug!("session", "outgoing session error; err={:?}", err);
+                        return Poll::Ready(());
+                    }
+                }
+            }
+
+            if self.receiving {
+                match self.poll_incoming(cx) {
+                    Ok(made) => progress |= made,
+                    Err(Error::QueueClosed) => self.receiving = false,
+                    Err(err) => {
+                        debug!("session", "incoming session error; err={:?}", err);
+                        return Poll::Ready(());
+                    }
+                }
+            }
+
+            if disconnected && !progress {
+                debug!("session", "session ended");
+                return Poll::Ready(());
+            }
+
+            if !progress && !self.notify {
+                return Poll::Pending;
+            }
+
+            if self.notify && !disconnected {
+                match Pin::new(&mut self.wakeups).poll_write(cx, &[0]) {
+                    Poll::Ready(Ok(_)) => self.notify = false,
+                    Poll::Ready(Err(..)) => return Poll::Ready(()),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+    }
+
+    // Drains the socket; wakeups only mean that the rings have changed.
+    fn poll_wakeups(&mut self, cx: &mut Context<'_>) -> Result<(), Error> {
+        let mut buf = [0; 64];
+        loop {
+            match Pin::new(&mut self.wakeups).poll_read(cx, &mut buf) {
+                Poll::Ready(Ok(0)) | Poll::Ready(Err(..)) => return Err(Error::TransportClosed),
+                Poll::Ready(Ok(_)) => {}
+                Poll::Pending => return Ok(()),
+            }
+        }
+    }
+
+    // Returns whether any progress was made.
+    fn poll_outgoing(&mut self, cx: &mut Context<'_>) -> Result<bool, Error> {
+        let mut progress = false;
+        loop {
+            let outgoing = match self.outgoing.as_mut() {
+                Some(outgoing) => outgoing,
+                None => match self.pipe.rx.poll_recv(cx) {
+                    Poll::Pending => return Ok(progress),
+                    Poll::Ready(None) => return Err(Error::QueueClosed),
+                    Poll::Ready(Some(Delivery::Message(message)))
+                    | Poll::Ready(Some(Delivery::Envelope(Envelope { message, .. }))) => {
+                        trace!("session", "sending message; len={}", message.payload.len());
+                        self.pipe.outgoing.pop();
+                        self.outgoing.get_or_insert(Outgoing::new(message))
+                    }
+                },
+            };
+
+            let written = self.tx.push(&outgoing.parts(), outgoing.offset);
+            if written == 0 {
+                // The reader wakes us once it has made room.
+                if self.tx.park_writer() {
+                    continue;
+                }
+
+                return Ok(progress);
+            }
+
+            progress = true;
+            outgoing.offset += written;
+            if outgoing.offset == outgoing.len() {
+                self.outgoing = None;
+            }
+
+            if self.tx.unpark_reader() {
+                self.notify = true;
+            }
+        }
+    }
+
+    fn poll_incoming(&mut self, cx: &mut Context<'_>) -> Result<bool, Error> {
+        let mut progress = false;
+        loop {
+            match self.pipe.tx.poll_ready(cx) {
+                Poll::Pending => return Ok(progress),
+                Poll::Ready(Err(..)) => return Err(Error::QueueClosed),
+                Poll::Ready(Ok(())) => {}
+            }
+
+            let last = match self.rx.pop(&mut self.incoming) {
+                Ok(Some(last)) => last,
+                Ok(None) => {
+                    // The writer wakes us once it has written.
+                    if self.rx.park_reader() {
+                        continue;
+                    }
+
+                    return Ok(progress);
+                }
+
+                Err(..) => return Err(Error::TransportClosed),
+            };
+
+            // Messages are preceded by their group of at most 255 bytes.
+            if self.incoming.len() > 1 + 255 + self.max_message_size {
+                self.counters.oversized();
+                return Err(Error::MessageTooLarge);
+            }
+
+            progress = true;
+            if self.rx.unpark_writer() {
+                self.notify = true;
+            }
+
+            if last {
+                self.deliver()?;
+            }
+        }
+    }
+
+    fn deliver(&mut self) -> Result<(), Error> {
+        let mut body = self.incoming.split().freeze();
+        let length = *body.first().ok_or(Error::InvalidGroup)? as usize;
+        if body.len() < 1 + length {
+            return Err(Error::InvalidGroup);
+        }
+
+        let group = body.split_to(1 + length);
+        let group = group[1..].try_into().map_err(|_| Error::InvalidGroup)?;
+        if body.len() > self.max_message_size {
+            self.counters.oversized();
+            return Err(Error::MessageTooLarge);
+        }
+
+        trace!("session", "receiving message; len={}", body.len());
+
+        let received = message::received(&mut self.info);
+        let delivery = Delivery::Envelope(Envelope {
+            info: self.info.clone(),
+            route: self.pipe.id,
+            message: Message {
+                payload: Payload::from(body),
+                group,
+            },
+            received,
+        });
+
+        if let Err(mpsc::error::TrySendError::Closed(..)) = self.pipe.try_send(delivery) {
+            return Err(Error::QueueClosed);
+        }
+
+        Ok(())
+    }
+}
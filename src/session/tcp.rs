@@ -1,45 +1,83 @@
 use std::net::SocketAddr;
-use tokio::net;
-use tokio::stream::StreamExt;
 
-use super::{Engine, Pipe, Session};
+use super::{session_span, Connection, Engine, Pipe, Session};
+use crate::runtime::{Rt, Runtime};
+use crate::util;
 use crate::{Endpoint, Error, Route};
 
+type TcpListener = <Rt as Runtime>::TcpListener;
+
 impl Engine {
     pub(crate) async fn tcp_listen(self, addr: std::net::SocketAddr) -> Result<Endpoint, Error> {
-        let listener = net::TcpListener::bind(addr).await?;
-        let addr = listener.local_addr()?;
-        tokio::spawn(self.tcp_listen_internal(listener));
+        let listener = Rt::tcp_bind(addr).await?;
+        let addr = Rt::tcp_local_addr(&listener)?;
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(self.tcp_listen_internal(listener), span));
         Ok(Endpoint::Tcp(addr))
     }
 
     pub(crate) async fn tcp_connect(self, addr: SocketAddr, pipe: Pipe) -> Result<(), Error> {
-        tokio::spawn(self.tcp_connect_internal(addr, pipe));
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(
+            self.tcp_connect_internal(addr, pipe),
+            span,
+        ));
         Ok(())
     }
 
-    async fn tcp_listen_internal(mut self, mut listener: net::TcpListener) {
-        let mut incoming = listener.incoming();
-        while let Some(transport) = incoming.next().await {
-            let transport = transport.expect("accept");
-            let address = transport.peer_addr().ok();
-            let session = Session::establish(&mut self, transport, None, address).await;
-            tokio::spawn(session.run());
+    async fn tcp_listen_internal(mut self, mut listener: TcpListener) {
+        loop {
+            let (transport, address) = Rt::tcp_accept(&mut listener).await.expect("accept");
+            let connection = Connection {
+                peer_address: Some(address),
+                local_endpoint: Rt::tcp_stream_local_addr(&transport)
+                    .ok()
+                    .map(Endpoint::Tcp),
+                ..Default::default()
+            };
+
+            let span = session_span(&self, Some(address));
+            let establish = Session::establish(&mut self, transport, None, connection);
+            if let Ok(session) = util::instrument(establish, span.clone()).await {
+                let run = async move {
+                    session.run().await;
+                };
+
+                Rt::spawn(util::instrument(run, span));
+            }
         }
     }
 
     async fn tcp_connect_internal(mut self, addr: SocketAddr, mut pipe: Pipe) {
         loop {
-            match net::TcpStream::connect(addr).await {
+            match Rt::tcp_connect(addr).await {
                 Ok(transport) => {
-                    let address = transport.peer_addr().ok();
-                    let session =
-                        Session::establish(&mut self, transport, Some(pipe), address).await;
-                    pipe = session.run().await;
-                    return;
+                    let connection = Connection {
+                        peer_address: Some(addr),
+                        local_endpoint: Rt::tcp_stream_local_addr(&transport)
+                            .ok()
+                            .map(Endpoint::Tcp),
+                        ..Default::default()
+                    };
+
+                    let span = session_span(&self, Some(addr));
+                    let establish =
+                        Session::establish(&mut self, transport, Some(pipe), connection);
+                    match util::instrument(establish, span.clone()).await {
+                        Ok(session) => {
+                            pipe = util::instrument(session.run(), span).await;
+                            return;
+                        }
+
+                        // Retry the handshake with a new connection.
+                        Err(returned) => {
+                            pipe = returned.expect("pipe returned");
+                            Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                        }
+                    }
                 }
                 Err(_err) => {
-                    tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
                 }
             }
         }
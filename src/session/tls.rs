@@ -0,0 +1,149 @@
+use futures_rustls::{TlsAcceptor, TlsConnector};
+use std::net::SocketAddr;
+use tokio_util::compat::{FuturesAsyncReadCompatExt, Tokio02AsyncReadCompatExt};
+
+use super::{session_span, Connection, Engine, Pipe, Session, HANDSHAKE_TIMEOUT};
+use crate::runtime::{self, Rt, Runtime};
+use crate::{tls, util};
+use crate::{Endpoint, Error};
+
+type TcpListener = <Rt as Runtime>::TcpListener;
+type TcpStream = <Rt as Runtime>::TcpStream;
+
+impl Engine {
+    pub(crate) async fn tls_listen(self, addr: SocketAddr) -> Result<Endpoint, Error> {
+        let acceptor = self.tls_options()?.acceptor()?;
+        let listener = Rt::tcp_bind(addr).await?;
+        let addr = Rt::tcp_local_addr(&listener)?;
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(
+            self.tls_listen_internal(listener, acceptor),
+            span,
+        ));
+        Ok(Endpoint::Tls(addr))
+    }
+
+    pub(crate) async fn tls_connect(self, addr: SocketAddr, pipe: Pipe) -> Result<(), Error> {
+        let options = self.tls_options()?;
+        let connector = options.connector()?;
+        let server_name = options.server_name(addr)?;
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(
+            self.tls_connect_internal(addr, pipe, connector, server_name),
+            span,
+        ));
+        Ok(())
+    }
+
+    pub(super) fn tls_options(&self) -> Result<&tls::TlsOptions, Error> {
+        self.options.tls.as_ref().ok_or(Error::OptionInvalid)
+    }
+
+    async fn tls_listen_internal(self, mut listener: TcpListener, acceptor: TlsAcceptor) {
+        loop {
+            let (transport, address) = match Rt::tcp_accept(&mut listener).await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    debug!("session", "accept failed: {}", err);
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+            };
+
+            let span = session_span(&self, Some(address));
+
+            // A peer that stalls the TLS handshake must not hold up the next.
+            let accept = self
+                .clone()
+                .tls_accept(transport, address, acceptor.clone());
+            Rt::spawn(util::instrument(accept, span));
+        }
+    }
+
+    async fn tls_accept(
+        mut self,
+        transport: TcpStream,
+        address: SocketAddr,
+        acceptor: TlsAcceptor,
+    ) {
+        let local = Rt::tcp_stream_local_addr(&transport).ok();
+        let handshake = acceptor.accept(Tokio02AsyncReadCompatExt::compat(transport));
+        let stream = match runtime::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+            Some(Ok(stream)) => stream,
+            _ => {
+                debug!("session", "tls handshake failed");
+                self.peers.counters().handshake_failed();
+                return;
+            }
+        };
+
+        let connection = Connection {
+            peer_address: Some(address),
+            local_endpoint: local.map(Endpoint::Tls),
+            peer_subject: tls::subject(stream.get_ref().1),
+        };
+
+        let transport = FuturesAsyncReadCompatExt::compat(stream);
+        if let Ok(session) = Session::establish(&mut self, transport, None, connection).await {
+            session.run().await;
+        }
+    }
+
+    async fn tls_connect_internal(
+        mut self,
+        addr: SocketAddr,
+        mut pipe: Pipe,
+        connector: TlsConnector,
+        server_name: tls::ServerName,
+    ) {
+        loop {
+            let transport = match Rt::tcp_connect(addr).await {
+                Ok(transport) => transport,
+                Err(_err) => {
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+            };
+
+            let local = Rt::tcp_stream_local_addr(&transport).ok();
+            let span = session_span(&self, Some(addr));
+
+            let stream = match connector
+                .connect(
+                    server_name.clone(),
+                    Tokio02AsyncReadCompatExt::compat(transport),
+                )
+                .await
+            {
+                Ok(stream) => stream,
+                Err(_err) => {
+                    debug!("session", parent: &span, "tls handshake failed");
+                    self.peers.counters().handshake_failed();
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+            };
+
+            let connection = Connection {
+                peer_address: Some(addr),
+                local_endpoint: local.map(Endpoint::Tls),
+                peer_subject: tls::subject(stream.get_ref().1),
+            };
+
+            let transport = FuturesAsyncReadCompatExt::compat(stream);
+            let establish = Session::establish(&mut self, transport, Some(pipe), connection);
+            match util::instrument(establish, span.clone()).await {
+                Ok(session) => {
+                    util::instrument(session.run(), span).await;
+                    return;
+                }
+
+                // Retry the handshake with a new connection.
+                Err(returned) => {
+                    pipe = returned.expect("pipe returned");
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                }
+            }
+        }
+    }
+}
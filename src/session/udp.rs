@@ -1,31 +1,31 @@
-use futures::stream::Stream;
-use std::collections::HashSet;
+use bytes::BytesMut;
+use futures::future::{self, Either};
 use std::convert::TryInto;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::task::{Context, Poll};
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net;
-use tokio::stream::StreamExt;
-use tokio::sync::mpsc;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::sync::{mpsc, watch};
+use tokio_util::codec::{Decoder, Encoder};
 
-use super::{Engine, Pipe, Session};
+use super::{Engine, Pipe};
 
+use crate::runtime::{Rt, Runtime};
 use crate::{
-    dispatch::{Delivery, Registry},
-    message::{Envelope, Info, Payload},
+    dispatch::Delivery,
+    message::{self, Envelope, Info, Payload},
+    stats::Counters,
     sync::Arc,
-    zmtp, Endpoint, Group, Message, Route,
+    util, zmtp, Endpoint, Group, Message,
 };
 
+// Largest payload of a UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
 impl Engine {
-    pub(crate) async fn udp_listen(
-        self,
-        addr: std::net::SocketAddr,
-    ) -> Result<Endpoint, crate::Error> {
-        let socket = net::UdpSocket::bind(addr).await?;
-        let addr = socket.local_addr()?;
+    pub(crate) async fn udp_listen(self, addr: SocketAddr) -> Result<Endpoint, crate::Error> {
+        let transport = Rt::udp_bind(addr).await?;
+        let addr = Rt::udp_local_addr(&transport)?;
         let pipe = self.peers.create(&self.options);
-        tokio::spawn(self.udp_connect_internal(socket, pipe));
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(self.udp_run(transport, None, pipe), span));
         Ok(Endpoint::Udp(addr))
     }
 
@@ -34,167 +34,177 @@ impl Engine {
         addr: SocketAddr,
         pipe: Pipe,
     ) -> Result<(), crate::Error> {
-        let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
-        let socket = net::UdpSocket::bind(bind_addr).await?;
-        socket.connect(addr).await?;
-        tokio::spawn(self.udp_connect_internal(socket, pipe));
+        let bind_addr = match addr {
+            SocketAddr::V4(..) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(..) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+
+        let transport = Rt::udp_bind(bind_addr).await?;
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(
+            self.udp_run(transport, Some(addr), pipe),
+            span,
+        ));
         Ok(())
     }
 
-    // async fn udp_listen_internal(mut self, mut listener: net::UdpListener) {
-    //     let mut incoming = listener.incoming();
-    //     while let Some(transport) = incoming.next().await {
-    //         let transport = transport.expect("accept");
-    //         let address = transport.peer_addr().ok();
-    //         let session = Session::establish(&mut self, transport, None, address).await;
-    //         tokio::spawn(session.run());
-    //     }
-    // }
-
-    async fn udp_connect_internal(self, transport: net::UdpSocket, pipe: Pipe) {
-        let socket = Socket {
-            transport: zmtp::udp::frame(transport),
+    // Listeners have no peer to send to, so they only receive. Like other
+    // sessions, this ends once both queues are closed or the transport fails.
+    async fn udp_run(
+        self,
+        transport: <Rt as Runtime>::UdpSocket,
+        peer: Option<SocketAddr>,
+        pipe: Pipe,
+    ) {
+        let mut socket = Socket {
+            codec: zmtp::udp::Zudp {
+                max_message_size: self.options.max_message_size,
+            },
+            groups: self.groups.clone(),
             pipe,
-            next_frame: None,
+            counters: self.peers.counters(),
         };
-    }
-}
 
-#[derive(Debug)]
-pub(crate) enum Error {
-    QueueClosed,
-    TransportClosed,
-    // UnexpectedMultipartMessage,
-    // UnexpectedFrame,
-    InvalidGroup,
-    // MissingGroup,
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        let (mut receiving, mut sending) = (true, true);
+
+        while receiving || sending {
+            let event = {
+                let datagram = match receiving {
+                    true => Either::Left(Rt::udp_recv_from(&transport, &mut buffer)),
+                    false => Either::Right(future::pending()),
+                };
+
+                let delivery = match sending {
+                    true => Either::Left(socket.pipe.rx.recv()),
+                    false => Either::Right(future::pending()),
+                };
+
+                futures::pin_mut!(delivery);
+                match future::select(datagram, delivery).await {
+                    Either::Left((datagram, _)) => Either::Left(datagram),
+                    Either::Right((delivery, _)) => Either::Right(delivery),
+                }
+            };
+
+            match event {
+                Either::Left(Ok((len, addr))) => receiving = socket.receive(&buffer[..len], addr),
+
+                Either::Left(Err(err)) => {
+                    debug!("session::udp", "transport failed; error={}", err);
+                    break;
+                }
+
+                Either::Right(Some(Delivery::Message(message)))
+                | Either::Right(Some(Delivery::Envelope(Envelope { message, .. }))) => {
+                    socket.pipe.outgoing.pop();
+
+                    let addr = match peer {
+                        Some(addr) => addr,
+                        None => {
+                            trace!("session::udp", "dropping message without a peer");
+                            continue;
+                        }
+                    };
+
+                    trace!(
+                        "session::udp",
+                        "sending message; len={}",
+                        message.payload.len()
+                    );
+                    let datagram = socket.encode(message);
+
+                    // Datagrams may be lost anyway, so the session goes on.
+                    if let Err(err) = Rt::udp_send_to(&transport, &datagram, addr).await {
+                        debug!("session::udp", "failed to send message; error={}", err);
+                    }
+                }
+
+                Either::Right(None) => sending = false,
+            }
+        }
+
+        debug!("session::udp", "session ended");
+        self.peers.remove(socket.pipe.id);
+    }
 }
 
-#[must_use = "futures do nothing unless polled"]
 #[derive(Debug)]
-pub(crate) struct Socket {
-    transport: zmtp::udp::Framed,
+struct Socket {
+    codec: zmtp::udp::Zudp,
+    groups: Option<watch::Receiver<Vec<Group>>>,
     pipe: Pipe,
-    next_frame: Option<zmtp::udp::Frame>,
+    counters: Arc<Counters>,
 }
 
 impl Socket {
-    fn poll_incoming(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
-        loop {
-            if let Err(_) = futures::ready!(self.pipe.tx.poll_ready(cx)) {
-                return Poll::Ready(Err(Error::QueueClosed));
+    // Returns false once the socket no longer receives. Datagrams that are
+    // invalid, or do not fit in the queue, are dropped.
+    fn receive(&mut self, datagram: &[u8], addr: SocketAddr) -> bool {
+        let (group, payload) = match self.codec.decode(&mut BytesMut::from(datagram)) {
+            Ok(Some(zmtp::udp::Frame::Message { group, payload })) => (group, payload),
+
+            Err(zmtp::DecodeError::MessageTooLarge) => {
+                trace!("session::udp", "dropping oversized message");
+                self.counters.oversized();
+                return true;
             }
 
-            tokio::pin! {
-                let reader = &mut self.transport;
+            Ok(None) | Err(..) => {
+                trace!("session::udp", "dropping invalid datagram");
+                return true;
             }
+        };
 
-            match futures::ready!(reader.poll_next(cx)) {
-                Some(Ok((zmtp::udp::Frame::Message { payload, group }, addr))) => {
-                    trace!("session::udp", "receiving message; len={}", payload.len());
-
-                    let group = group.as_ref().try_into().map_err(|_| Error::InvalidGroup)?;
-
-                    // Only deliver if this socket is subscribed to the group.
-                    if self.pipe.groups.rx.borrow().contains(&group) {
-                        let delivery = Delivery::Envelope(Envelope {
-                            info: Arc::new(Info {
-                                peer_address: Some(addr),
-                                ..Default::default()
-                            }),
-                            route: self.pipe.id,
-                            message: Message {
-                                payload: Payload::from(payload),
-                                group,
-                            },
-                        });
-
-                        if let Err(mpsc::error::TrySendError::Closed(..)) =
-                            self.pipe.tx.try_send(delivery)
-                        {
-                            return Poll::Ready(Err(Error::QueueClosed));
-                        }
-                    }
-                }
+        let group: Group = match group.as_ref().try_into() {
+            Ok(group) => group,
+            Err(..) => {
+                trace!("session::udp", "dropping message with invalid group");
+                return true;
+            }
+        };
 
-                Some(Err(..)) | None => {
-                    return Poll::Ready(Err(Error::TransportClosed));
-                }
+        // Only deliver messages of groups that the socket joined.
+        if let Some(groups) = &self.groups {
+            if !groups.borrow().contains(&group) {
+                return true;
             }
         }
+
+        trace!("session::udp", "receiving message; len={}", payload.len());
+
+        let mut info = Arc::new(Info {
+            peer_address: Some(addr),
+            ..Default::default()
+        });
+
+        let received = message::received(&mut info);
+        let delivery = Delivery::Envelope(Envelope {
+            info,
+            route: self.pipe.id,
+            message: Message {
+                payload: Payload::from(payload),
+                group,
+            },
+            received,
+        });
+
+        !matches!(
+            self.pipe.try_send(delivery),
+            Err(mpsc::error::TrySendError::Closed(..))
+        )
     }
-}
 
-// impl<T: AsyncWrite + Unpin> Session<T> {
-//     fn poll_deliver(
-//         &mut self,
-//         cx: &mut Context<'_>,
-//         frame: zmtp::Frame,
-//     ) -> Poll<Result<(), Error>> {
-//         debug_assert!(self.next_frame.is_none());
-
-//         tokio::pin! {
-//             let writer = &mut self.transport;
-//         }
-
-//         match writer.poll_ready(cx) {
-//             Poll::Pending => {
-//                 trace!("buffered outgoing frame");
-//                 self.next_frame.replace(frame);
-//                 Poll::Pending
-//             }
-
-//             Poll::Ready(Ok(())) => {
-//                 tokio::pin! {
-//                     let writer = &mut self.transport;
-//                 }
-
-//                 Poll::Ready(writer.start_send(frame).map_err(|_| Error::TransportClosed))
-//             }
-
-//             Poll::Ready(Err(..)) => {
-//                 return Poll::Ready(Err(Error::TransportClosed));
-//             }
-//         }
-//     }
-
-//     fn poll_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
-//         if let Some(frame) = self.next_frame.take() {
-//             futures::ready!(self.poll_deliver(cx, frame)?);
-//         }
-
-//         debug_assert!(self.next_frame.is_none());
-
-//         loop {
-//             match self.pipe.rx.poll_recv(cx) {
-//                 Poll::Pending => {
-//                     tokio::pin! {
-//                         let writer = &mut self.transport;
-//                     }
-
-//                     trace!("flushing transport");
-
-//                     futures::ready!(writer.poll_flush(cx)).map_err(|_| Error::TransportClosed)?;
-//                     return Poll::Pending;
-//                 }
-
-//                 Poll::Ready(Some(Delivery::Message(message)))
-//                 | Poll::Ready(Some(Delivery::Envelope(Envelope { message, .. }))) => {
-//                     trace!("sending message; len={}", message.payload.len());
-
-//                     futures::ready!(self.poll_deliver(
-//                         cx,
-//                         zmtp::Frame::Message {
-//                             more: false,
-//                             payload: message.payload.into_bytes(),
-//                         }
-//                     ))?;
-//                 }
-
-//                 Poll::Ready(None) => {
-//                     return Poll::Ready(Err(Error::QueueClosed));
-//                 }
-//             }
-//         }
-//     }
-// }
+    fn encode(&mut self, message: Message) -> BytesMut {
+        let frame = zmtp::udp::Frame::Message {
+            group: message.group.as_bytes().into(),
+            payload: message.payload.into_bytes(),
+        };
+
+        let mut datagram = BytesMut::new();
+        self.codec
+            .encode(frame, &mut datagram)
+            .expect("encoding cannot fail");
+        datagram
+    }
+}
@@ -0,0 +1,101 @@
+use std::net::SocketAddr;
+
+use super::{session_span, Connection, Engine, Pipe, Session};
+use crate::runtime::{Rt, Runtime};
+use crate::uring::{self, UringListener};
+use crate::util;
+use crate::{Endpoint, Error};
+
+// Sessions run on the socket runtime as usual; only their reads, writes and
+// accepts go through the ring. Falls back to epoll if io_uring is unavailable.
+impl Engine {
+    pub(crate) async fn uring_listen(self, addr: SocketAddr) -> Result<Endpoint, Error> {
+        let driver = match uring::driver() {
+            Some(driver) => driver,
+            None => return self.tcp_listen(addr).await,
+        };
+
+        let listener = UringListener::bind(driver, addr)?;
+        let addr = listener.local_addr()?;
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(self.uring_listen_internal(listener), span));
+        Ok(Endpoint::Tcp(addr))
+    }
+
+    pub(crate) async fn uring_connect(self, addr: SocketAddr, pipe: Pipe) -> Result<(), Error> {
+        if uring::driver().is_none() {
+            return self.tcp_connect(addr, pipe).await;
+        }
+
+        let span = self.span.clone();
+        Rt::spawn(util::instrument(
+            self.uring_connect_internal(addr, pipe),
+            span,
+        ));
+        Ok(())
+    }
+
+    async fn uring_listen_internal(mut self, mut listener: UringListener) {
+        loop {
+            // Running out of descriptors, for example, must not end the listener.
+            let (transport, address) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    debug!("session", "accept failed: {}", err);
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+            };
+
+            let connection = Connection {
+                peer_address: Some(address),
+                local_endpoint: transport.local_addr().ok().map(Endpoint::Tcp),
+                ..Default::default()
+            };
+
+            let span = session_span(&self, Some(address));
+            let establish = Session::establish(&mut self, transport, None, connection);
+            if let Ok(session) = util::instrument(establish, span.clone()).await {
+                let run = async move {
+                    session.run().await;
+                };
+
+                Rt::spawn(util::instrument(run, span));
+            }
+        }
+    }
+
+    async fn uring_connect_internal(mut self, addr: SocketAddr, mut pipe: Pipe) {
+        let driver = uring::driver().expect("io_uring driver");
+        loop {
+            match uring::connect(driver, addr).await {
+                Ok(transport) => {
+                    let connection = Connection {
+                        peer_address: Some(addr),
+                        local_endpoint: transport.local_addr().ok().map(Endpoint::Tcp),
+                        ..Default::default()
+                    };
+
+                    let span = session_span(&self, Some(addr));
+                    let establish =
+                        Session::establish(&mut self, transport, Some(pipe), connection);
+                    match util::instrument(establish, span.clone()).await {
+                        Ok(session) => {
+                            util::instrument(session.run(), span).await;
+                            return;
+                        }
+
+                        // Retry the handshake with a new connection.
+                        Err(returned) => {
+                            pipe = returned.expect("pipe returned");
+                            Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                        }
+                    }
+                }
+                Err(_err) => {
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                }
+            }
+        }
+    }
+}
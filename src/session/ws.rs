@@ -0,0 +1,443 @@
+use async_tungstenite::tungstenite::{
+    client::IntoClientRequest,
+    handshake::server::{ErrorResponse, Request, Response},
+    http::{HeaderValue, StatusCode},
+    Message,
+};
+use async_tungstenite::WebSocketStream;
+use bytes::{Buf, BufMut, BytesMut};
+use futures::{io, Future, Sink, Stream};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Encoder;
+use tokio_util::compat::{Compat, Tokio02AsyncReadCompatExt};
+
+use super::{session_span, Connection, Engine, Pipe, Session, HANDSHAKE_TIMEOUT};
+use crate::runtime::{self, Rt, Runtime};
+use crate::{util, zmtp};
+use crate::{Endpoint, Error};
+
+type TcpListener = <Rt as Runtime>::TcpListener;
+type TcpStream = Compat<<Rt as Runtime>::TcpStream>;
+
+// Subprotocols of ZWS/2.0 with the NULL mechanism, in order of preference.
+const PROTOCOLS: &[&str] = &["ZWS2.0/NULL", "ZWS2.0"];
+
+impl Engine {
+    pub(crate) async fn ws_listen(self, addr: SocketAddr, path: String) -> Result<Endpoint, Error> {
+        let listener = Rt::tcp_bind(addr).await?;
+        let addr = Rt::tcp_local_addr(&listener)?;
+        let span = self.span.clone();
+        let upgrade = |stream| async move { Ok((stream, None)) };
+        Rt::spawn(util::instrument(
+            self.ws_listen_internal(listener, path.clone(), Endpoint::Ws, upgrade),
+            span,
+        ));
+        Ok(Endpoint::Ws(addr, path))
+    }
+
+    pub(crate) async fn ws_connect(
+        self,
+        addr: SocketAddr,
+        path: String,
+        pipe: Pipe,
+    ) -> Result<(), Error> {
+        let span = self.span.clone();
+        let upgrade = |stream| async move { Ok((stream, None)) };
+        Rt::spawn(util::instrument(
+            self.ws_connect_internal(addr, path, pipe, Endpoint::Ws, upgrade),
+            span,
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) async fn wss_listen(
+        self,
+        addr: SocketAddr,
+        path: String,
+    ) -> Result<Endpoint, Error> {
+        let acceptor = self.tls_options()?.acceptor()?;
+        let listener = Rt::tcp_bind(addr).await?;
+        let addr = Rt::tcp_local_addr(&listener)?;
+        let span = self.span.clone();
+        let upgrade = move |stream| {
+            let accept = acceptor.accept(stream);
+            async move {
+                let stream = accept.await?;
+                let subject = crate::tls::subject(stream.get_ref().1);
+                Ok((stream, subject))
+            }
+        };
+
+        Rt::spawn(util::instrument(
+            self.ws_listen_internal(listener, path.clone(), Endpoint::Wss, upgrade),
+            span,
+        ));
+        Ok(Endpoint::Wss(addr, path))
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) async fn wss_connect(
+        self,
+        addr: SocketAddr,
+        path: String,
+        pipe: Pipe,
+    ) -> Result<(), Error> {
+        let options = self.tls_options()?;
+        let connector = options.connector()?;
+        let server_name = options.server_name(addr)?;
+        let span = self.span.clone();
+        let upgrade = move |stream| {
+            let connect = connector.connect(server_name.clone(), stream);
+            async move {
+                let stream = connect.await?;
+                let subject = crate::tls::subject(stream.get_ref().1);
+                Ok((stream, subject))
+            }
+        };
+
+        Rt::spawn(util::instrument(
+            self.ws_connect_internal(addr, path, pipe, Endpoint::Wss, upgrade),
+            span,
+        ));
+        Ok(())
+    }
+
+    // The upgrade wraps the TCP stream, e.g. in TLS, and returns the peer
+    // subject. The endpoint describes local addresses.
+    async fn ws_listen_internal<U, F, S>(
+        self,
+        mut listener: TcpListener,
+        path: String,
+        endpoint: fn(SocketAddr, String) -> Endpoint,
+        upgrade: U,
+    ) where
+        U: Fn(TcpStream) -> F,
+        F: Future<Output = io::Result<(S, Option<String>)>> + Send + 'static,
+        S: io::AsyncRead + io::AsyncWrite + Unpin + Send + 'static,
+    {
+        loop {
+            let (transport, address) = match Rt::tcp_accept(&mut listener).await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    debug!("session", "accept failed: {}", err);
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+            };
+
+            let local = Rt::tcp_stream_local_addr(&transport).ok();
+            let span = session_span(&self, Some(address));
+            let connection = Connection {
+                peer_address: Some(address),
+                local_endpoint: local.map(|local| endpoint(local, path.clone())),
+                peer_subject: None,
+            };
+
+            // A peer that stalls the upgrade must not hold up the next.
+            let upgrade = upgrade(transport.compat());
+            let accept = self.clone().ws_accept(upgrade, connection, path.clone());
+            Rt::spawn(util::instrument(accept, span));
+        }
+    }
+
+    async fn ws_accept<F, S>(mut self, upgrade: F, mut connection: Connection, path: String)
+    where
+        F: Future<Output = io::Result<(S, Option<String>)>>,
+        S: io::AsyncRead + io::AsyncWrite + Unpin + Send + 'static,
+    {
+        let accept = async {
+            let (stream, subject) = upgrade.await.ok()?;
+            #[allow(clippy::result_large_err)]
+            let callback = |request: &Request, response| accept(request, response, &path);
+            let socket = async_tungstenite::accept_hdr_async(stream, callback)
+                .await
+                .ok()?;
+            Some((socket, subject))
+        };
+
+        let socket = match runtime::timeout(HANDSHAKE_TIMEOUT, accept).await {
+            Some(Some((socket, subject))) => {
+                connection.peer_subject = subject;
+                socket
+            }
+            _ => {
+                debug!("session", "websocket handshake failed");
+                self.peers.counters().handshake_failed();
+                return;
+            }
+        };
+
+        let establish = Session::establish(&mut self, Zws::new(socket), None, connection);
+        if let Ok(session) = establish.await {
+            session.run().await;
+        }
+    }
+
+    async fn ws_connect_internal<U, F, S>(
+        mut self,
+        addr: SocketAddr,
+        path: String,
+        mut pipe: Pipe,
+        endpoint: fn(SocketAddr, String) -> Endpoint,
+        upgrade: U,
+    ) where
+        U: Fn(TcpStream) -> F,
+        F: Future<Output = io::Result<(S, Option<String>)>>,
+        S: io::AsyncRead + io::AsyncWrite + Unpin + Send + 'static,
+    {
+        let url = endpoint(addr, path.clone()).to_string();
+
+        loop {
+            let transport = match Rt::tcp_connect(addr).await {
+                Ok(transport) => transport,
+                Err(_err) => {
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+            };
+
+            let local = Rt::tcp_stream_local_addr(&transport).ok();
+            let span = session_span(&self, Some(addr));
+
+            let connect = async {
+                let (stream, subject) = upgrade(transport.compat()).await.ok()?;
+                let mut request = url.as_str().into_client_request().ok()?;
+                request.headers_mut().insert(
+                    "Sec-WebSocket-Protocol",
+                    HeaderValue::from_str(&PROTOCOLS.join(", ")).ok()?,
+                );
+
+                let (socket, _) = async_tungstenite::client_async(request, stream)
+                    .await
+                    .ok()?;
+                Some((socket, subject))
+            };
+
+            let (socket, subject) = match connect.await {
+                Some(connected) => connected,
+                None => {
+                    debug!("session", parent: &span, "websocket handshake failed");
+                    self.peers.counters().handshake_failed();
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+            };
+
+            let connection = Connection {
+                peer_address: Some(addr),
+                local_endpoint: local.map(|local| endpoint(local, path.clone())),
+                peer_subject: subject,
+            };
+
+            let establish = Session::establish(&mut self, Zws::new(socket), Some(pipe), connection);
+            match util::instrument(establish, span.clone()).await {
+                Ok(session) => {
+                    util::instrument(session.run(), span).await;
+                    return;
+                }
+
+                // Retry the handshake with a new connection.
+                Err(returned) => {
+                    pipe = returned.expect("pipe returned");
+                    Rt::delay_for(std::time::Duration::from_millis(10)).await;
+                }
+            }
+        }
+    }
+}
+
+// Accepts requests for the path of the endpoint that offer a ZWS subprotocol.
+// The error type is dictated by the handshake callback.
+#[allow(clippy::result_large_err)]
+fn accept(
+    request: &Request,
+    mut response: Response,
+    path: &str,
+) -> Result<Response, ErrorResponse> {
+    let reject = |status| {
+        let mut response = ErrorResponse::new(None);
+        *response.status_mut() = status;
+        Err(response)
+    };
+
+    if request.uri().path() != path {
+        return reject(StatusCode::NOT_FOUND);
+    }
+
+    let offered = request
+        .headers()
+        .get_all("Sec-WebSocket-Protocol")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    match PROTOCOLS.iter().find(|protocol| offered.contains(protocol)) {
+        Some(protocol) => {
+            response
+                .headers_mut()
+                .insert("Sec-WebSocket-Protocol", HeaderValue::from_static(protocol));
+            Ok(response)
+        }
+
+        None => reject(StatusCode::BAD_REQUEST),
+    }
+}
+
+// Translates between a ZMTP stream and ZWS/2.0 messages, which consist of a
+// flags octet followed by the frame body. Both peers skip the greeting.
+#[derive(Debug)]
+pub(crate) struct Zws<S> {
+    socket: WebSocketStream<S>,
+
+    // Incoming ZMTP stream, starting with a greeting on behalf of the peer.
+    incoming: BytesMut,
+
+    // Outgoing ZMTP stream up to the next incomplete frame.
+    outgoing: BytesMut,
+
+    // Length of our own greeting that remains to be dropped.
+    greeting: usize,
+}
+
+const GREETING_LEN: usize = 64;
+
+const ZWS_MORE: u8 = 0x01;
+const ZWS_COMMAND: u8 = 0x02;
+
+const ZMTP_MORE: u8 = 0x01;
+const ZMTP_LONG: u8 = 0x02;
+const ZMTP_COMMAND: u8 = 0x04;
+
+impl<S> Zws<S> {
+    fn new(socket: WebSocketStream<S>) -> Self {
+        let greeting = zmtp::Frame::Greeting {
+            version: (3, 1),
+            security: zmtp::Security::Null,
+        };
+
+        let mut incoming = BytesMut::new();
+        zmtp::Zmtp::default()
+            .encode(greeting, &mut incoming)
+            .expect("encode greeting");
+
+        Self {
+            socket,
+            incoming,
+            outgoing: BytesMut::new(),
+            greeting: GREETING_LEN,
+        }
+    }
+
+    fn receive(&mut self, message: &[u8]) -> io::Result<()> {
+        let (flags, body) = match message.split_first() {
+            Some((flags, body)) if flags & !(ZWS_MORE | ZWS_COMMAND) == 0 => (*flags, body),
+            _ => return Err(io::ErrorKind::InvalidData.into()),
+        };
+
+        let mut flags = (flags & ZWS_MORE) | (flags & ZWS_COMMAND) << 1;
+        if body.len() > 255 {
+            flags |= ZMTP_LONG;
+            self.incoming.put_u8(flags);
+            self.incoming.put_u64(body.len() as u64);
+        } else {
+            self.incoming.put_u8(flags);
+            self.incoming.put_u8(body.len() as u8);
+        }
+
+        self.incoming.put_slice(body);
+        Ok(())
+    }
+
+    // Length of the next complete ZMTP frame in the outgoing stream.
+    fn next_frame(&self) -> Option<(usize, usize)> {
+        let flags = *self.outgoing.first()?;
+        let (header, size) = if flags & ZMTP_LONG != 0 {
+            let mut length = self.outgoing.get(1..9)?;
+            (9, length.get_u64() as usize)
+        } else {
+            (2, *self.outgoing.get(1)? as usize)
+        };
+
+        if self.outgoing.len() < header + size {
+            return None;
+        }
+
+        Some((header, size))
+    }
+}
+
+fn other(err: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::other(err)
+}
+
+impl<S: io::AsyncRead + io::AsyncWrite + Unpin> AsyncRead for Zws<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            if !self.incoming.is_empty() {
+                let len = buf.len().min(self.incoming.len());
+                buf[..len].copy_from_slice(&self.incoming[..len]);
+                self.incoming.advance(len);
+                return Poll::Ready(Ok(len));
+            }
+
+            match futures::ready!(Pin::new(&mut self.socket).poll_next(cx)) {
+                Some(Ok(Message::Binary(message))) => self.receive(&message)?,
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(0)),
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(io::ErrorKind::InvalidData.into()))
+                }
+
+                // Pings are answered by the socket itself.
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Poll::Ready(Err(other(err))),
+            }
+        }
+    }
+}
+
+impl<S: io::AsyncRead + io::AsyncWrite + Unpin> AsyncWrite for Zws<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let skip = self.greeting.min(buf.len());
+        self.greeting -= skip;
+        self.outgoing.extend_from_slice(&buf[skip..]);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some((header, size)) = self.next_frame() {
+            futures::ready!(Pin::new(&mut self.socket).poll_ready(cx)).map_err(other)?;
+
+            let frame = self.outgoing.split_to(header + size);
+            let flags = (frame[0] & ZMTP_MORE) | (frame[0] & ZMTP_COMMAND) >> 1;
+
+            let mut message = Vec::with_capacity(1 + size);
+            message.push(flags);
+            message.extend_from_slice(&frame[header..]);
+
+            Pin::new(&mut self.socket)
+                .start_send(Message::binary(message))
+                .map_err(other)?;
+        }
+
+        Pin::new(&mut self.socket).poll_flush(cx).map_err(other)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.socket).poll_close(cx).map_err(other)
+    }
+}
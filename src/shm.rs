@@ -0,0 +1,433 @@
+use bytes::BytesMut;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::{ffi::OsStrExt, fs::OpenOptionsExt, io::AsRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{io, mem, process, ptr};
+
+// Bytes of data in each direction. Larger messages are split across records.
+pub(crate) const CAPACITY: usize = 1 << 20;
+
+const MAGIC: u64 = 0x726d_712d_7368_6d31; // "rmq-shm1"
+
+// Records start with their length; the top bit marks the last record of a
+// message.
+const RECORD_HEADER: usize = mem::size_of::<u32>();
+const RECORD_LAST: u32 = 1 << 31;
+
+static SEGMENTS: AtomicUsize = AtomicUsize::new(0);
+
+// Names are used in file names, so they are limited to a safe set.
+pub(crate) fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+        && !name.starts_with('.')
+}
+
+// Unix socket used to find the listener, for the handshake and for wakeups.
+pub(crate) fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rmq-{}.sock", name))
+}
+
+fn segment_path(name: &str) -> PathBuf {
+    let dir = Path::new("/dev/shm");
+    let dir = if dir.is_dir() {
+        dir.to_owned()
+    } else {
+        std::env::temp_dir()
+    };
+
+    let count = SEGMENTS.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!("rmq-{}-{}-{}", name, process::id(), count))
+}
+
+#[repr(C)]
+struct SegmentHeader {
+    magic: u64,
+    capacity: u64,
+}
+
+// Positions only ever increase; the reader and writer sides live on separate
+// cache lines.
+#[repr(C, align(64))]
+struct RingHeader {
+    tail: AtomicU64,
+    reader_waiting: AtomicU32,
+    _pad: [u8; 52],
+    head: AtomicU64,
+    writer_waiting: AtomicU32,
+}
+
+const SEGMENT_HEADER: usize = 64;
+const RING_HEADER: usize = mem::size_of::<RingHeader>();
+
+// File backed memory mapping that holds two rings, one for each direction.
+#[derive(Debug)]
+pub(crate) struct Segment {
+    ptr: *mut u8,
+    len: usize,
+    capacity: usize,
+    path: PathBuf,
+}
+
+// The mapping is only accessed through rings, which synchronise via atomics.
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl Segment {
+    pub(crate) fn create(name: &str, capacity: usize) -> io::Result<Self> {
+        debug_assert_eq!(capacity % 64, 0);
+        let path = segment_path(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+
+        let len = SEGMENT_HEADER + 2 * (RING_HEADER + capacity);
+        let segment = file
+            .set_len(len as u64)
+            .and_then(|_| Self::map(&file, len, capacity, path.clone()));
+
+        let segment = match segment {
+            Ok(segment) => segment,
+            Err(err) => {
+                let _ = fs::remove_file(&path);
+                return Err(err);
+            }
+        };
+
+        // The file is zeroed, so only the header needs to be written.
+        unsafe {
+            ptr::write(
+                segment.ptr as *mut SegmentHeader,
+                SegmentHeader {
+                    magic: MAGIC,
+                    capacity: capacity as u64,
+                },
+            );
+        }
+
+        Ok(segment)
+    }
+
+    pub(crate) fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let len = file.metadata()?.len() as usize;
+        if len < SEGMENT_HEADER {
+            return Err(invalid());
+        }
+
+        let mut segment = Self::map(&file, len, 0, path)?;
+        let header = unsafe { ptr::read(segment.ptr as *const SegmentHeader) };
+        let capacity = header.capacity as usize;
+        if header.magic != MAGIC
+            || capacity & 63 != 0
+            || len != SEGMENT_HEADER + 2 * (RING_HEADER + capacity)
+        {
+            return Err(invalid());
+        }
+
+        segment.capacity = capacity;
+        Ok(segment)
+    }
+
+    fn map(file: &File, len: usize, capacity: usize, path: PathBuf) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+            capacity,
+            path,
+        })
+    }
+
+    pub(crate) fn path_bytes(&self) -> &[u8] {
+        self.path.as_os_str().as_bytes()
+    }
+
+    // Both sides have mapped the segment, so the file is no longer needed.
+    pub(crate) fn unlink(&self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+
+    // Ring 0 carries data from the connector, ring 1 from the listener.
+    pub(crate) fn ring(self: &Arc<Self>, index: usize) -> Ring {
+        debug_assert!(index < 2);
+        let offset = SEGMENT_HEADER + index * (RING_HEADER + self.capacity);
+        unsafe {
+            Ring {
+                header: self.ptr.add(offset) as *const RingHeader,
+                data: self.ptr.add(offset + RING_HEADER),
+                capacity: self.capacity,
+                _segment: self.clone(),
+            }
+        }
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid shared memory segment")
+}
+
+// Single producer, single consumer ring of length prefixed records.
+#[derive(Debug)]
+pub(crate) struct Ring {
+    header: *const RingHeader,
+    data: *mut u8,
+    capacity: usize,
+    _segment: Arc<Segment>,
+}
+
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn header(&self) -> &RingHeader {
+        unsafe { &*self.header }
+    }
+
+    fn used(&self) -> usize {
+        let header = self.header();
+        let tail = header.tail.load(Ordering::Acquire);
+        let head = header.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head) as usize
+    }
+
+    // Writes as much of the message, starting at offset, as fits in a single
+    // record. Returns the number of bytes written.
+    pub(crate) fn push(&mut self, parts: &[&[u8]], offset: usize) -> usize {
+        let total = parts.iter().map(|part| part.len()).sum::<usize>();
+        let free = self.capacity.saturating_sub(self.used());
+        if free <= RECORD_HEADER || offset >= total {
+            return 0;
+        }
+
+        let len = (total - offset).min(free - RECORD_HEADER);
+        let mut record = len as u32;
+        if offset + len == total {
+            record |= RECORD_LAST;
+        }
+
+        let mut tail = self.header().tail.load(Ordering::Relaxed);
+        self.copy_in(tail, &record.to_le_bytes());
+        tail += RECORD_HEADER as u64;
+
+        let mut skip = offset;
+        let mut remaining = len;
+        for part in parts {
+            if skip >= part.len() {
+                skip -= part.len();
+                continue;
+            }
+
+            let chunk = &part[skip..part.len().min(skip + remaining)];
+            self.copy_in(tail, chunk);
+            tail += chunk.len() as u64;
+            remaining -= chunk.len();
+            skip = 0;
+
+            if remaining == 0 {
+                break;
+            }
+        }
+
+        self.header().tail.store(tail, Ordering::Release);
+        len
+    }
+
+    // Appends the next record to the buffer and returns whether it completes
+    // a message, or None if the ring is empty.
+    pub(crate) fn pop(&mut self, buf: &mut BytesMut) -> io::Result<Option<bool>> {
+        let used = self.used();
+        if used == 0 {
+            return Ok(None);
+        }
+
+        // The tail is written by the peer, so it must not be trusted to stay
+        // within the ring.
+        let mut head = self.header().head.load(Ordering::Relaxed);
+        let mut record = [0; RECORD_HEADER];
+        if used < RECORD_HEADER || used > self.capacity {
+            return Err(invalid());
+        }
+
+        self.copy_out(head, &mut record);
+        let record = u32::from_le_bytes(record);
+        let len = (record & !RECORD_LAST) as usize;
+        if len > self.capacity - RECORD_HEADER || len > used - RECORD_HEADER {
+            return Err(invalid());
+        }
+
+        head += RECORD_HEADER as u64;
+        let start = buf.len();
+        buf.resize(start + len, 0);
+        self.copy_out(head, &mut buf[start..]);
+        head += len as u64;
+
+        self.header().head.store(head, Ordering::Release);
+        Ok(Some(record & RECORD_LAST != 0))
+    }
+
+    // Announces that the reader is about to wait, and returns whether data
+    // arrived in the meantime.
+    pub(crate) fn park_reader(&self) -> bool {
+        self.header().reader_waiting.store(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        self.used() > 0
+    }
+
+    // Announces that the writer is about to wait, and returns whether room
+    // was made in the meantime.
+    pub(crate) fn park_writer(&self) -> bool {
+        self.header().writer_waiting.store(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        self.capacity.saturating_sub(self.used()) > RECORD_HEADER
+    }
+
+    // Whether the reader must be woken up after writing.
+    pub(crate) fn unpark_reader(&self) -> bool {
+        atomic::fence(Ordering::SeqCst);
+        self.header().reader_waiting.swap(0, Ordering::SeqCst) != 0
+    }
+
+    // Whether the writer must be woken up after reading.
+    pub(crate) fn unpark_writer(&self) -> bool {
+        atomic::fence(Ordering::SeqCst);
+        self.header().writer_waiting.swap(0, Ordering::SeqCst) != 0
+    }
+
+    fn copy_in(&mut self, pos: u64, src: &[u8]) {
+        let start = (pos % self.capacity as u64) as usize;
+        let first = src.len().min(self.capacity - start);
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), self.data.add(start), first);
+            ptr::copy_nonoverlapping(src[first..].as_ptr(), self.data, src.len() - first);
+        }
+    }
+
+    fn copy_out(&self, pos: u64, dst: &mut [u8]) {
+        let start = (pos % self.capacity as u64) as usize;
+        let first = dst.len().min(self.capacity - start);
+        unsafe {
+            ptr::copy_nonoverlapping(self.data.add(start), dst.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(self.data, dst[first..].as_mut_ptr(), dst.len() - first);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rings(capacity: usize) -> (Ring, Ring) {
+        let segment = Arc::new(Segment::create("test", capacity).unwrap());
+        let peer = Arc::new(Segment::open(segment.path.clone()).unwrap());
+        segment.unlink().unwrap();
+        (segment.ring(0), peer.ring(0))
+    }
+
+    #[test]
+    fn valid_name_rejects_paths() {
+        assert!(valid_name("bench-1"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("../etc"));
+        assert!(!valid_name("a/b"));
+    }
+
+    #[test]
+    fn push_and_pop_wrap_around() {
+        let (mut writer, mut reader) = rings(64);
+        let mut buf = BytesMut::new();
+
+        for i in 0..100u8 {
+            let message = vec![i; 20];
+            assert_eq!(writer.push(&[&[i], &message], 0), 21);
+            assert_eq!(reader.pop(&mut buf).unwrap(), Some(true));
+
+            let body = buf.split();
+            assert_eq!(body[0], i);
+            assert_eq!(&body[1..], &message[..]);
+        }
+
+        assert_eq!(reader.pop(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn large_messages_are_split() {
+        let (mut writer, mut reader) = rings(64);
+        let message = (0..200).map(|i| i as u8).collect::<Vec<_>>();
+        let mut buf = BytesMut::new();
+        let mut offset = 0;
+
+        loop {
+            offset += writer.push(&[&message], offset);
+            if !writer.park_writer() {
+                assert!(reader.unpark_writer());
+            }
+
+            match reader.pop(&mut buf).unwrap() {
+                Some(true) => break,
+                Some(false) => {}
+                None => panic!("ring empty"),
+            }
+        }
+
+        assert_eq!(offset, message.len());
+        assert_eq!(&buf[..], &message[..]);
+    }
+
+    #[test]
+    fn pop_rejects_corrupted_tail() {
+        let (mut writer, mut reader) = rings(64);
+        let mut buf = BytesMut::new();
+        assert_eq!(writer.push(&[&[1; 8]], 0), 8);
+
+        // Claims more data than the ring can hold.
+        let tail = writer.header().tail.load(Ordering::Relaxed);
+        writer.header().tail.store(tail + 1000, Ordering::Release);
+        assert!(reader.pop(&mut buf).is_err());
+
+        // Behind the head.
+        let head = reader.header().head.load(Ordering::Relaxed);
+        writer
+            .header()
+            .tail
+            .store(head.wrapping_sub(1), Ordering::Release);
+        assert!(reader.pop(&mut buf).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn open_rejects_other_files() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), [0; 256]).unwrap();
+        assert!(Segment::open(file.path().to_owned()).is_err());
+    }
+}
@@ -1,15 +1,19 @@
+use futures::Future;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::task::{Context, Poll};
 use std::time::Duration;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 
 use crate::dispatch::{Dispatcher, Receiver, Register, Sender};
 use crate::message::Info;
+use crate::runtime::{Rt, Runtime, Spawner};
 use crate::session::Engine;
-use crate::sync::{Arc, MutexGuard};
-use crate::zmtp::SocketType;
-use crate::util::Exchange;
-use crate::{Endpoint, Error, Group, Message, Route, ToEndpoint};
+use crate::sync::{Arc, Mutex};
+use crate::util::{self, Exchange, Span};
+use crate::zmtp::{self, SocketType};
+use crate::{Endpoint, Error, Group, Message, Route, Stats, ToEndpoint};
 
 mod types;
 
@@ -27,19 +31,56 @@ pub(super) trait Base: Send + Default {
     }
 }
 
-#[derive(Default)]
 pub(crate) struct Socket<T: Base> {
     base: T,
     dispatcher: Dispatcher<T::Sender, T::Receiver>,
     options: Options,
+    span: Span,
+
+    // Started on first use. Dropped last, so sessions see their queues close
+    // while it still runs.
+    spawner: Mutex<Option<Arc<Spawner>>>,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct Options {
     pub outgoing_queue_size: usize,
     pub incoming_queue_size: usize,
     pub heartbeat_timeout: Duration,
     pub max_reconnect_interval: Duration,
+
+    // Size of the buffer incoming data is read into. Frames that are larger
+    // are read into a buffer of their own.
+    pub read_buffer_size: usize,
+
+    // Peers that send larger messages are disconnected; oversized datagrams
+    // are dropped.
+    pub max_message_size: usize,
+
+    // Sent to peers in the READY command. Metadata names must start with "X-".
+    pub identity: Option<Vec<u8>>,
+    pub metadata: BTreeMap<String, Vec<u8>>,
+
+    // Required for tls:// endpoints.
+    #[cfg(feature = "tls")]
+    pub tls: Option<crate::TlsOptions>,
+
+    // Drives tcp:// endpoints through io_uring, if the kernel supports it and
+    // the io-uring feature is enabled. Otherwise epoll is used.
+    pub io_uring: bool,
+
+    // Runs listeners, connectors and sessions on a thread of the socket's own,
+    // instead of on the runtime that listen and connect are called from.
+    pub io_thread: bool,
+
+    // CPUs that the I/O thread may run on; empty to leave it to the OS.
+    // Requires the affinity feature.
+    pub cpu_affinity: Vec<usize>,
+
+    // Runs listeners, connectors and sessions on this runtime instead. Only
+    // exists with the tokio runtime, which no other feature disables.
+    #[cfg(all(feature = "tokio", not(feature = "async-std")))]
+    pub runtime: Option<tokio::runtime::Handle>,
 }
 
 impl Default for Options {
@@ -49,7 +90,87 @@ impl Default for Options {
             incoming_queue_size: 1024,
             heartbeat_timeout: Duration::from_secs(10),
             max_reconnect_interval: Duration::from_secs(30),
+            read_buffer_size: 8 * 1024,
+            max_message_size: zmtp::MAX_MESSAGE_SIZE,
+            identity: None,
+            metadata: BTreeMap::new(),
+
+            #[cfg(feature = "tls")]
+            tls: None,
+
+            io_uring: false,
+            io_thread: false,
+            cpu_affinity: Vec::new(),
+
+            #[cfg(all(feature = "tokio", not(feature = "async-std")))]
+            runtime: None,
+        }
+    }
+}
+
+impl Options {
+    fn properties(&self) -> Result<HashMap<Cow<'static, str>, Vec<u8>>, Error> {
+        let mut properties = HashMap::new();
+
+        // Identities starting with a zero byte are reserved by libzmq.
+        if let Some(identity) = &self.identity {
+            if identity.is_empty() || identity.len() > 255 || identity[0] == 0 {
+                return Err(Error::OptionInvalid);
+            }
+
+            properties.insert(Cow::Borrowed(zmtp::tag::IDENTITY), identity.clone());
+        }
+
+        for (name, value) in &self.metadata {
+            let prefix = name.as_bytes().get(..2);
+            let custom = name.len() > 2 && prefix.is_some_and(|p| p.eq_ignore_ascii_case(b"X-"));
+            if !custom || !zmtp::valid_property(name, value) {
+                return Err(Error::OptionInvalid);
+            }
+
+            properties.insert(Cow::Owned(name.clone()), value.clone());
+        }
+
+        Ok(properties)
+    }
+
+    fn spawner(&self) -> Result<Option<Spawner>, Error> {
+        #[cfg(all(feature = "tokio", not(feature = "async-std")))]
+        {
+            if let Some(handle) = &self.runtime {
+                if self.io_thread {
+                    return Err(Error::OptionInvalid);
+                }
+
+                return Ok(Some(Spawner::Handle(handle.clone())));
+            }
+        }
+
+        // Pinning needs the affinity feature, and only applies to an I/O thread.
+        let affinity = self.cpu_affinity.clone();
+        if !affinity.is_empty() && (!self.io_thread || !cfg!(feature = "affinity")) {
+            return Err(Error::OptionInvalid);
+        }
+
+        if !self.io_thread {
+            return Ok(None);
         }
+
+        match Rt::io_thread(affinity) {
+            Ok(executor) => Ok(Some(Spawner::Thread(executor))),
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => Err(Error::OptionInvalid),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+}
+
+// Queues that tests fill up with a few messages.
+#[cfg(test)]
+pub(crate) fn test_options() -> Options {
+    Options {
+        outgoing_queue_size: 2,
+        incoming_queue_size: 2,
+        ..Default::default()
     }
 }
 
@@ -62,41 +183,92 @@ impl<T: Base> fmt::Debug for Socket<T> {
     }
 }
 
+impl<T: Base> Default for Socket<T> {
+    fn default() -> Self {
+        Self::with_options(Default::default())
+    }
+}
+
 impl<T: Base> Socket<T> {
     pub(super) fn with_options(options: Options) -> Self {
         Self {
+            base: Default::default(),
+            dispatcher: Dispatcher::new(T::SELF),
             options,
-            ..Default::default()
+            span: span!("socket", "socket", socket_type = ?T::SELF),
+            spawner: Default::default(),
         }
     }
 
+    pub(super) fn stats(&self) -> Stats {
+        self.dispatcher.counters.snapshot()
+    }
+
     pub(super) async fn listen<'a>(&self, addr: impl ToEndpoint) -> Result<Endpoint, Error> {
-        self.create_engine().listen(addr).await
+        let engine = self.create_engine()?;
+        let addr = addr.to_endpoint().await?;
+        self.spawn_engine(util::instrument(engine.listen(addr), self.span.clone()))
+            .await
     }
 
     pub(super) async fn connect(&self, addr: impl ToEndpoint) -> Result<Route, Error> {
-        self.create_engine().connect(addr).await
+        let engine = self.create_engine()?;
+        let addr = addr.to_endpoint().await?;
+        self.spawn_engine(util::instrument(engine.connect(addr), self.span.clone()))
+            .await
     }
 
     pub(super) fn base(&self) -> &T {
         &self.base
     }
 
-    pub(super) fn tx(&self) -> MutexGuard<'_, T::Sender> {
+    pub(super) fn tx(&self) -> &T::Sender {
         self.dispatcher.tx()
     }
 
-    pub(super) fn rx(&self) -> MutexGuard<'_, T::Receiver> {
+    pub(super) fn rx(&self) -> &T::Receiver {
         self.dispatcher.rx()
     }
 
-    fn create_engine(&self) -> Engine {
-        Engine {
+    // Listeners and connectors set up their I/O on the runtime that runs them,
+    // and spawn their sessions there.
+    async fn spawn_engine<F, R>(&self, future: F) -> Result<R, Error>
+    where
+        F: Future<Output = Result<R, Error>> + Send + 'static,
+        R: Send + 'static,
+    {
+        let spawner = {
+            let mut spawner = self.spawner.lock();
+            if spawner.is_none() {
+                *spawner = self.options.spawner()?.map(Arc::new);
+            }
+
+            spawner.clone()
+        };
+
+        let spawner = match spawner {
+            Some(spawner) => spawner,
+            None => return future.await,
+        };
+
+        let (tx, rx) = oneshot::channel();
+        spawner.spawn(async move {
+            let _ = tx.send(future.await);
+        });
+
+        // The runtime was shut down.
+        rx.await.unwrap_or(Err(Error::TransportUnavailable))
+    }
+
+    fn create_engine(&self) -> Result<Engine, Error> {
+        Ok(Engine {
             socket_type: T::SELF,
             remote_type: T::PEER,
             peers: self.dispatcher.registry(),
             groups: self.base.groups(),
-            options: self.options,
-        }
+            properties: self.options.properties()?,
+            options: self.options.clone(),
+            span: self.span.clone(),
+        })
     }
 }
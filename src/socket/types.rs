@@ -1,4 +1,5 @@
 use crate::dispatch::{FairReceiver, FairSender, Publisher, Router};
+use crate::rpc::Correlator;
 use crate::Group;
 use crate::sync::Mutex;
 use crate::util::Exchange;
@@ -6,7 +7,9 @@ use crate::util::Exchange;
 use super::{Base, SocketType};
 
 #[derive(Debug, Default)]
-pub(crate) struct Client;
+pub(crate) struct Client {
+    pub(crate) requests: Correlator,
+}
 
 impl Base for Client {
     const SELF: SocketType = SocketType::CLIENT;
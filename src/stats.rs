@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::str;
+
+use crate::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::sync::{Arc, Mutex};
+use crate::zmtp::SocketType;
+use crate::Route;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub messages_in: u64,
+    pub bytes_in: u64,
+    pub messages_out: u64,
+    pub bytes_out: u64,
+    pub dropped: u64,
+    pub queued_in: usize,
+    pub queued_out: usize,
+    pub connects: u64,
+    pub disconnects: u64,
+    pub handshake_failures: u64,
+    pub oversized_messages: u64,
+    pub peers: HashMap<Route, PeerStats>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PeerStats {
+    pub messages_in: u64,
+    pub bytes_in: u64,
+    pub messages_out: u64,
+    pub bytes_out: u64,
+    pub dropped: u64,
+    pub queued_in: usize,
+    pub queued_out: usize,
+}
+
+// Number of messages in a peer queue. Shared by both ends of the queue, which
+// may be a session and a dispatcher, or two dispatchers for inproc peers.
+//
+// Messages are counted before they are queued and after they are taken off
+// the queue, so the count never drops below zero.
+#[derive(Debug, Default)]
+pub(crate) struct Depth {
+    count: AtomicUsize,
+    // Gauges of the peers that see this queue.
+    #[cfg(feature = "metrics")]
+    gauges: Mutex<Vec<Arc<Gauges>>>,
+}
+
+impl Depth {
+    pub(crate) fn push(&self) {
+        self.update(|count| count.fetch_add(1, Ordering::Relaxed), 1.0);
+    }
+
+    pub(crate) fn pop(&self) {
+        let previous = self.update(|count| count.fetch_sub(1, Ordering::Relaxed), -1.0);
+        debug_assert_ne!(
+            previous, 0,
+            "message taken off the queue before it was counted"
+        );
+    }
+
+    fn get(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "metrics")]
+    fn update(&self, f: impl FnOnce(&AtomicUsize) -> usize, delta: f64) -> usize {
+        // Gauges are added and removed under the same lock, so that each one
+        // sees every change made while it watches.
+        let gauges = self.gauges.lock();
+        let previous = f(&self.count);
+        for gauge in gauges.iter() {
+            gauge.increment(delta);
+        }
+        previous
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn update(&self, f: impl FnOnce(&AtomicUsize) -> usize, _delta: f64) -> usize {
+        f(&self.count)
+    }
+
+    #[cfg(feature = "metrics")]
+    fn watch(&self, gauges: Arc<Gauges>) {
+        let mut watching = self.gauges.lock();
+        gauges.increment(self.get() as f64);
+        watching.push(gauges);
+    }
+
+    #[cfg(feature = "metrics")]
+    fn unwatch(&self, gauges: &Arc<Gauges>) {
+        let mut watching = self.gauges.lock();
+        watching.retain(|watching| !Arc::ptr_eq(watching, gauges));
+        gauges.increment(-(self.get() as f64));
+    }
+}
+
+// Queue depth gauges of a peer and of its socket.
+#[cfg(feature = "metrics")]
+#[derive(Debug)]
+struct Gauges {
+    socket: metrics::Gauge,
+    peer: metrics::Gauge,
+}
+
+#[cfg(feature = "metrics")]
+impl Gauges {
+    fn new(name: &'static str, peer_name: &'static str, socket: &Counters, id: Route) -> Self {
+        let socket = socket.socket_label();
+        Self {
+            socket: metrics::gauge!(name, "socket" => socket),
+            peer: metrics::gauge!(peer_name, "socket" => socket, "peer" => id.to_string()),
+        }
+    }
+
+    fn increment(&self, delta: f64) {
+        self.socket.increment(delta);
+        self.peer.increment(delta);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    messages_in: AtomicU64,
+    bytes_in: AtomicU64,
+    messages_out: AtomicU64,
+    bytes_out: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Totals {
+    fn received(&self, len: usize) {
+        self.messages_in.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn sent(&self, len: usize) {
+        self.messages_out.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Counters of a single socket, shared with its dispatchers and sessions.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    socket_type: SocketType,
+    totals: Totals,
+    connects: AtomicU64,
+    disconnects: AtomicU64,
+    handshake_failures: AtomicU64,
+    oversized_messages: AtomicU64,
+    peers: Mutex<HashMap<Route, Arc<PeerCounters>>>,
+}
+
+impl Counters {
+    pub(crate) fn new(socket_type: SocketType) -> Self {
+        Self {
+            socket_type,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn connected(&self) {
+        self.connects.fetch_add(1, Ordering::Relaxed);
+        self.export("rmq_connects_total", 1);
+    }
+
+    fn disconnected(&self) {
+        self.disconnects.fetch_add(1, Ordering::Relaxed);
+        self.export("rmq_disconnects_total", 1);
+    }
+
+    pub(crate) fn handshake_failed(&self) {
+        self.handshake_failures.fetch_add(1, Ordering::Relaxed);
+        self.export("rmq_handshake_failures_total", 1);
+    }
+
+    // A peer sent a message over the maximum size.
+    pub(crate) fn oversized(&self) {
+        self.oversized_messages.fetch_add(1, Ordering::Relaxed);
+        self.export("rmq_oversized_messages_total", 1);
+    }
+
+    pub(crate) fn insert(&self, id: Route, peer: Arc<PeerCounters>) {
+        self.peers.lock().insert(id, peer);
+    }
+
+    // Peers are removed once their session has ended, which is counted as a
+    // disconnect. Totals of the socket include messages of removed peers.
+    pub(crate) fn remove(&self, id: Route) {
+        if self.peers.lock().remove(&id).is_some() {
+            self.disconnected();
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        let peers: HashMap<_, _> = self
+            .peers
+            .lock()
+            .iter()
+            .map(|(id, peer)| (*id, peer.snapshot()))
+            .collect();
+
+        Stats {
+            messages_in: self.totals.messages_in.load(Ordering::Relaxed),
+            bytes_in: self.totals.bytes_in.load(Ordering::Relaxed),
+            messages_out: self.totals.messages_out.load(Ordering::Relaxed),
+            bytes_out: self.totals.bytes_out.load(Ordering::Relaxed),
+            dropped: self.totals.dropped.load(Ordering::Relaxed),
+            queued_in: peers.values().map(|peer| peer.queued_in).sum(),
+            queued_out: peers.values().map(|peer| peer.queued_out).sum(),
+            connects: self.connects.load(Ordering::Relaxed),
+            disconnects: self.disconnects.load(Ordering::Relaxed),
+            handshake_failures: self.handshake_failures.load(Ordering::Relaxed),
+            oversized_messages: self.oversized_messages.load(Ordering::Relaxed),
+            peers,
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    fn export(&self, name: &'static str, value: u64) {
+        metrics::counter!(name, "socket" => self.socket_label()).increment(value);
+    }
+
+    #[cfg(feature = "metrics")]
+    fn socket_label(&self) -> &'static str {
+        // Socket type tags are ASCII.
+        str::from_utf8(self.socket_type.as_bytes()).unwrap()
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn export(&self, _name: &'static str, _value: u64) {}
+}
+
+// Counters of a single peer. Dispatchers update these as messages enter and
+// leave the peer queues; sessions only track the queue depth.
+#[derive(Debug)]
+pub(crate) struct PeerCounters {
+    socket: Arc<Counters>,
+    totals: Totals,
+    pub(crate) incoming: Arc<Depth>,
+    pub(crate) outgoing: Arc<Depth>,
+    #[cfg(feature = "metrics")]
+    gauges: [Arc<Gauges>; 2],
+}
+
+impl PeerCounters {
+    pub(crate) fn new(
+        socket: Arc<Counters>,
+        id: Route,
+        incoming: Arc<Depth>,
+        outgoing: Arc<Depth>,
+    ) -> Self {
+        #[cfg(feature = "metrics")]
+        let gauges = {
+            let gauges_in = Arc::new(Gauges::new(
+                "rmq_queued_in",
+                "rmq_peer_queued_in",
+                &socket,
+                id,
+            ));
+            let gauges_out = Arc::new(Gauges::new(
+                "rmq_queued_out",
+                "rmq_peer_queued_out",
+                &socket,
+                id,
+            ));
+            incoming.watch(gauges_in.clone());
+            outgoing.watch(gauges_out.clone());
+            [gauges_in, gauges_out]
+        };
+        #[cfg(not(feature = "metrics"))]
+        let _ = id;
+
+        Self {
+            socket,
+            totals: Default::default(),
+            incoming,
+            outgoing,
+            #[cfg(feature = "metrics")]
+            gauges,
+        }
+    }
+
+    pub(crate) fn received(&self, len: usize) {
+        self.incoming.pop();
+        self.totals.received(len);
+        self.socket.totals.received(len);
+        self.socket.export("rmq_messages_in_total", 1);
+        self.socket.export("rmq_bytes_in_total", len as u64);
+    }
+
+    // The message is counted into the outgoing queue by the caller, before it
+    // is queued.
+    pub(crate) fn sent(&self, len: usize) {
+        self.totals.sent(len);
+        self.socket.totals.sent(len);
+        self.socket.export("rmq_messages_out_total", 1);
+        self.socket.export("rmq_bytes_out_total", len as u64);
+    }
+
+    pub(crate) fn dropped(&self) {
+        self.totals.dropped();
+        self.socket.totals.dropped();
+        self.socket.export("rmq_messages_dropped_total", 1);
+    }
+
+    fn snapshot(&self) -> PeerStats {
+        PeerStats {
+            messages_in: self.totals.messages_in.load(Ordering::Relaxed),
+            bytes_in: self.totals.bytes_in.load(Ordering::Relaxed),
+            messages_out: self.totals.messages_out.load(Ordering::Relaxed),
+            bytes_out: self.totals.bytes_out.load(Ordering::Relaxed),
+            dropped: self.totals.dropped.load(Ordering::Relaxed),
+            queued_in: self.incoming.get(),
+            queued_out: self.outgoing.get(),
+        }
+    }
+}
+
+// Queues may outlive the peer, so their gauges stop counting with the peer.
+#[cfg(feature = "metrics")]
+impl Drop for PeerCounters {
+    fn drop(&mut self) {
+        let [gauges_in, gauges_out] = &self.gauges;
+        self.incoming.unwatch(gauges_in);
+        self.outgoing.unwatch(gauges_out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_includes_peers_and_totals() {
+        let socket = Arc::new(Counters::new(SocketType::CLIENT));
+        let peer = Arc::new(PeerCounters::new(
+            socket.clone(),
+            Route { id: 1 },
+            Default::default(),
+            Default::default(),
+        ));
+
+        socket.insert(Route { id: 1 }, peer.clone());
+        socket.connected();
+
+        peer.outgoing.push();
+        peer.sent(3);
+        peer.outgoing.push();
+        peer.sent(4);
+        peer.outgoing.pop();
+        peer.incoming.push();
+        peer.received(5);
+        peer.dropped();
+
+        let stats = socket.snapshot();
+        assert_eq!(stats.messages_out, 2);
+        assert_eq!(stats.bytes_out, 7);
+        assert_eq!(stats.messages_in, 1);
+        assert_eq!(stats.bytes_in, 5);
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(stats.connects, 1);
+        assert_eq!(stats.queued_out, 1);
+        assert_eq!(stats.queued_in, 0);
+        assert_eq!(stats.peers[&Route { id: 1 }].messages_out, 2);
+
+        socket.remove(Route { id: 1 });
+        let stats = socket.snapshot();
+        assert_eq!(stats.messages_out, 2);
+        assert!(stats.peers.is_empty());
+    }
+}
@@ -2,6 +2,30 @@
 
 pub(crate) use loom::sync::atomic;
 pub(crate) use loom::sync::Arc;
+pub(crate) use loom::sync::MutexGuard;
+
+#[derive(Debug)]
+pub(crate) struct AtomicWaker {
+    waker: loom::future::AtomicWaker,
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self {
+            waker: loom::future::AtomicWaker::new(),
+        }
+    }
+}
+
+impl AtomicWaker {
+    pub fn register(&self, waker: &std::task::Waker) {
+        self.waker.register_by_ref(waker)
+    }
+
+    pub fn wake(&self) {
+        self.waker.wake()
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct Mutex<T> {
@@ -10,16 +34,24 @@ pub(crate) struct Mutex<T> {
 
 impl<T: Default> Default for Mutex<T> {
     fn default() -> Self {
-        Self {
-            mutex: loom::sync::Mutex::new(Default::default()),
-        }
+        Self::new(Default::default())
     }
 }
 
 impl<T> Mutex<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            mutex: loom::sync::Mutex::new(value),
+        }
+    }
+
     pub fn lock(&self) -> loom::sync::MutexGuard<'_, T> {
         self.mutex.lock().unwrap()
     }
+
+    pub fn try_lock(&self) -> Option<loom::sync::MutexGuard<'_, T>> {
+        self.mutex.try_lock().ok()
+    }
 }
 
 #[derive(Debug)]
@@ -31,7 +63,7 @@ pub(crate) struct RwLock<T> {
 impl<T: Default> Default for RwLock<T> {
     fn default() -> Self {
         Self {
-            mutex: loom::sync::RwLock::new(Default::default()),
+            mutex: loom::sync::Mutex::new(Default::default()),
         }
     }
 }
@@ -45,3 +77,218 @@ impl<T> RwLock<T> {
         self.mutex.lock().unwrap()
     }
 }
+
+// Loom is a dev-dependency of this crate only, so pass the cfg to this crate
+// alone and run the models from the test binary. Loom runs threads on small
+// stacks, which unoptimized builds overflow:
+//
+//     CARGO_PROFILE_TEST_OPT_LEVEL=1 cargo rustc --lib --profile test -- --cfg loom
+#[cfg(test)]
+mod tests {
+    use futures::task::{waker, ArcWake};
+    use loom::thread;
+    use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+    use std::task::{Context, Poll};
+
+    use super::Arc;
+    use crate::dispatch::{FairSender, Peer, Register};
+    use crate::socket::Options;
+    use crate::sync::Snapshot;
+    use crate::Message;
+
+    fn options() -> Options {
+        Options {
+            outgoing_queue_size: 2,
+            ..Default::default()
+        }
+    }
+
+    #[derive(Default)]
+    struct Woken(AtomicBool);
+
+    impl ArcWake for Woken {
+        fn wake_by_ref(arc_self: &std::sync::Arc<Self>) {
+            arc_self.0.store(true, SeqCst);
+        }
+    }
+
+    // Polls until sent, like a task that is woken up straight away.
+    fn send(sender: &FairSender, message: Message) {
+        let woken = std::sync::Arc::new(Woken::default());
+        let waker = waker(woken);
+        let mut message = Some(message);
+        while sender
+            .poll_send(&mut message, &mut Context::from_waker(&waker))
+            .is_pending()
+        {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn snapshot_load_during_update() {
+        loom::model(|| {
+            let snapshot = Arc::new(Snapshot::new(vec![1]));
+            let writer = {
+                let snapshot = snapshot.clone();
+                thread::spawn(move || snapshot.update(|list| [&list[..], &[2]].concat()))
+            };
+
+            let list = snapshot.load();
+            assert!(*list == [1] || *list == [1, 2]);
+
+            writer.join().unwrap();
+            assert_eq!(*snapshot.load(), [1, 2]);
+        });
+    }
+
+    // Readers never go back to a version that was replaced.
+    #[test]
+    fn snapshot_loads_during_updates() {
+        loom::model(|| {
+            let snapshot = Arc::new(Snapshot::new(vec![1]));
+            let writer = {
+                let snapshot = snapshot.clone();
+                thread::spawn(move || {
+                    snapshot.update(|list| [&list[..], &[2]].concat());
+                    snapshot.update(|list| [&list[..], &[3]].concat());
+                })
+            };
+
+            let first = snapshot.load().len();
+            let second = snapshot.load().len();
+            assert!(first <= second);
+
+            writer.join().unwrap();
+            assert_eq!(*snapshot.load(), [1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn snapshot_concurrent_updates() {
+        loom::model(|| {
+            let snapshot = Arc::new(Snapshot::new(Vec::new()));
+            let writer = {
+                let snapshot = snapshot.clone();
+                thread::spawn(move || snapshot.update(|list| [&list[..], &[1]].concat()))
+            };
+
+            snapshot.update(|list| [&list[..], &[2]].concat());
+            writer.join().unwrap();
+
+            let mut list = (*snapshot.load()).clone();
+            list.sort();
+            assert_eq!(list, [1, 2]);
+        });
+    }
+
+    #[test]
+    fn register_during_send() {
+        loom::model(|| {
+            let sender = Arc::new(FairSender::default());
+            let (peer, mut pipe) = Peer::create(&options());
+            let registrar = {
+                let sender = sender.clone();
+                thread::spawn(move || sender.insert(peer.id, peer.tx))
+            };
+
+            let woken = std::sync::Arc::new(Woken::default());
+            let waker = waker(woken.clone());
+            let mut cx = Context::from_waker(&waker);
+            let mut message = Some(Message::default());
+            let sent = sender.poll_send(&mut message, &mut cx).is_ready();
+            registrar.join().unwrap();
+
+            // Sends that missed the new peer are woken up to try again.
+            if !sent {
+                assert!(woken.0.load(SeqCst));
+                assert_eq!(sender.poll_send(&mut message, &mut cx), Poll::Ready(Ok(())));
+            }
+
+            assert!(pipe.rx.try_recv().is_ok());
+        });
+    }
+
+    #[test]
+    fn remove_during_send() {
+        loom::model(|| {
+            let sender = Arc::new(FairSender::default());
+            let (peer1, mut pipe1) = Peer::create(&options());
+            let (peer2, mut pipe2) = Peer::create(&options());
+            sender.insert(peer1.id, peer1.tx);
+            sender.insert(peer2.id, peer2.tx);
+
+            let id = peer1.id;
+            let registrar = {
+                let sender = sender.clone();
+                thread::spawn(move || sender.remove(id))
+            };
+
+            send(&sender, Message::default());
+            registrar.join().unwrap();
+            send(&sender, Message::default());
+
+            let received1 = std::iter::from_fn(|| pipe1.rx.try_recv().ok()).count();
+            let received2 = std::iter::from_fn(|| pipe2.rx.try_recv().ok()).count();
+            assert!(received1 <= 1);
+            assert_eq!(received1 + received2, 2);
+        });
+    }
+
+    #[test]
+    fn concurrent_sends() {
+        loom::model(|| {
+            let sender = Arc::new(FairSender::default());
+            let (peer, mut pipe) = Peer::create(&options());
+            sender.insert(peer.id, peer.tx);
+
+            let other = {
+                let sender = sender.clone();
+                thread::spawn(move || send(&sender, Message::default()))
+            };
+
+            send(&sender, Message::default());
+            other.join().unwrap();
+
+            assert!(pipe.rx.try_recv().is_ok());
+            assert!(pipe.rx.try_recv().is_ok());
+        });
+    }
+
+    // A sender that finds the peer in use is woken up once the other is done.
+    #[test]
+    fn senders_compete_for_peer() {
+        loom::model(|| {
+            let sender = Arc::new(FairSender::default());
+            let (peer, mut pipe) = Peer::create(&options());
+            sender.insert(peer.id, peer.tx);
+
+            let poll_send = |sender: &FairSender| {
+                let woken = std::sync::Arc::new(Woken::default());
+                let waker = waker(woken.clone());
+                let mut message = Some(Message::default());
+                let sent = sender
+                    .poll_send(&mut message, &mut Context::from_waker(&waker))
+                    .is_ready();
+                (sent, message, woken, waker)
+            };
+
+            let other = {
+                let sender = sender.clone();
+                thread::spawn(move || poll_send(&sender))
+            };
+
+            let polls = vec![poll_send(&sender), other.join().unwrap()];
+            for (sent, mut message, woken, waker) in polls {
+                if !sent {
+                    assert!(woken.0.load(SeqCst));
+                    let mut cx = Context::from_waker(&waker);
+                    assert_eq!(sender.poll_send(&mut message, &mut cx), Poll::Ready(Ok(())));
+                }
+            }
+
+            assert!(pipe.rx.try_recv().is_ok());
+            assert!(pipe.rx.try_recv().is_ok());
+        });
+    }
+}
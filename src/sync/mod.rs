@@ -3,8 +3,16 @@ mod loom;
 
 #[cfg(not(loom))]
 mod loom {
+    pub(crate) use futures::task::AtomicWaker;
     pub(crate) use parking_lot::{Mutex, MutexGuard, RwLock};
     pub(crate) use std::sync::{atomic, Arc};
 }
 
+// Swaps reference counted pointers.
+#[allow(unsafe_code)]
+mod snapshot;
+mod try_mutex;
+
 pub(crate) use self::loom::*;
+pub(crate) use self::snapshot::Snapshot;
+pub(crate) use self::try_mutex::TryMutex;
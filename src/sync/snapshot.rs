@@ -0,0 +1,116 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+
+use super::atomic::{AtomicPtr, AtomicUsize, Ordering::SeqCst};
+use super::{Arc, Mutex};
+
+// A value that is replaced as a whole, so that readers never wait for writers
+// or for each other. Readers clone the current version; writers copy it, swap
+// in the modified copy, and retire the old version. Writers do not wait for
+// readers either: retired versions are released by a later update that finds
+// no reader counted, or once the snapshot is dropped.
+pub(crate) struct Snapshot<T> {
+    current: AtomicPtr<T>,
+
+    // Readers between loading the pointer and cloning the version it points to.
+    readers: AtomicUsize,
+
+    // Replaced versions that a reader may still be about to clone. Held by
+    // the writer while it updates.
+    retired: Mutex<Vec<Arc<T>>>,
+    marker: PhantomData<Arc<T>>,
+}
+
+impl<T> Snapshot<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self {
+            current: AtomicPtr::new(Arc::into_raw(Arc::new(value)) as *mut T),
+            readers: AtomicUsize::new(0),
+            retired: Mutex::new(Vec::new()),
+            marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn load(&self) -> Arc<T> {
+        self.readers.fetch_add(1, SeqCst);
+        let current = self.current.load(SeqCst);
+
+        // The writer does not release this version while we are counted.
+        let version = ManuallyDrop::new(unsafe { Arc::from_raw(current) });
+        let version = Arc::clone(&version);
+
+        self.readers.fetch_sub(1, SeqCst);
+        version
+    }
+
+    // Versions are never reused while a reader holds on to them, so comparing
+    // addresses is enough.
+    pub(crate) fn is_current(&self, version: &Arc<T>) -> bool {
+        std::ptr::eq(self.current.load(SeqCst), &**version)
+    }
+
+    pub(crate) fn update(&self, f: impl FnOnce(&T) -> T) {
+        let mut retired = self.retired.lock();
+
+        let next = Arc::new(f(&self.load()));
+        let previous = self.current.swap(Arc::into_raw(next) as *mut T, SeqCst);
+        retired.push(unsafe { Arc::from_raw(previous) });
+
+        // Readers that arrive from now on see the next version. Readers that
+        // saw a retired version were counted before it was swapped out, so
+        // none is left once the count has dropped to zero.
+        if self.readers.load(SeqCst) == 0 {
+            retired.clear();
+        }
+    }
+}
+
+impl<T> Drop for Snapshot<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Arc::from_raw(self.current.load(SeqCst)) });
+    }
+}
+
+impl<T: Default> Default for Snapshot<T> {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Snapshot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Snapshot").field(&self.load()).finish()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_replaces_value() {
+        let snapshot = Snapshot::new(vec![1]);
+        let before = snapshot.load();
+        assert!(snapshot.is_current(&before));
+
+        snapshot.update(|list| [&list[..], &[2]].concat());
+
+        assert_eq!(*before, vec![1]);
+        assert_eq!(*snapshot.load(), vec![1, 2]);
+        assert!(!snapshot.is_current(&before));
+    }
+
+    #[test]
+    fn drop_releases_all_versions() {
+        let value = Arc::new(());
+        let snapshot = Snapshot::new(value.clone());
+        let before = snapshot.load();
+        snapshot.update(|value| value.clone());
+        assert_eq!(Arc::strong_count(&value), 3);
+
+        drop(before);
+        drop(snapshot);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
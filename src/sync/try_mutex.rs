@@ -0,0 +1,135 @@
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::task::{Context, Poll, Waker};
+
+use super::{Mutex, MutexGuard};
+
+// A lock that tasks wait for without blocking their thread. Tasks that find it
+// locked are woken up once it is released. All of them are, since a single
+// waker slot would forget every task but the last one that asked.
+#[derive(Default)]
+pub(crate) struct TryMutex<T> {
+    mutex: Mutex<T>,
+    waiters: Mutex<Vec<Waker>>,
+}
+
+impl<T> TryMutex<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self {
+            mutex: Mutex::new(value),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn try_lock(&self) -> Option<TryMutexGuard<'_, T>> {
+        let guard = self.mutex.try_lock()?;
+        Some(TryMutexGuard {
+            guard: Some(guard),
+            waiters: &self.waiters,
+        })
+    }
+
+    pub(crate) fn poll_lock(&self, cx: &mut Context<'_>) -> Poll<TryMutexGuard<'_, T>> {
+        if let Some(guard) = self.try_lock() {
+            return Poll::Ready(guard);
+        }
+
+        {
+            let mut waiters = self.waiters.lock();
+            if !waiters.iter().any(|waker| waker.will_wake(cx.waker())) {
+                waiters.push(cx.waker().clone());
+            }
+        }
+
+        // Check again in case the lock was released before we were added.
+        match self.try_lock() {
+            Some(guard) => Poll::Ready(guard),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TryMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_tuple("TryMutex").field(&*guard).finish(),
+            None => f.write_str("TryMutex(<locked>)"),
+        }
+    }
+}
+
+pub(crate) struct TryMutexGuard<'a, T> {
+    guard: Option<MutexGuard<'a, T>>,
+    waiters: &'a Mutex<Vec<Waker>>,
+}
+
+impl<T> Deref for TryMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().expect("guard released")
+    }
+}
+
+impl<T> DerefMut for TryMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().expect("guard released")
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TryMutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TryMutexGuard").field(&**self).finish()
+    }
+}
+
+impl<T> Drop for TryMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Release the lock before waking, so that woken tasks can take it.
+        self.guard = None;
+        let waiters = mem::take(&mut *self.waiters.lock());
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use claim::*;
+    use futures_test::task::new_count_waker;
+
+    #[test]
+    fn poll_lock_waits_for_release() {
+        let mutex = TryMutex::new(1);
+        let (waker, count) = new_count_waker();
+        let cx = &mut Context::from_waker(&waker);
+
+        let guard = mutex.try_lock().unwrap();
+        assert_pending!(mutex.poll_lock(cx));
+        assert_none!(mutex.try_lock());
+        assert_eq!(count, 0);
+
+        drop(guard);
+        assert_eq!(count, 1);
+        assert_eq!(*assert_ready!(mutex.poll_lock(cx)), 1);
+    }
+
+    #[test]
+    fn release_wakes_all_waiters() {
+        let mutex = TryMutex::new(());
+        let (waker1, count1) = new_count_waker();
+        let (waker2, count2) = new_count_waker();
+
+        let guard = mutex.try_lock().unwrap();
+        assert_pending!(mutex.poll_lock(&mut Context::from_waker(&waker1)));
+        assert_pending!(mutex.poll_lock(&mut Context::from_waker(&waker2)));
+        assert_pending!(mutex.poll_lock(&mut Context::from_waker(&waker2)));
+
+        drop(guard);
+        assert_eq!(count1, 1);
+        assert_eq!(count2, 1);
+    }
+}
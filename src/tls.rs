@@ -0,0 +1,140 @@
+use futures_rustls::rustls::{
+    self,
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use futures_rustls::{TlsAcceptor, TlsConnector};
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::Error;
+
+pub(crate) type ServerName = rustls::pki_types::ServerName<'static>;
+
+#[derive(Default, Clone)]
+pub struct TlsOptions {
+    // DER encoded certificate chain, leaf first, and private key presented to
+    // peers. Required to listen, and to connect with mutual TLS.
+    pub certificate_chain: Vec<Vec<u8>>,
+    pub private_key: Option<Vec<u8>>,
+
+    // DER encoded CA certificates that peer certificates are verified against.
+    pub roots: Vec<Vec<u8>>,
+
+    // Listeners require clients to present a certificate signed by the roots.
+    pub mutual: bool,
+
+    // Sent as SNI and verified against the server certificate. Defaults to the
+    // IP address of the endpoint.
+    pub server_name: Option<String>,
+}
+
+impl fmt::Debug for TlsOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsOptions")
+            .field("certificates", &self.certificate_chain.len())
+            .field("roots", &self.roots.len())
+            .field("mutual", &self.mutual)
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsOptions {
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor, Error> {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|_| Error::OptionInvalid)?;
+
+        let builder = if self.mutual {
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(self.root_store()?),
+                provider(),
+            )
+            .build()
+            .map_err(|_| Error::OptionInvalid)?;
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let (chain, key) = self.identity().ok_or(Error::OptionInvalid)?;
+        let config = builder
+            .with_single_cert(chain, key)
+            .map_err(|_| Error::OptionInvalid)?;
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    pub(crate) fn connector(&self) -> Result<TlsConnector, Error> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|_| Error::OptionInvalid)?
+            .with_root_certificates(self.root_store()?);
+
+        let config = match self.identity() {
+            Some((chain, key)) => builder
+                .with_client_auth_cert(chain, key)
+                .map_err(|_| Error::OptionInvalid)?,
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+
+    pub(crate) fn server_name(&self, addr: SocketAddr) -> Result<ServerName, Error> {
+        match &self.server_name {
+            Some(name) => ServerName::try_from(name.clone()).map_err(|_| Error::OptionInvalid),
+            None => Ok(ServerName::from(addr.ip())),
+        }
+    }
+
+    fn root_store(&self) -> Result<RootCertStore, Error> {
+        let mut store = RootCertStore::empty();
+        for root in &self.roots {
+            store
+                .add(CertificateDer::from(root.clone()))
+                .map_err(|_| Error::OptionInvalid)?;
+        }
+
+        // Peers cannot be verified without roots.
+        if store.is_empty() {
+            return Err(Error::OptionInvalid);
+        }
+
+        Ok(store)
+    }
+
+    fn identity(&self) -> Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+        let key = PrivateKeyDer::try_from(self.private_key.clone()?).ok()?;
+        let chain = self
+            .certificate_chain
+            .iter()
+            .cloned()
+            .map(CertificateDer::from)
+            .collect::<Vec<_>>();
+
+        if chain.is_empty() {
+            return None;
+        }
+
+        Some((chain, key))
+    }
+}
+
+// Always use ring, regardless of the default provider of the process.
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+// Subject of the verified leaf certificate of the peer, e.g. "CN=example".
+pub(crate) fn subject(connection: &rustls::CommonState) -> Option<String> {
+    let leaf = connection.peer_certificates()?.first()?;
+    let (_, certificate) = X509Certificate::from_der(leaf).ok()?;
+    Some(certificate.subject().to_string())
+}
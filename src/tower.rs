@@ -0,0 +1,199 @@
+use futures::future::{poll_fn, BoxFuture};
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use std::error;
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+use crate::{rpc, Client, Envelope, Error, IntoMessage, Message, Request, Server};
+use std::sync::Arc;
+
+pub type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+#[derive(Debug, Clone)]
+pub struct ClientService {
+    client: Arc<Client>,
+}
+
+impl ClientService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+}
+
+impl From<Client> for ClientService {
+    fn from(client: Client) -> Self {
+        Self::new(Arc::new(client))
+    }
+}
+
+impl<T: IntoMessage + Send + 'static> Service<T> for ClientService {
+    type Response = Envelope<Message>;
+    type Error = Error;
+    type Future = BoxFuture<'static, Result<Envelope<Message>, Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        // Backpressure is applied when the request is queued for sending.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, message: T) -> Self::Future {
+        let client = self.client.clone();
+        async move { client.request(message).await }.boxed()
+    }
+}
+
+enum Event<R, E> {
+    Request(Request),
+    Response(Request, Result<R, E>),
+}
+
+pub async fn serve<S>(server: &Server, mut service: S) -> Result<(), BoxError>
+where
+    S: Service<Envelope<Message>>,
+    S::Response: IntoMessage,
+    S::Error: Into<BoxError>,
+{
+    let mut responses = FuturesUnordered::new();
+    let mut replies = FuturesUnordered::new();
+
+    loop {
+        let event = poll_fn(|cx| {
+            while let Poll::Ready(Some(result)) = replies.poll_next_unpin(cx) {
+                match result {
+                    Ok(()) => {}
+                    Err(Error::RoutingError) => {
+                        trace!("tower", "discarding reply to disconnected peer");
+                    }
+                    Err(err) => return Poll::Ready(Err(err.into())),
+                }
+            }
+
+            if let Poll::Ready(Some((request, response))) = responses.poll_next_unpin(cx) {
+                return Poll::Ready(Ok(Event::Response(request, response)));
+            }
+
+            // Only accept new requests once the service is able to process them.
+            if let Err(err) = futures::ready!(service.poll_ready(cx)) {
+                return Poll::Ready(Err(err.into()));
+            }
+
+            loop {
+                let envelope = match futures::ready!(server.inner.rx().poll_recv(cx)) {
+                    Ok(envelope) => envelope,
+                    Err(err) => return Poll::Ready(Err(err.into())),
+                };
+
+                if let Some(request) = rpc::request(envelope) {
+                    return Poll::Ready(Ok(Event::Request(request)));
+                }
+
+                trace!("tower", "discarding request without correlation id");
+            }
+        })
+        .await?;
+
+        match event {
+            Event::Request(request) => {
+                let response = service.call(request.envelope.clone());
+                responses.push(response.map(move |response| (request, response)));
+            }
+
+            Event::Response(request, response) => {
+                let message = match response {
+                    Ok(response) => rpc::reply(&request, response.into_message()),
+                    Err(err) => {
+                        let err: BoxError = err.into();
+                        debug!(
+                            "tower",
+                            "service failed; route={} err={}",
+                            request.route(),
+                            err
+                        );
+                        rpc::failure(&request)
+                    }
+                };
+
+                replies.push(server.route(message, request.route()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Rt, Runtime};
+    use claim::*;
+    use futures::future;
+
+    #[cfg(feature = "inproc")]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
+    async fn client_service_calls_server_service() {
+        let server = Server::default();
+        let addr = server.listen("inproc://rmq-tower-test").await.unwrap();
+
+        let client = Client::default();
+        client.connect(&addr).await.unwrap();
+
+        let echo = tower_service_fn(|envelope: Envelope<Message>| {
+            future::ok::<_, Error>([&b"re: "[..], envelope.as_bytes()].concat())
+        });
+
+        Rt::spawn(async move {
+            let _ = serve(&server, echo).await;
+        });
+
+        let mut service = ClientService::from(client);
+        assert_ok_eq!(service.call("hello").await, b"re: hello");
+    }
+
+    #[cfg(feature = "inproc")]
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(not(feature = "async-std"), tokio::test)]
+    async fn failed_requests_receive_error_reply() {
+        let server = Server::default();
+        let addr = server.listen("inproc://rmq-tower-fail-test").await.unwrap();
+
+        let client = Client::default();
+        client.connect(&addr).await.unwrap();
+
+        let fail = tower_service_fn(|envelope: Envelope<Message>| match envelope.as_bytes() {
+            b"fail" => future::err(Error::Unsupported),
+            _ => future::ok(envelope.message),
+        });
+
+        Rt::spawn(async move {
+            let _ = serve(&server, fail).await;
+        });
+
+        let mut service = ClientService::from(client);
+        assert_eq!(service.call("fail").await, Err(Error::RequestFailed));
+        assert_ok_eq!(service.call("hello").await, b"hello");
+    }
+
+    struct ServiceFn<F>(F);
+
+    fn tower_service_fn<F>(f: F) -> ServiceFn<F> {
+        ServiceFn(f)
+    }
+
+    impl<F, R, T> Service<Envelope<Message>> for ServiceFn<F>
+    where
+        F: FnMut(Envelope<Message>) -> R,
+        R: futures::Future<Output = Result<T, Error>>,
+    {
+        type Response = T;
+        type Error = Error;
+        type Future = R;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, envelope: Envelope<Message>) -> R {
+            (self.0)(envelope)
+        }
+    }
+}
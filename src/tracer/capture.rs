@@ -0,0 +1,409 @@
+use std::convert::TryInto;
+use std::io::{self, Read};
+use std::time::Duration;
+
+use super::Error;
+
+const PCAP_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER: u32 = 0x1a2b_3c4d;
+
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_TSRESOL: u16 = 9;
+
+// Larger packets and blocks are taken to be corrupt rather than allocated.
+const MAX_LEN: usize = 16 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub timestamp: Option<Duration>,
+    pub link_type: u32,
+    pub data: Vec<u8>,
+}
+
+pub(super) fn is_capture(magic: &[u8]) -> bool {
+    match magic.get(..4) {
+        Some(magic) => {
+            let le = u32::from_le_bytes(magic.try_into().unwrap());
+            let be = u32::from_be_bytes(magic.try_into().unwrap());
+            [PCAP_MICROS, PCAP_NANOS, PCAPNG_SECTION].contains(&le)
+                || [PCAP_MICROS, PCAP_NANOS].contains(&be)
+        }
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    link_type: u32,
+    // Number of timestamp units per second.
+    resolution: u64,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap { interface: Interface },
+    Pcapng { interfaces: Vec<Interface> },
+}
+
+// Reads packets from a pcap or pcapng capture.
+#[derive(Debug)]
+pub struct Packets<R> {
+    reader: R,
+    big_endian: bool,
+    format: Format,
+}
+
+impl<R: Read> Packets<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        if u32::from_le_bytes(magic) == PCAPNG_SECTION {
+            let mut packets = Self {
+                reader,
+                big_endian: false,
+                format: Format::Pcapng {
+                    interfaces: Vec::new(),
+                },
+            };
+
+            packets.read_section()?;
+            return Ok(packets);
+        }
+
+        let (big_endian, resolution) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic))
+        {
+            (PCAP_MICROS, _) => (false, 1_000_000),
+            (PCAP_NANOS, _) => (false, 1_000_000_000),
+            (_, PCAP_MICROS) => (true, 1_000_000),
+            (_, PCAP_NANOS) => (true, 1_000_000_000),
+            _ => return Err(Error::InvalidCapture("unknown file format")),
+        };
+
+        let mut header = [0; 20];
+        reader.read_exact(&mut header)?;
+
+        let mut packets = Self {
+            reader,
+            big_endian,
+            format: Format::Pcap {
+                interface: Interface {
+                    link_type: 0,
+                    resolution,
+                },
+            },
+        };
+
+        let link_type = packets.u32(&header[16..20]);
+        if let Format::Pcap { interface } = &mut packets.format {
+            interface.link_type = link_type;
+        }
+
+        Ok(packets)
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes[..2].try_into().unwrap();
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    // Reads the remainder of a section header block, of which the block type
+    // has already been consumed. Also determines the byte order.
+    fn read_section(&mut self) -> Result<(), Error> {
+        let mut header = [0; 8];
+        self.reader.read_exact(&mut header)?;
+
+        self.big_endian = match header[4..8].try_into().map(u32::from_le_bytes) {
+            Ok(PCAPNG_BYTE_ORDER) => false,
+            _ if u32::from_be_bytes(header[4..8].try_into().unwrap()) == PCAPNG_BYTE_ORDER => true,
+            _ => return Err(Error::InvalidCapture("invalid byte order magic")),
+        };
+
+        let len = self.u32(&header[..4]) as usize;
+        if !(12..=MAX_LEN).contains(&len) {
+            return Err(Error::InvalidCapture("invalid block length"));
+        }
+
+        let mut rest = vec![0; len - 12];
+        self.reader.read_exact(&mut rest)?;
+
+        if let Format::Pcapng { interfaces } = &mut self.format {
+            interfaces.clear();
+        }
+
+        Ok(())
+    }
+
+    fn next_pcap(&mut self, interface: Interface) -> Result<Option<Packet>, Error> {
+        let mut header = [0; 16];
+        if !read_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+
+        let seconds = self.u32(&header[0..4]) as u64;
+        let fraction = self.u32(&header[4..8]) as u64;
+        let len = self.u32(&header[8..12]) as usize;
+        if len > MAX_LEN {
+            return Err(Error::InvalidCapture("invalid packet length"));
+        }
+
+        let mut data = vec![0; len];
+        self.reader.read_exact(&mut data)?;
+
+        Ok(Some(Packet {
+            timestamp: Some(
+                Duration::from_secs(seconds) + timestamp(fraction, interface.resolution),
+            ),
+            link_type: interface.link_type,
+            data,
+        }))
+    }
+
+    fn next_pcapng(&mut self) -> Result<Option<Packet>, Error> {
+        loop {
+            let mut header = [0; 4];
+            if !read_or_eof(&mut self.reader, &mut header)? {
+                return Ok(None);
+            }
+
+            if u32::from_le_bytes(header) == PCAPNG_SECTION {
+                self.read_section()?;
+                continue;
+            }
+
+            let kind = self.u32(&header);
+
+            let mut len = [0; 4];
+            self.reader.read_exact(&mut len)?;
+            let len = self.u32(&len) as usize;
+            if !(12..=MAX_LEN).contains(&len) || !len.is_multiple_of(4) {
+                return Err(Error::InvalidCapture("invalid block length"));
+            }
+
+            let mut body = vec![0; len - 8];
+            self.reader.read_exact(&mut body)?;
+            body.truncate(len - 12);
+
+            match kind {
+                PCAPNG_INTERFACE if body.len() >= 8 => {
+                    let link_type = self.u16(&body[0..2]) as u32;
+                    let resolution = self.resolution(&body[8..]);
+                    if let Format::Pcapng { interfaces } = &mut self.format {
+                        interfaces.push(Interface {
+                            link_type,
+                            resolution,
+                        });
+                    }
+                }
+
+                PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                    let interface = self.interface(self.u32(&body[0..4]) as usize)?;
+
+                    let units =
+                        (self.u32(&body[4..8]) as u64) << 32 | self.u32(&body[8..12]) as u64;
+                    let captured = self.u32(&body[12..16]) as usize;
+                    let data = body
+                        .get(20..20 + captured)
+                        .ok_or(Error::InvalidCapture("truncated packet"))?;
+
+                    return Ok(Some(Packet {
+                        timestamp: Some(timestamp(units, interface.resolution)),
+                        link_type: interface.link_type,
+                        data: data.to_vec(),
+                    }));
+                }
+
+                PCAPNG_SIMPLE_PACKET if body.len() >= 4 => {
+                    let interface = self.interface(0)?;
+
+                    let original = self.u32(&body[0..4]) as usize;
+                    let data = &body[4..];
+                    return Ok(Some(Packet {
+                        timestamp: None,
+                        link_type: interface.link_type,
+                        data: data[..original.min(data.len())].to_vec(),
+                    }));
+                }
+
+                // Skip other blocks, such as statistics and name resolution.
+                _ => {}
+            }
+        }
+    }
+
+    fn interface(&self, index: usize) -> Result<Interface, Error> {
+        match &self.format {
+            Format::Pcapng { interfaces } => interfaces.get(index).copied(),
+            Format::Pcap { interface } => Some(*interface),
+        }
+        .ok_or(Error::InvalidCapture("unknown interface"))
+    }
+
+    fn resolution(&self, mut options: &[u8]) -> u64 {
+        while options.len() >= 4 {
+            let code = self.u16(&options[0..2]);
+            let len = self.u16(&options[2..4]) as usize;
+            let value = &options[4..options.len().min(4 + len)];
+
+            if code == PCAPNG_TSRESOL && len == 1 && !value.is_empty() {
+                let exponent = (value[0] & 0x7f) as u32;
+                return if value[0] & 0x80 == 0 {
+                    10u64.saturating_pow(exponent)
+                } else {
+                    2u64.saturating_pow(exponent)
+                };
+            }
+
+            // Options are padded to 32 bits.
+            let padded = 4 + len.div_ceil(4) * 4;
+            options = &options[options.len().min(padded)..];
+        }
+
+        1_000_000
+    }
+}
+
+impl<R: Read> Iterator for Packets<R> {
+    type Item = Result<Packet, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.format {
+            Format::Pcap { interface } => self.next_pcap(interface),
+            Format::Pcapng { .. } => self.next_pcapng(),
+        };
+
+        result.transpose()
+    }
+}
+
+// Converts a number of timestamp units, of which there are resolution per
+// second, without overflowing at high resolutions.
+fn timestamp(units: u64, resolution: u64) -> Duration {
+    let nanos = units as u128 * 1_000_000_000 / resolution as u128;
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+// Fills the buffer completely, or returns false if the reader is at EOF.
+fn read_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> Result<bool, Error> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(Error::InvalidCapture("truncated capture")),
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::*;
+
+    #[test]
+    fn reads_pcap() {
+        let mut capture = Vec::new();
+        capture.extend_from_slice(&PCAP_MICROS.to_le_bytes());
+        capture.extend_from_slice(&[2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0]);
+        capture.extend_from_slice(&101u32.to_le_bytes());
+        capture.extend_from_slice(&[10, 0, 0, 0, 20, 0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0]);
+        capture.extend_from_slice(b"abc");
+
+        let mut packets = Packets::new(&capture[..]).unwrap();
+        assert_eq!(
+            packets.next().unwrap().unwrap(),
+            Packet {
+                timestamp: Some(Duration::from_micros(10_000_020)),
+                link_type: 101,
+                data: b"abc".to_vec(),
+            }
+        );
+        assert_none!(packets.next());
+    }
+
+    #[test]
+    fn reads_pcapng() {
+        let mut capture = Vec::new();
+        capture.extend_from_slice(&PCAPNG_SECTION.to_le_bytes());
+        capture.extend_from_slice(&28u32.to_le_bytes());
+        capture.extend_from_slice(&PCAPNG_BYTE_ORDER.to_le_bytes());
+        capture.extend_from_slice(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        capture.extend_from_slice(&28u32.to_le_bytes());
+
+        // Interface with nanosecond resolution.
+        capture.extend_from_slice(&PCAPNG_INTERFACE.to_le_bytes());
+        capture.extend_from_slice(&32u32.to_le_bytes());
+        capture.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        capture.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        capture.extend_from_slice(&32u32.to_le_bytes());
+
+        capture.extend_from_slice(&PCAPNG_ENHANCED_PACKET.to_le_bytes());
+        capture.extend_from_slice(&36u32.to_le_bytes());
+        capture.extend_from_slice(&0u32.to_le_bytes());
+        capture.extend_from_slice(&0u32.to_le_bytes());
+        capture.extend_from_slice(&1_500_000_000u32.to_le_bytes());
+        capture.extend_from_slice(&3u32.to_le_bytes());
+        capture.extend_from_slice(&3u32.to_le_bytes());
+        capture.extend_from_slice(b"abc\0");
+        capture.extend_from_slice(&36u32.to_le_bytes());
+
+        let mut packets = Packets::new(&capture[..]).unwrap();
+        assert_eq!(
+            packets.next().unwrap().unwrap(),
+            Packet {
+                timestamp: Some(Duration::from_millis(1500)),
+                link_type: 1,
+                data: b"abc".to_vec(),
+            }
+        );
+        assert_none!(packets.next());
+    }
+
+    #[test]
+    fn reads_timestamps_at_high_resolutions() {
+        assert_eq!(timestamp(u64::MAX, 1), Duration::from_secs(u64::MAX));
+        assert_eq!(timestamp(3 << 62, 1 << 63), Duration::from_millis(1500));
+        assert_eq!(timestamp(u64::MAX, u64::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn rejects_oversized_packets() {
+        let mut capture = Vec::new();
+        capture.extend_from_slice(&PCAP_MICROS.to_le_bytes());
+        capture.extend_from_slice(&[2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0]);
+        capture.extend_from_slice(&101u32.to_le_bytes());
+        capture.extend_from_slice(&[10, 0, 0, 0, 20, 0, 0, 0]);
+        capture.extend_from_slice(&u32::MAX.to_le_bytes());
+        capture.extend_from_slice(&u32::MAX.to_le_bytes());
+
+        let mut packets = Packets::new(&capture[..]).unwrap();
+        assert!(matches!(
+            packets.next(),
+            Some(Err(Error::InvalidCapture("invalid packet length")))
+        ));
+    }
+}
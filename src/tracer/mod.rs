@@ -0,0 +1,504 @@
+mod capture;
+mod tcp;
+
+use bytes::BytesMut;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::{fmt, str};
+use tokio_util::codec::Decoder as _;
+
+use crate::zmtp::{self, Zmtp};
+
+pub use capture::{Packet, Packets};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Greeting {
+        version: (u8, u8),
+        mechanism: String,
+    },
+    Ready {
+        socket_type: String,
+        properties: BTreeMap<String, Vec<u8>>,
+    },
+    Message {
+        more: bool,
+        size: usize,
+    },
+    Error {
+        reason: Vec<u8>,
+    },
+    Ping {
+        ttl: u16,
+        context: Vec<u8>,
+    },
+    Pong {
+        context: Vec<u8>,
+    },
+    Subscribe {
+        group: Vec<u8>,
+    },
+    Cancel {
+        group: Vec<u8>,
+    },
+    Join {
+        group: Vec<u8>,
+    },
+    Leave {
+        group: Vec<u8>,
+    },
+}
+
+impl From<zmtp::Frame> for Frame {
+    fn from(frame: zmtp::Frame) -> Self {
+        let text = |bytes: &[u8]| {
+            String::from_utf8_lossy(bytes)
+                .trim_end_matches('\0')
+                .to_owned()
+        };
+
+        match frame {
+            zmtp::Frame::Greeting { version, security } => Self::Greeting {
+                version,
+                mechanism: text(security.as_bytes()),
+            },
+            zmtp::Frame::Ready {
+                socket_type,
+                properties,
+            } => Self::Ready {
+                socket_type: text(socket_type.as_bytes()),
+                properties: properties
+                    .into_iter()
+                    .map(|(key, value)| (key.into_owned(), value))
+                    .collect(),
+            },
+            zmtp::Frame::Message { more, payload } => Self::Message {
+                more,
+                size: payload.len(),
+            },
+            zmtp::Frame::Error { reason } => Self::Error {
+                reason: reason.to_vec(),
+            },
+            zmtp::Frame::Ping { ttl, context } => Self::Ping {
+                ttl,
+                context: context.to_vec(),
+            },
+            zmtp::Frame::Pong { context } => Self::Pong {
+                context: context.to_vec(),
+            },
+            zmtp::Frame::Subscribe { group } => Self::Subscribe {
+                group: group.to_vec(),
+            },
+            zmtp::Frame::Cancel { group } => Self::Cancel {
+                group: group.to_vec(),
+            },
+            zmtp::Frame::Join { group } => Self::Join {
+                group: group.to_vec(),
+            },
+            zmtp::Frame::Leave { group } => Self::Leave {
+                group: group.to_vec(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Greeting { version, mechanism } => write!(
+                f,
+                "GREETING version={}.{} mechanism={}",
+                version.0, version.1, mechanism
+            ),
+            Self::Ready {
+                socket_type,
+                properties,
+            } => {
+                write!(f, "READY Socket-Type={}", socket_type)?;
+                for (key, value) in properties {
+                    write!(f, " {}={}", key, Value(value))?;
+                }
+
+                Ok(())
+            }
+            Self::Message { more: true, size } => write!(f, "MESSAGE size={} more", size),
+            Self::Message { more: false, size } => write!(f, "MESSAGE size={}", size),
+            Self::Error { reason } => write!(f, "ERROR reason={}", Value(reason)),
+            Self::Ping { ttl, context } => {
+                write!(f, "PING ttl={} context={}", ttl, Value(context))
+            }
+            Self::Pong { context } => write!(f, "PONG context={}", Value(context)),
+            Self::Subscribe { group } => write!(f, "SUBSCRIBE group={}", Value(group)),
+            Self::Cancel { group } => write!(f, "CANCEL group={}", Value(group)),
+            Self::Join { group } => write!(f, "JOIN group={}", Value(group)),
+            Self::Leave { group } => write!(f, "LEAVE group={}", Value(group)),
+        }
+    }
+}
+
+// Prints printable values as text and anything else as hex.
+struct Value<'a>(&'a [u8]);
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.iter().all(|byte| byte.is_ascii_graphic()) {
+            return write!(f, "{}", str::from_utf8(self.0).unwrap());
+        }
+
+        write!(f, "0x")?;
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+// One direction of a TCP connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Flow {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+impl fmt::Display for Flow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} > {}", self.source, self.destination)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub timestamp: Option<Duration>,
+    pub flow: Option<Flow>,
+    pub frame: Frame,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(timestamp) = self.timestamp {
+            write!(
+                f,
+                "{}.{:06} ",
+                timestamp.as_secs(),
+                timestamp.subsec_micros()
+            )?;
+        }
+
+        if let Some(flow) = self.flow {
+            write!(f, "{} ", flow)?;
+        }
+
+        write!(f, "{}", self.frame)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    InvalidCapture(&'static str),
+    InvalidStream(Option<Flow>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::InvalidCapture(reason) => write!(f, "invalid capture: {}", reason),
+            Self::InvalidStream(Some(flow)) => write!(f, "invalid ZMTP stream {}", flow),
+            Self::InvalidStream(None) => write!(f, "invalid ZMTP stream"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(cause: io::Error) -> Self {
+        match cause.kind() {
+            io::ErrorKind::UnexpectedEof => Self::InvalidCapture("truncated capture"),
+            _ => Self::Io(cause),
+        }
+    }
+}
+
+// Decodes ZMTP frames from one direction of a connection.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    zmtp: Zmtp,
+    buffer: BytesMut,
+    failed: bool,
+}
+
+impl Decoder {
+    // Adds data to the stream and returns the frames that are now complete.
+    // Decoding stops at the first invalid frame.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Result<Frame, Error>> {
+        let mut frames = Vec::new();
+        if self.failed {
+            return frames;
+        }
+
+        self.buffer.extend_from_slice(data);
+        loop {
+            match self.zmtp.decode(&mut self.buffer) {
+                Ok(Some(frame)) => frames.push(Ok(frame.into())),
+                Ok(None) => return frames,
+                Err(..) => {
+                    self.failed = true;
+                    self.buffer.clear();
+                    frames.push(Err(Error::InvalidStream(None)));
+                    return frames;
+                }
+            }
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+}
+
+#[derive(Debug, Default)]
+struct Connection {
+    stream: tcp::Stream,
+    // Not set for connections that do not start with a ZMTP greeting.
+    decoder: Option<Decoder>,
+}
+
+#[derive(Debug)]
+enum Source<R> {
+    Capture {
+        packets: Packets<R>,
+        connections: HashMap<Flow, Connection>,
+    },
+    Stream {
+        reader: R,
+        decoder: Decoder,
+    },
+}
+
+// Iterates over the ZMTP frames in a pcap or pcapng capture, or in a raw byte
+// stream that contains one direction of a ZMTP connection.
+#[derive(Debug)]
+pub struct Trace<R> {
+    source: Source<io::Chain<io::Cursor<Vec<u8>>, R>>,
+    events: VecDeque<Result<Event, Error>>,
+    done: bool,
+}
+
+pub fn trace<R: Read>(mut reader: R) -> Result<Trace<R>, Error> {
+    let mut magic = Vec::with_capacity(4);
+    (&mut reader).take(4).read_to_end(&mut magic)?;
+    let is_capture = capture::is_capture(&magic);
+    let reader = io::Cursor::new(magic).chain(reader);
+
+    let source = if is_capture {
+        Source::Capture {
+            packets: Packets::new(reader)?,
+            connections: HashMap::new(),
+        }
+    } else {
+        Source::Stream {
+            reader,
+            decoder: Decoder::default(),
+        }
+    };
+
+    Ok(Trace {
+        source,
+        events: VecDeque::new(),
+        done: false,
+    })
+}
+
+impl<R: Read> Trace<R> {
+    fn read(&mut self) -> Result<bool, Error> {
+        match &mut self.source {
+            Source::Capture {
+                packets,
+                connections,
+            } => {
+                let packet = match packets.next() {
+                    Some(packet) => packet?,
+                    None => return Ok(false),
+                };
+
+                let segment = match tcp::parse(packet.link_type, &packet.data) {
+                    Some(segment) => segment,
+                    None => return Ok(true),
+                };
+
+                let connection = connections.entry(segment.flow).or_default();
+                if segment.syn {
+                    connection.decoder = None;
+                }
+
+                let data = connection
+                    .stream
+                    .push(segment.seq, segment.syn, segment.payload);
+
+                // Only decode connections that are captured from the start.
+                if connection.decoder.is_none() && data.first() == Some(&0xff) {
+                    connection.decoder = Some(Decoder::default());
+                }
+
+                if let Some(decoder) = &mut connection.decoder {
+                    let (timestamp, flow) = (packet.timestamp, segment.flow);
+                    self.events
+                        .extend(decoder.push(&data).into_iter().map(|frame| match frame {
+                            Ok(frame) => Ok(Event {
+                                timestamp,
+                                flow: Some(flow),
+                                frame,
+                            }),
+                            Err(..) => Err(Error::InvalidStream(Some(flow))),
+                        }));
+                }
+
+                if segment.closed {
+                    connections.remove(&segment.flow);
+                }
+
+                Ok(true)
+            }
+
+            Source::Stream { reader, decoder } => {
+                let mut buffer = [0; 8192];
+                let len = match reader.read(&mut buffer) {
+                    Ok(0) => return Ok(false),
+                    Ok(len) => len,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => return Ok(true),
+                    Err(err) => return Err(err.into()),
+                };
+
+                self.events
+                    .extend(decoder.push(&buffer[..len]).into_iter().map(|frame| {
+                        frame.map(|frame| Event {
+                            timestamp: None,
+                            flow: None,
+                            frame,
+                        })
+                    }));
+
+                Ok(!decoder.failed())
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Trace<R> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.events.is_empty() && !self.done {
+            match self.read() {
+                Ok(more) => self.done = !more,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREETING: &[u8] = b"\xff\0\0\0\0\0\0\0\0\x7f\x03\x01NULL\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+    const READY: &[u8] =
+        b"\x04\x2c\x05READY\x0bSocket-Type\0\0\0\x06CLIENT\x08Identity\0\0\0\x03abc";
+
+    fn frames(trace: impl Iterator<Item = Result<Event, Error>>) -> Vec<String> {
+        trace.map(|event| event.unwrap().to_string()).collect()
+    }
+
+    // Builds a raw IPv4 packet with a TCP segment.
+    fn packet(seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let len = (40 + payload.len()) as u16;
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0, 127, 0, 0, 1, 127, 0, 0, 2]);
+        packet.extend_from_slice(&[0x9c, 0x40, 0x15, 0xb3]);
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 0x50, flags, 0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn pcap(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut capture = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        capture.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 101, 0, 0, 0]);
+        for (i, packet) in packets.iter().enumerate() {
+            let len = packet.len() as u32;
+            capture.extend_from_slice(&1u32.to_le_bytes());
+            capture.extend_from_slice(&(i as u32).to_le_bytes());
+            capture.extend_from_slice(&len.to_le_bytes());
+            capture.extend_from_slice(&len.to_le_bytes());
+            capture.extend_from_slice(packet);
+        }
+
+        capture
+    }
+
+    #[test]
+    fn trace_decodes_raw_stream() {
+        let stream = [GREETING, READY, b"\x01\x03abc\x00\x00"].concat();
+        assert_eq!(
+            frames(trace(&stream[..]).unwrap()),
+            vec![
+                "GREETING version=3.1 mechanism=NULL",
+                "READY Socket-Type=CLIENT Identity=abc",
+                "MESSAGE size=3 more",
+                "MESSAGE size=0",
+            ]
+        );
+    }
+
+    #[test]
+    fn trace_reassembles_captured_segments() {
+        let capture = pcap(&[
+            packet(99, 0x02, b""),
+            packet(100 + GREETING.len() as u32, 0x18, READY),
+            packet(100, 0x18, GREETING),
+            packet(
+                100 + (GREETING.len() + READY.len()) as u32,
+                0x18,
+                b"\x04\x08\x04PING\x00\x0a\x00",
+            ),
+        ]);
+
+        assert_eq!(
+            frames(trace(&capture[..]).unwrap()),
+            vec![
+                "1.000002 127.0.0.1:40000 > 127.0.0.2:5555 GREETING version=3.1 mechanism=NULL",
+                "1.000002 127.0.0.1:40000 > 127.0.0.2:5555 READY Socket-Type=CLIENT Identity=abc",
+                "1.000003 127.0.0.1:40000 > 127.0.0.2:5555 PING ttl=10 context=0x00",
+            ]
+        );
+    }
+
+    #[test]
+    fn trace_ignores_connections_without_greeting() {
+        let capture = pcap(&[packet(100, 0x18, b"GET / HTTP/1.1\r\n")]);
+        assert_eq!(frames(trace(&capture[..]).unwrap()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn trace_reports_invalid_stream() {
+        let stream = [GREETING, b"\xf0\x00"].concat();
+        let mut trace = trace(&stream[..]).unwrap();
+        assert!(trace.next().unwrap().is_ok());
+        assert!(matches!(
+            trace.next(),
+            Some(Err(Error::InvalidStream(None)))
+        ));
+        assert!(trace.next().is_none());
+    }
+}
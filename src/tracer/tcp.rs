@@ -0,0 +1,240 @@
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::Flow;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_RAW_OPENBSD: u32 = 12;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const PROTOCOL_TCP: u8 = 6;
+
+// Limit the number of out-of-order segments kept per stream.
+const MAX_PENDING: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Segment<'a> {
+    pub flow: Flow,
+    pub seq: u32,
+    pub syn: bool,
+    pub closed: bool,
+    pub payload: &'a [u8],
+}
+
+// Extracts a TCP segment from a captured packet. Anything that is not TCP over
+// IPv4 or IPv6 is ignored.
+pub(super) fn parse(link_type: u32, data: &[u8]) -> Option<Segment<'_>> {
+    let (ethertype, packet) = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes(data.get(12..14)?.try_into().ok()?);
+            let mut offset = 14;
+            while ethertype == ETHERTYPE_VLAN {
+                ethertype = u16::from_be_bytes(data.get(offset + 2..offset + 4)?.try_into().ok()?);
+                offset += 4;
+            }
+
+            (ethertype, data.get(offset..)?)
+        }
+
+        LINKTYPE_LINUX_SLL => (
+            u16::from_be_bytes(data.get(14..16)?.try_into().ok()?),
+            data.get(16..)?,
+        ),
+
+        LINKTYPE_LINUX_SLL2 => (
+            u16::from_be_bytes(data.get(0..2)?.try_into().ok()?),
+            data.get(20..)?,
+        ),
+
+        // Address family in host byte order of the capturing machine.
+        LINKTYPE_NULL => match data.get(4).map(|byte| byte >> 4) {
+            Some(4) => (ETHERTYPE_IPV4, &data[4..]),
+            Some(6) => (ETHERTYPE_IPV6, &data[4..]),
+            _ => return None,
+        },
+
+        LINKTYPE_RAW | LINKTYPE_RAW_OPENBSD => match data.first().map(|byte| byte >> 4) {
+            Some(4) => (ETHERTYPE_IPV4, data),
+            Some(6) => (ETHERTYPE_IPV6, data),
+            _ => return None,
+        },
+
+        _ => return None,
+    };
+
+    let (source, destination, segment) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_len = (*packet.first()? as usize & 0x0f) * 4;
+            let total_len = u16::from_be_bytes(packet.get(2..4)?.try_into().ok()?) as usize;
+            if *packet.get(9)? != PROTOCOL_TCP {
+                return None;
+            }
+
+            // Ignore fragments other than the first.
+            if u16::from_be_bytes(packet.get(6..8)?.try_into().ok()?) & 0x1fff != 0 {
+                return None;
+            }
+
+            let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv4Addr::from(source)),
+                IpAddr::from(Ipv4Addr::from(destination)),
+                packet.get(header_len..total_len.min(packet.len()))?,
+            )
+        }
+
+        ETHERTYPE_IPV6 => {
+            let payload_len = u16::from_be_bytes(packet.get(4..6)?.try_into().ok()?) as usize;
+            if *packet.get(6)? != PROTOCOL_TCP {
+                return None;
+            }
+
+            let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv6Addr::from(source)),
+                IpAddr::from(Ipv6Addr::from(destination)),
+                packet.get(40..(40 + payload_len).min(packet.len()))?,
+            )
+        }
+
+        _ => return None,
+    };
+
+    let source_port = u16::from_be_bytes(segment.get(0..2)?.try_into().ok()?);
+    let destination_port = u16::from_be_bytes(segment.get(2..4)?.try_into().ok()?);
+    let seq = u32::from_be_bytes(segment.get(4..8)?.try_into().ok()?);
+    let header_len = (*segment.get(12)? as usize >> 4) * 4;
+    let flags = *segment.get(13)?;
+
+    Some(Segment {
+        flow: Flow {
+            source: SocketAddr::new(source, source_port),
+            destination: SocketAddr::new(destination, destination_port),
+        },
+        seq,
+        syn: flags & 0x02 != 0,
+        // FIN or RST.
+        closed: flags & 0x05 != 0,
+        payload: segment.get(header_len..)?,
+    })
+}
+
+// Reassembles one direction of a TCP connection.
+#[derive(Debug, Default)]
+pub(super) struct Stream {
+    next_seq: Option<u32>,
+    pending: Vec<(u32, Vec<u8>)>,
+}
+
+impl Stream {
+    // Adds a segment and returns any data that is now in order.
+    pub fn push(&mut self, seq: u32, syn: bool, payload: &[u8]) -> Vec<u8> {
+        if syn {
+            self.next_seq = Some(seq.wrapping_add(1));
+            self.pending.clear();
+        }
+
+        // Without a SYN the capture started halfway; take the first segment
+        // as the start of the stream.
+        let next_seq = *self.next_seq.get_or_insert(seq);
+
+        let mut data = Vec::new();
+        if !Self::append(&mut data, next_seq, seq, payload) {
+            if self.pending.len() < MAX_PENDING {
+                self.pending.push((seq, payload.to_vec()));
+            }
+
+            return data;
+        }
+
+        // Apply pending segments that have become contiguous.
+        loop {
+            let next_seq = self.next_seq().wrapping_add(data.len() as u32);
+            let before = data.len();
+            self.pending
+                .retain(|(seq, payload)| !Self::append(&mut data, next_seq, *seq, payload));
+
+            if data.len() == before {
+                break;
+            }
+        }
+
+        self.next_seq = Some(self.next_seq().wrapping_add(data.len() as u32));
+        data
+    }
+
+    fn next_seq(&self) -> u32 {
+        self.next_seq.unwrap_or_default()
+    }
+
+    // Appends the part of the payload at or after the expected sequence
+    // number. Returns false if the payload starts beyond it.
+    fn append(data: &mut Vec<u8>, next_seq: u32, seq: u32, payload: &[u8]) -> bool {
+        let offset = next_seq.wrapping_sub(seq) as i32;
+        if offset < 0 {
+            return false;
+        }
+
+        if let Some(new) = payload.get(offset as usize..) {
+            data.extend_from_slice(new);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::*;
+
+    #[test]
+    fn parse_extracts_tcp_over_ipv4() {
+        let mut packet = vec![0x45, 0, 0, 44, 0, 0, 0x40, 0, 64, 6, 0, 0];
+        packet.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        packet.extend_from_slice(&[0x15, 0xb3, 0x9c, 0x40, 0, 0, 0, 7, 0, 0, 0, 0, 0x50, 0x18]);
+        packet.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(b"abcd");
+
+        let segment = parse(LINKTYPE_RAW, &packet).unwrap();
+        assert_eq!(segment.flow.source, "10.0.0.1:5555".parse().unwrap());
+        assert_eq!(segment.flow.destination, "10.0.0.2:40000".parse().unwrap());
+        assert_eq!(segment.seq, 7);
+        assert!(!segment.syn);
+        assert_eq!(segment.payload, b"abcd");
+    }
+
+    #[test]
+    fn parse_ignores_udp() {
+        let mut packet = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0];
+        packet.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2, 0, 1, 0, 2, 0, 8, 0, 0]);
+        assert_none!(parse(LINKTYPE_RAW, &packet));
+    }
+
+    #[test]
+    fn stream_reorders_segments() {
+        let mut stream = Stream::default();
+        assert_eq!(stream.push(99, true, b""), b"");
+        assert_eq!(stream.push(105, false, b"fgh"), b"");
+        assert_eq!(stream.push(100, false, b"abcd"), b"abcd");
+        assert_eq!(stream.push(102, false, b"cde"), b"efgh");
+        assert_eq!(stream.push(107, false, b"hij"), b"ij");
+    }
+
+    #[test]
+    fn stream_handles_sequence_wraparound() {
+        let mut stream = Stream::default();
+        assert_eq!(stream.push(u32::MAX - 1, false, b"ab"), b"ab");
+        assert_eq!(stream.push(1, false, b"de"), b"");
+        assert_eq!(stream.push(0, false, b"cd"), b"cde");
+    }
+}
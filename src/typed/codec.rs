@@ -0,0 +1,114 @@
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{error, fmt};
+
+pub trait Codec: fmt::Debug + Default + Send + Sync + 'static {
+    type EncodeError: error::Error + Send + Sync + 'static;
+    type DecodeError: error::Error + Send + Sync + 'static;
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Bytes, Self::EncodeError>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::DecodeError>;
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Json;
+
+impl Codec for Json {
+    type EncodeError = serde_json::Error;
+    type DecodeError = serde_json::Error;
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Bytes, Self::EncodeError> {
+        serde_json::to_vec(value).map(Bytes::from)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::DecodeError> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Bincode;
+
+impl Codec for Bincode {
+    type EncodeError = bincode::Error;
+    type DecodeError = bincode::Error;
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Bytes, Self::EncodeError> {
+        bincode::serialize(value).map(Bytes::from)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::DecodeError> {
+        bincode::deserialize(bytes)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    type EncodeError = rmp_serde::encode::Error;
+    type DecodeError = rmp_serde::decode::Error;
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Bytes, Self::EncodeError> {
+        rmp_serde::to_vec(value).map(Bytes::from)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::DecodeError> {
+        rmp_serde::from_slice(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Request {
+        id: u32,
+        name: String,
+        tags: Vec<String>,
+    }
+
+    macro_rules! assert_roundtrips {
+        ( $codec:expr ) => {{
+            let codec = $codec;
+            let request = Request {
+                id: 42,
+                name: "hello".to_owned(),
+                tags: vec!["a".to_owned(), "b".to_owned()],
+            };
+
+            let bytes = codec.encode(&request).expect("encode");
+            assert_ok_eq!(codec.decode::<Request>(&bytes), request);
+        }};
+    }
+
+    #[test]
+    fn json_roundtrips() {
+        assert_roundtrips!(Json);
+    }
+
+    #[test]
+    fn bincode_roundtrips() {
+        assert_roundtrips!(Bincode);
+    }
+
+    #[test]
+    fn message_pack_roundtrips() {
+        assert_roundtrips!(MessagePack);
+    }
+
+    #[test]
+    fn json_encodes_text() {
+        assert_ok_eq!(Json.encode(&[1, 2, 3]), Bytes::from(&b"[1,2,3]"[..]));
+    }
+
+    #[test]
+    fn decode_fails_on_invalid_data() {
+        assert_err!(Json.decode::<Request>(b"{\"id\":"));
+        assert_err!(Bincode.decode::<Request>(b"\x01"));
+        assert_err!(MessagePack.decode::<Request>(b"\xc1"));
+    }
+}
@@ -0,0 +1,234 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+use std::{error, fmt};
+
+use crate::{Endpoint, Envelope, Group, Message, Route, ToEndpoint};
+
+mod codec;
+
+pub use codec::{Bincode, Codec, Json, MessagePack};
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Socket(crate::Error),
+    Encode(BoxError),
+    Decode { route: Route, cause: BoxError },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Socket(err) => write!(f, "socket error: {}", err),
+            Self::Encode(err) => write!(f, "failed to encode message: {}", err),
+            Self::Decode { route, cause } => {
+                write!(f, "failed to decode message from {}: {}", route, cause)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Socket(err) => Some(err),
+            Self::Encode(err) => Some(err.as_ref()),
+            Self::Decode { cause, .. } => Some(cause.as_ref()),
+        }
+    }
+}
+
+impl From<crate::Error> for Error {
+    fn from(cause: crate::Error) -> Self {
+        Self::Socket(cause)
+    }
+}
+
+fn encode<C: Codec, T: Serialize>(codec: &C, value: &T) -> Result<bytes::Bytes, Error> {
+    codec.encode(value).map_err(|err| Error::Encode(err.into()))
+}
+
+fn decode<C: Codec, T: DeserializeOwned>(
+    codec: &C,
+    envelope: Envelope<Message>,
+) -> Result<Envelope<T>, Error> {
+    match codec.decode(envelope.as_bytes()) {
+        Ok(message) => Ok(envelope.map(|_| message)),
+        Err(err) => Err(Error::Decode {
+            route: envelope.route,
+            cause: err.into(),
+        }),
+    }
+}
+
+macro_rules! define_typed {
+    ($name:ident, $socket:ident, <$($param:ident),+>) => {
+        pub struct $name<$($param,)+ C = Json> {
+            inner: crate::$socket,
+            codec: C,
+            marker: PhantomData<fn() -> ($($param,)+)>,
+        }
+
+        impl<$($param,)+ C: Codec> $name<$($param,)+ C> {
+            pub fn with_codec(inner: crate::$socket, codec: C) -> Self {
+                Self {
+                    inner,
+                    codec,
+                    marker: PhantomData,
+                }
+            }
+
+            pub fn into_inner(self) -> crate::$socket {
+                self.inner
+            }
+
+            pub async fn listen(&self, addr: impl ToEndpoint) -> Result<Endpoint, Error> {
+                Ok(self.inner.listen(addr).await?)
+            }
+
+            pub async fn connect(&self, addr: impl ToEndpoint) -> Result<Route, Error> {
+                Ok(self.inner.connect(addr).await?)
+            }
+        }
+
+        impl<$($param,)+ C: Codec> From<crate::$socket> for $name<$($param,)+ C> {
+            fn from(inner: crate::$socket) -> Self {
+                Self::with_codec(inner, Default::default())
+            }
+        }
+
+        impl<$($param,)+ C: Codec> Default for $name<$($param,)+ C> {
+            fn default() -> Self {
+                crate::$socket::default().into()
+            }
+        }
+
+        impl<$($param,)+ C: Codec> fmt::Debug for $name<$($param,)+ C> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("inner", &self.inner)
+                    .field("codec", &self.codec)
+                    .finish()
+            }
+        }
+    };
+}
+
+define_typed!(TypedClient, Client, <Req, Resp>);
+
+impl<Req: Serialize, Resp: DeserializeOwned, C: Codec> TypedClient<Req, Resp, C> {
+    pub async fn send(&self, message: &Req) -> Result<(), Error> {
+        Ok(self.inner.send(encode(&self.codec, message)?).await?)
+    }
+
+    pub async fn recv(&self) -> Result<Envelope<Resp>, Error> {
+        decode(&self.codec, self.inner.recv().await?)
+    }
+}
+
+define_typed!(TypedServer, Server, <Req, Resp>);
+
+impl<Req: DeserializeOwned, Resp: Serialize, C: Codec> TypedServer<Req, Resp, C> {
+    pub async fn recv(&self) -> Result<Envelope<Req>, Error> {
+        decode(&self.codec, self.inner.recv().await?)
+    }
+
+    pub async fn route(&self, message: &Resp, route: Route) -> Result<(), Error> {
+        Ok(self.inner.route(encode(&self.codec, message)?, route).await?)
+    }
+}
+
+define_typed!(TypedScatter, Scatter, <T>);
+
+impl<T: Serialize, C: Codec> TypedScatter<T, C> {
+    pub async fn send(&self, message: &T) -> Result<(), Error> {
+        Ok(self.inner.send(encode(&self.codec, message)?).await?)
+    }
+}
+
+define_typed!(TypedGather, Gather, <T>);
+
+impl<T: DeserializeOwned, C: Codec> TypedGather<T, C> {
+    pub async fn recv(&self) -> Result<Envelope<T>, Error> {
+        decode(&self.codec, self.inner.recv().await?)
+    }
+}
+
+define_typed!(TypedRadio, Radio, <T>);
+
+impl<T: Serialize, C: Codec> TypedRadio<T, C> {
+    pub fn broadcast(&self, message: &T, group: Group) -> Result<(), Error> {
+        Ok(self.inner.broadcast(encode(&self.codec, message)?, group)?)
+    }
+}
+
+define_typed!(TypedDish, Dish, <T>);
+
+impl<T: DeserializeOwned, C: Codec> TypedDish<T, C> {
+    pub async fn recv(&self) -> Result<Envelope<T>, Error> {
+        decode(&self.codec, self.inner.recv().await?)
+    }
+
+    pub fn join(&self, group: Group) {
+        self.inner.join(group)
+    }
+
+    pub fn leave(&self, group: Group) {
+        self.inner.leave(group)
+    }
+}
+
+define_typed!(TypedPeer, Peer, <T>);
+
+impl<T: Serialize + DeserializeOwned, C: Codec> TypedPeer<T, C> {
+    pub async fn recv(&self) -> Result<Envelope<T>, Error> {
+        decode(&self.codec, self.inner.recv().await?)
+    }
+
+    pub async fn route(&self, message: &T, route: Route) -> Result<(), Error> {
+        Ok(self.inner.route(encode(&self.codec, message)?, route).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::*;
+
+    #[test]
+    fn decode_reports_route_of_invalid_message() {
+        let envelope = Envelope {
+            info: Default::default(),
+            route: Route { id: 7 },
+            message: Message {
+                payload: (&b"{\"id\":"[..]).into(),
+                ..Default::default()
+            },
+            received: 0,
+        };
+
+        match decode::<_, u32>(&Json, envelope) {
+            Err(Error::Decode { route, .. }) => assert_eq!(route, Route { id: 7 }),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_returns_typed_envelope() {
+        let envelope = Envelope {
+            info: Default::default(),
+            route: Route { id: 7 },
+            message: Message {
+                payload: (&b"[1,2]"[..]).into(),
+                ..Default::default()
+            },
+            received: 0,
+        };
+
+        let decoded = assert_ok!(decode::<_, Vec<u32>>(&Json, envelope));
+        assert_eq!(decoded.route, Route { id: 7 });
+        assert_eq!(decoded.message, vec![1, 2]);
+    }
+}
@@ -0,0 +1,743 @@
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::{self, SocketAddr};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::{mem, thread};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+const ENTRIES: u32 = 1024;
+
+// Registered buffers shared by all sockets. Operations fall back to heap
+// buffers when the pool is exhausted or could not be registered.
+const BUFFERS: usize = 64;
+pub(crate) const BUFFER_SIZE: usize = 64 * 1024;
+
+const WAKEUP: u64 = u64::MAX;
+
+lazy_static::lazy_static! {
+    static ref DRIVER: Option<Arc<Driver>> = Driver::start()
+        .map_err(|err| {
+            debug!("uring", "io_uring unavailable, using epoll: {}", err);
+        })
+        .ok();
+}
+
+// Returns the shared driver, or none if io_uring is unavailable.
+pub(crate) fn driver() -> Option<&'static Arc<Driver>> {
+    DRIVER.as_ref()
+}
+
+pub(crate) enum Buffer {
+    Fixed(u16),
+    Heap(Box<[u8]>),
+}
+
+// Keeps resources alive until the kernel is done with them.
+enum Hold {
+    None,
+    Socket(Arc<net::TcpStream>),
+    Listener(Arc<net::TcpListener>),
+    Connect(Arc<net::TcpStream>, Box<SockAddr>),
+}
+
+struct Op {
+    waker: Option<Waker>,
+    results: VecDeque<i32>,
+    buffer: Option<Buffer>,
+    hold: Hold,
+    multishot: bool,
+    done: bool,
+    abandoned: bool,
+}
+
+#[derive(Default)]
+struct State {
+    queue: Vec<squeue::Entry>,
+    ops: HashMap<u64, Op>,
+    next: u64,
+}
+
+pub(crate) struct Driver {
+    state: Mutex<State>,
+    parked: AtomicBool,
+    wakeup: File,
+    pool: Pool,
+}
+
+struct Pool {
+    base: *mut u8,
+    free: Mutex<Vec<u16>>,
+}
+
+// The pool memory is only accessed through buffers owned by a single socket or
+// by the kernel.
+unsafe impl Send for Pool {}
+unsafe impl Sync for Pool {}
+
+impl Driver {
+    fn start() -> io::Result<Arc<Driver>> {
+        let ring = IoUring::new(ENTRIES)?;
+
+        let wakeup = unsafe {
+            let fd = libc::eventfd(0, libc::EFD_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            File::from_raw_fd(fd)
+        };
+
+        // The pool lives as long as the process, like the driver thread.
+        let base = Box::leak(vec![0u8; BUFFERS * BUFFER_SIZE].into_boxed_slice()).as_mut_ptr();
+        let iovecs: Vec<libc::iovec> = (0..BUFFERS)
+            .map(|i| libc::iovec {
+                iov_base: unsafe { base.add(i * BUFFER_SIZE) } as *mut _,
+                iov_len: BUFFER_SIZE,
+            })
+            .collect();
+
+        let free = match unsafe { ring.submitter().register_buffers(&iovecs) } {
+            Ok(()) => (0..BUFFERS as u16).rev().collect(),
+            Err(err) => {
+                debug!("uring", "registering buffers failed: {}", err);
+                Vec::new()
+            }
+        };
+
+        let driver = Arc::new(Driver {
+            state: Default::default(),
+            parked: AtomicBool::new(false),
+            wakeup,
+            pool: Pool {
+                base,
+                free: Mutex::new(free),
+            },
+        });
+
+        let shared = driver.clone();
+        thread::Builder::new()
+            .name("rmq-uring".into())
+            .spawn(move || shared.run(ring))?;
+
+        Ok(driver)
+    }
+
+    fn run(&self, mut ring: IoUring) {
+        let mut counter = [0u8; 8];
+        let read_wakeup = |counter: &mut [u8; 8]| {
+            opcode::Read::new(types::Fd(self.wakeup.as_raw_fd()), counter.as_mut_ptr(), 8)
+                .build()
+                .user_data(WAKEUP)
+        };
+
+        let mut pending = vec![read_wakeup(&mut counter)];
+        let mut wakers = Vec::new();
+
+        loop {
+            // Push everything queued since the last round and submit it all
+            // with a single system call.
+            pending.append(&mut self.state.lock().queue);
+            self.parked.store(true, Ordering::SeqCst);
+            if !self.state.lock().queue.is_empty() {
+                self.parked.store(false, Ordering::SeqCst);
+            }
+
+            for entry in pending.drain(..) {
+                while unsafe { ring.submission().push(&entry) }.is_err() {
+                    let _ = ring.submit();
+                }
+            }
+
+            let wait = self.parked.load(Ordering::SeqCst);
+            loop {
+                let result = if wait {
+                    ring.submit_and_wait(1)
+                } else {
+                    ring.submit()
+                };
+
+                match result {
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    // Too many completions outstanding; reap them first.
+                    Err(err) if err.raw_os_error() == Some(libc::EBUSY) => break,
+                    Err(err) => panic!("io_uring submit failed: {}", err),
+                    Ok(_) => break,
+                }
+            }
+
+            self.parked.store(false, Ordering::SeqCst);
+
+            let mut state = self.state.lock();
+            for cqe in ring.completion() {
+                if cqe.user_data() == WAKEUP {
+                    pending.push(read_wakeup(&mut counter));
+                    continue;
+                }
+
+                self.complete(&mut state, cqe, &mut wakers);
+            }
+
+            drop(state);
+            for waker in wakers.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+
+    fn complete(&self, state: &mut State, cqe: cqueue::Entry, wakers: &mut Vec<Waker>) {
+        let id = cqe.user_data();
+        let op = match state.ops.get_mut(&id) {
+            Some(op) => op,
+            None => return,
+        };
+
+        let done = !op.multishot || !cqueue::more(cqe.flags());
+        op.done = done;
+
+        if op.abandoned {
+            // Close sockets accepted after the listener was dropped.
+            if op.multishot && cqe.result() >= 0 {
+                drop(unsafe { net::TcpStream::from_raw_fd(cqe.result()) });
+            }
+
+            if done {
+                if let Some(op) = state.ops.remove(&id) {
+                    if let Some(buffer) = op.buffer {
+                        self.release(buffer);
+                    }
+                }
+            }
+            return;
+        }
+
+        op.results.push_back(cqe.result());
+        if let Some(waker) = op.waker.take() {
+            wakers.push(waker);
+        }
+    }
+
+    fn submit(&self, entry: squeue::Entry, buffer: Option<Buffer>, hold: Hold) -> u64 {
+        self.submit_op(entry, buffer, hold, false)
+    }
+
+    fn submit_op(
+        &self,
+        entry: squeue::Entry,
+        buffer: Option<Buffer>,
+        hold: Hold,
+        multishot: bool,
+    ) -> u64 {
+        let mut state = self.state.lock();
+        let id = state.next;
+        state.next += 1;
+        state.ops.insert(
+            id,
+            Op {
+                waker: None,
+                results: VecDeque::new(),
+                buffer,
+                hold,
+                multishot,
+                done: false,
+                abandoned: false,
+            },
+        );
+
+        state.queue.push(entry.user_data(id));
+        drop(state);
+
+        if self.parked.swap(false, Ordering::SeqCst) {
+            let _ = (&self.wakeup).write(&1u64.to_ne_bytes());
+        }
+
+        id
+    }
+
+    // Returns the next result of an operation. The operation is forgotten after
+    // its last result, which also returns its buffer.
+    fn poll(&self, id: u64, cx: &mut Context<'_>) -> Poll<Completion> {
+        let mut state = self.state.lock();
+        let op = state.ops.get_mut(&id).expect("unknown operation");
+        match op.results.pop_front() {
+            Some(result) => {
+                if op.done && op.results.is_empty() {
+                    let op = state.ops.remove(&id).expect("unknown operation");
+                    Poll::Ready(Completion {
+                        result,
+                        buffer: op.buffer,
+                        last: true,
+                    })
+                } else {
+                    Poll::Ready(Completion {
+                        result,
+                        buffer: None,
+                        last: false,
+                    })
+                }
+            }
+            None => {
+                op.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    // Forgets an operation that is still in flight; its resources are released
+    // when it completes.
+    fn abandon(&self, id: u64) {
+        let mut state = self.state.lock();
+        let op = state.ops.get_mut(&id).expect("unknown operation");
+        if op.done {
+            for &result in &op.results {
+                if op.multishot && result >= 0 {
+                    drop(unsafe { net::TcpStream::from_raw_fd(result) });
+                }
+            }
+
+            if let Some(op) = state.ops.remove(&id) {
+                if let Some(buffer) = op.buffer {
+                    self.release(buffer);
+                }
+            }
+        } else {
+            op.abandoned = true;
+            op.waker = None;
+            if op.multishot {
+                drop(state);
+                let cancel = self.submit(opcode::AsyncCancel::new(id).build(), None, Hold::None);
+                self.abandon(cancel);
+            }
+        }
+    }
+
+    fn acquire(&self) -> Buffer {
+        match self.pool.free.lock().pop() {
+            Some(index) => Buffer::Fixed(index),
+            None => Buffer::Heap(vec![0; BUFFER_SIZE].into_boxed_slice()),
+        }
+    }
+
+    fn release(&self, buffer: Buffer) {
+        if let Buffer::Fixed(index) = buffer {
+            self.pool.free.lock().push(index);
+        }
+    }
+
+    fn bytes<'a>(&self, buffer: &'a mut Buffer) -> &'a mut [u8] {
+        match buffer {
+            Buffer::Fixed(index) => unsafe {
+                let ptr = self.pool.base.add(*index as usize * BUFFER_SIZE);
+                std::slice::from_raw_parts_mut(ptr, BUFFER_SIZE)
+            },
+            Buffer::Heap(bytes) => bytes,
+        }
+    }
+
+    fn read(&self, socket: &Arc<net::TcpStream>, mut buffer: Buffer) -> u64 {
+        let fd = types::Fd(socket.as_raw_fd());
+        let ptr = self.bytes(&mut buffer).as_mut_ptr();
+        let entry = match buffer {
+            Buffer::Fixed(index) => {
+                opcode::ReadFixed::new(fd, ptr, BUFFER_SIZE as u32, index).build()
+            }
+            Buffer::Heap(_) => opcode::Recv::new(fd, ptr, BUFFER_SIZE as u32).build(),
+        };
+
+        self.submit(entry, Some(buffer), Hold::Socket(socket.clone()))
+    }
+
+    fn write(
+        &self,
+        socket: &Arc<net::TcpStream>,
+        mut buffer: Buffer,
+        start: usize,
+        end: usize,
+    ) -> u64 {
+        let fd = types::Fd(socket.as_raw_fd());
+        let ptr = self.bytes(&mut buffer)[start..end].as_ptr();
+        let len = (end - start) as u32;
+        let entry = match buffer {
+            Buffer::Fixed(index) => opcode::WriteFixed::new(fd, ptr, len, index).build(),
+            Buffer::Heap(_) => opcode::Send::new(fd, ptr, len)
+                .flags(libc::MSG_NOSIGNAL)
+                .build(),
+        };
+
+        self.submit(entry, Some(buffer), Hold::Socket(socket.clone()))
+    }
+}
+
+struct Completion {
+    result: i32,
+    buffer: Option<Buffer>,
+    last: bool,
+}
+
+fn result(result: i32) -> io::Result<usize> {
+    if result < 0 {
+        Err(io::Error::from_raw_os_error(-result))
+    } else {
+        Ok(result as usize)
+    }
+}
+
+pub(crate) struct UringListener {
+    driver: Arc<Driver>,
+    listener: Arc<net::TcpListener>,
+    accept: Option<u64>,
+}
+
+impl UringListener {
+    pub(crate) fn bind(driver: &Arc<Driver>, addr: SocketAddr) -> io::Result<UringListener> {
+        Ok(UringListener {
+            driver: driver.clone(),
+            listener: Arc::new(net::TcpListener::bind(addr)?),
+            accept: None,
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // A single multishot accept delivers all incoming connections.
+    pub(crate) async fn accept(&mut self) -> io::Result<(UringStream, SocketAddr)> {
+        futures::future::poll_fn(|cx| loop {
+            let id = match self.accept {
+                Some(id) => id,
+                None => {
+                    let fd = types::Fd(self.listener.as_raw_fd());
+                    let entry = opcode::AcceptMulti::new(fd)
+                        .flags(libc::SOCK_CLOEXEC)
+                        .build();
+                    let hold = Hold::Listener(self.listener.clone());
+                    *self
+                        .accept
+                        .insert(self.driver.submit_op(entry, None, hold, true))
+                }
+            };
+
+            // The kernel may end a multishot accept, in which case it is
+            // submitted again.
+            let completion = futures::ready!(self.driver.poll(id, cx));
+            if completion.last {
+                self.accept = None;
+            }
+
+            let fd = result(completion.result)? as RawFd;
+
+            let socket = unsafe { net::TcpStream::from_raw_fd(fd) };
+            match socket.peer_addr() {
+                Ok(addr) => {
+                    return Poll::Ready(UringStream::new(&self.driver, socket).map(|s| (s, addr)))
+                }
+                Err(_) => continue,
+            }
+        })
+        .await
+    }
+}
+
+impl Drop for UringListener {
+    fn drop(&mut self) {
+        if let Some(id) = self.accept.take() {
+            self.driver.abandon(id);
+        }
+    }
+}
+
+#[repr(C)]
+union SockAddr {
+    v4: libc::sockaddr_in,
+    v6: libc::sockaddr_in6,
+}
+
+fn sockaddr(addr: &SocketAddr) -> (Box<SockAddr>, libc::socklen_t) {
+    match addr {
+        SocketAddr::V4(addr) => {
+            let mut sin: libc::sockaddr_in = zeroed();
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            (
+                Box::new(SockAddr { v4: sin }),
+                mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        }
+        SocketAddr::V6(addr) => {
+            let mut sin6: libc::sockaddr_in6 = zeroed();
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_scope_id = addr.scope_id();
+            (
+                Box::new(SockAddr { v6: sin6 }),
+                mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+            )
+        }
+    }
+}
+
+fn zeroed<T>() -> T {
+    // Only used for plain C socket address structs.
+    unsafe { mem::zeroed() }
+}
+
+pub(crate) async fn connect(driver: &Arc<Driver>, addr: SocketAddr) -> io::Result<UringStream> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+
+    let socket = unsafe {
+        let fd = libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Arc::new(net::TcpStream::from_raw_fd(fd))
+    };
+
+    let (sockaddr, len) = sockaddr(&addr);
+    let ptr = &*sockaddr as *const SockAddr as *const libc::sockaddr;
+    let entry = opcode::Connect::new(types::Fd(socket.as_raw_fd()), ptr, len).build();
+    let id = driver.submit(entry, None, Hold::Connect(socket.clone(), sockaddr));
+
+    let mut connect = Connect {
+        driver,
+        id: Some(id),
+    };
+    let completion = futures::future::poll_fn(|cx| connect.poll(cx)).await;
+    result(completion.result)?;
+
+    let socket = Arc::try_unwrap(socket).expect("socket released by connect");
+    UringStream::new(driver, socket)
+}
+
+struct Connect<'a> {
+    driver: &'a Arc<Driver>,
+    id: Option<u64>,
+}
+
+impl Connect<'_> {
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Completion> {
+        let id = self.id.expect("connect polled after completion");
+        let ready = futures::ready!(self.driver.poll(id, cx));
+        self.id = None;
+        Poll::Ready(ready)
+    }
+}
+
+impl Drop for Connect<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.driver.abandon(id);
+        }
+    }
+}
+
+enum Reading {
+    Idle,
+    Pending(u64),
+    Ready(Buffer, usize, usize),
+    Closed,
+}
+
+enum Writing {
+    Idle,
+    Filling(Buffer, usize),
+    Pending(u64, usize, usize),
+}
+
+// A TCP stream whose reads and writes are submitted to the shared ring.
+pub(crate) struct UringStream {
+    driver: Arc<Driver>,
+    socket: Arc<net::TcpStream>,
+    reading: Reading,
+    writing: Writing,
+}
+
+impl UringStream {
+    fn new(driver: &Arc<Driver>, socket: net::TcpStream) -> io::Result<UringStream> {
+        socket.set_nodelay(true)?;
+        Ok(UringStream {
+            driver: driver.clone(),
+            socket: Arc::new(socket),
+            reading: Reading::Idle,
+            writing: Writing::Idle,
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match mem::replace(&mut self.writing, Writing::Idle) {
+                Writing::Pending(id, start, end) => {
+                    let completion = match self.driver.poll(id, cx) {
+                        Poll::Ready(ready) => ready,
+                        Poll::Pending => {
+                            self.writing = Writing::Pending(id, start, end);
+                            return Poll::Pending;
+                        }
+                    };
+
+                    let buffer = completion.buffer.expect("write returns buffer");
+                    let written = match result(completion.result) {
+                        Ok(0) => Err(io::ErrorKind::WriteZero.into()),
+                        Ok(written) => Ok(written),
+                        Err(err) => Err(err),
+                    };
+
+                    match written {
+                        Ok(written) if start + written < end => {
+                            let id = self
+                                .driver
+                                .write(&self.socket, buffer, start + written, end);
+                            self.writing = Writing::Pending(id, start + written, end);
+                        }
+                        Ok(_) => {
+                            self.writing = Writing::Filling(buffer, 0);
+                            return Poll::Ready(Ok(()));
+                        }
+                        Err(err) => {
+                            self.driver.release(buffer);
+                            return Poll::Ready(Err(err));
+                        }
+                    }
+                }
+                Writing::Filling(buffer, len) if len > 0 => {
+                    let id = self.driver.write(&self.socket, buffer, 0, len);
+                    self.writing = Writing::Pending(id, 0, len);
+                }
+                writing => {
+                    self.writing = writing;
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for UringStream {
+    fn drop(&mut self) {
+        // Operations still in flight hold on to the socket; a shutdown makes
+        // them complete so that it can be closed.
+        let _ = self.socket.shutdown(net::Shutdown::Both);
+
+        match mem::replace(&mut self.reading, Reading::Closed) {
+            Reading::Pending(id) => self.driver.abandon(id),
+            Reading::Ready(buffer, _, _) => self.driver.release(buffer),
+            _ => {}
+        }
+
+        match mem::replace(&mut self.writing, Writing::Idle) {
+            Writing::Pending(id, _, _) => self.driver.abandon(id),
+            Writing::Filling(buffer, _) => self.driver.release(buffer),
+            _ => {}
+        }
+    }
+}
+
+impl AsyncRead for UringStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        loop {
+            match mem::replace(&mut this.reading, Reading::Idle) {
+                Reading::Idle => {
+                    let buffer = this.driver.acquire();
+                    this.reading = Reading::Pending(this.driver.read(&this.socket, buffer));
+                }
+                Reading::Pending(id) => match this.driver.poll(id, cx) {
+                    Poll::Ready(completion) => {
+                        let buffer = completion.buffer.expect("read returns buffer");
+                        match result(completion.result) {
+                            Ok(0) => {
+                                this.driver.release(buffer);
+                                this.reading = Reading::Closed;
+                            }
+                            Ok(len) => this.reading = Reading::Ready(buffer, 0, len),
+                            Err(err) => {
+                                this.driver.release(buffer);
+                                return Poll::Ready(Err(err));
+                            }
+                        }
+                    }
+                    Poll::Pending => {
+                        this.reading = Reading::Pending(id);
+                        return Poll::Pending;
+                    }
+                },
+                Reading::Ready(mut buffer, start, end) => {
+                    let len = buf.len().min(end - start);
+                    buf[..len].copy_from_slice(&this.driver.bytes(&mut buffer)[start..start + len]);
+                    if start + len < end {
+                        this.reading = Reading::Ready(buffer, start + len, end);
+                    } else {
+                        // Keep the next read in flight while the data is
+                        // processed.
+                        this.reading = Reading::Pending(this.driver.read(&this.socket, buffer));
+                    }
+                    return Poll::Ready(Ok(len));
+                }
+                Reading::Closed => {
+                    this.reading = Reading::Closed;
+                    return Poll::Ready(Ok(0));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for UringStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        loop {
+            match mem::replace(&mut this.writing, Writing::Idle) {
+                Writing::Idle => this.writing = Writing::Filling(this.driver.acquire(), 0),
+                Writing::Filling(mut buffer, len) if len < BUFFER_SIZE => {
+                    let count = buf.len().min(BUFFER_SIZE - len);
+                    this.driver.bytes(&mut buffer)[len..len + count].copy_from_slice(&buf[..count]);
+                    this.writing = Writing::Filling(buffer, len + count);
+                    return Poll::Ready(Ok(count));
+                }
+                writing => {
+                    this.writing = writing;
+                    futures::ready!(this.poll_write_buffer(cx))?;
+                }
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        futures::ready!(this.poll_write_buffer(cx))?;
+
+        // Give the buffer back so idle sockets do not hold on to the pool.
+        if let Writing::Filling(buffer, _) = mem::replace(&mut this.writing, Writing::Idle) {
+            this.driver.release(buffer);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(self.as_mut().poll_flush(cx))?;
+        Poll::Ready(self.socket.shutdown(net::Shutdown::Write))
+    }
+}
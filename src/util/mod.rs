@@ -1,5 +1,7 @@
 mod exchange;
 mod sequence;
+mod span;
 
 pub(crate) use exchange::Exchange;
 pub(crate) use sequence::Sequence;
+pub(crate) use span::{instrument, record, Span};
@@ -1,4 +1,5 @@
-use crate::sync::atomic::{AtomicU32, Ordering};
+// Sequences are kept in statics, which must not hold loom atomics.
+use std::sync::atomic::{AtomicU32, Ordering};
 
 #[derive(Debug)]
 pub(crate) struct Sequence {
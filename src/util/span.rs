@@ -0,0 +1,31 @@
+use futures::Future;
+use std::fmt;
+
+#[cfg(feature = "tracing")]
+pub(crate) type Span = tracing::Span;
+
+// Placeholder that keeps the fields and signatures the same without tracing.
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Span;
+
+// Enters the span whenever the future is polled. Needed for spawned tasks,
+// which do not inherit the span of the task that spawned them.
+#[cfg(feature = "tracing")]
+pub(crate) fn instrument<F: Future>(future: F, span: Span) -> impl Future<Output = F::Output> {
+    tracing_futures::Instrument::instrument(future, span)
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn instrument<F: Future>(future: F, _span: Span) -> impl Future<Output = F::Output> {
+    future
+}
+
+// Records a field of the current span that was declared as empty.
+#[cfg(feature = "tracing")]
+pub(crate) fn record(field: &str, value: impl fmt::Display) {
+    tracing::Span::current().record(field, &tracing::field::display(value));
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record(_field: &str, _value: impl fmt::Display) {}
@@ -1,44 +1,92 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::{io, str};
+use std::{fmt, io, str};
 
 use bytes::{Buf, BytesMut};
 use tokio_util::codec::Decoder;
 
 use super::frame::{tag, Frame, Security, SmallBuf, SocketType};
-use super::Zmtp;
+use super::{Zmtp, MAX_MESSAGE_SIZE};
 
 #[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
     Io,
     InvalidData,
     InvalidFrameSize,
+    MessageTooLarge,
     UnknownCommand,
     UnknownMechanism,
     UnknownSocketType,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io => write!(f, "io error"),
+            Self::InvalidData => write!(f, "invalid frame"),
+            Self::InvalidFrameSize => write!(f, "frame too large"),
+            Self::MessageTooLarge => write!(f, "message exceeds maximum size"),
+            Self::UnknownCommand => write!(f, "unknown command"),
+            Self::UnknownMechanism => write!(f, "unknown security mechanism"),
+            Self::UnknownSocketType => write!(f, "unknown socket type"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 macro_rules! ensure_capacity(
-    { $buf:expr, $value:expr } => {
+    { $self:expr, $buf:expr, $value:expr } => {
         {
             if $buf.len() < $value {
-                $buf.reserve($value + 10 - $buf.len());
+                $self.reserve($buf, $value);
                 return Ok(None);
             }
         }
     };
 );
 
+impl Zmtp {
+    // Makes room for a frame of the given size before reading more. A frame
+    // that does not fit is moved to a buffer of its full size, so the rest of
+    // it is read in place and split off without copying. Only the part read so
+    // far is copied; earlier frames keep the old buffer.
+    fn reserve(&self, buffer: &mut BytesMut, len: usize) {
+        let len = len.max(self.read_buffer_size);
+        if buffer.capacity() < len {
+            let mut sized = BytesMut::with_capacity(len);
+            sized.extend_from_slice(buffer);
+            *buffer = sized;
+        }
+    }
+}
+
+impl Zmtp {
+    // Messages are limited by the options, commands only by the default.
+    fn check_size(&self, marker: u8, len: usize) -> Result<(), Error> {
+        if marker & 0x04 == 0 {
+            if len > self.max_message_size {
+                return Err(Error::MessageTooLarge);
+            }
+        } else if len > MAX_MESSAGE_SIZE {
+            return Err(Error::InvalidFrameSize);
+        }
+
+        Ok(())
+    }
+}
+
 impl Decoder for Zmtp {
     type Item = Frame;
     type Error = Error;
 
     fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Frame>, Self::Error> {
-        ensure_capacity!(buffer, 2);
+        ensure_capacity!(self, buffer, 2);
         let marker = buffer[0];
 
         if marker == 0xff {
-            ensure_capacity!(buffer, 64);
+            ensure_capacity!(self, buffer, 64);
             return Ok(Some(decode_greeting(buffer)?));
         }
 
@@ -51,18 +99,19 @@ impl Decoder for Zmtp {
         if marker & 0x02 == 0 {
             // Short message or command
             len = buffer[1] as usize;
-            ensure_capacity!(buffer, len + 2);
+            self.check_size(marker, len)?;
+
+            ensure_capacity!(self, buffer, len + 2);
             buffer.advance(2);
         } else {
             // Long message
-            ensure_capacity!(buffer, 9);
+            ensure_capacity!(self, buffer, 9);
             len = parse_u64(&buffer[1..9]) as usize;
 
-            if len > self.max_message_size {
-                return Err(Error::InvalidFrameSize);
-            }
+            // Checked before the buffer is sized for the frame.
+            self.check_size(marker, len)?;
 
-            ensure_capacity!(buffer, len + 9);
+            ensure_capacity!(self, buffer, len + 9);
             buffer.advance(9);
         }
 
@@ -176,15 +225,12 @@ fn decode_ready(buffer: &mut BytesMut) -> Result<Frame, Error> {
             return Err(Error::InvalidData);
         }
 
+        let val = buffer.split_to(val_len);
         if key == tag::SOCKET_TYPE {
-            socket_type =
-                Some(SocketType::from_bytes(&buffer[..val_len]).ok_or(Error::UnknownSocketType)?)
+            socket_type = Some(SocketType::from_bytes(&val).ok_or(Error::UnknownSocketType)?)
         } else {
-            let val = buffer.split_to(val_len).freeze();
             properties.insert(key.to_owned().into(), val.to_vec());
         }
-
-        buffer.advance(val_len);
     }
 
     match socket_type {
@@ -1,14 +1,59 @@
-use std::io;
+use std::{fmt, io};
 
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use tokio_util::codec::Encoder;
 
-use super::frame::{tag, Frame};
+use super::frame::{tag, valid_property, Frame};
 use super::Zmtp;
 
 #[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
     Io,
+    InvalidProperty,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io => write!(f, "io error"),
+            Self::InvalidProperty => write!(f, "invalid property name or value"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Payloads from this size on are written from their own buffer instead of
+// being copied behind their header.
+pub(crate) const COPY_LIMIT: usize = 4096;
+
+impl Zmtp {
+    // Encodes a frame, except for a large message payload which is returned
+    // so that it can be written without copying.
+    pub(crate) fn encode_header(
+        &mut self,
+        frame: Frame,
+        buffer: &mut BytesMut,
+    ) -> Result<Option<Bytes>, Error> {
+        match frame {
+            Frame::Message { payload, more } if payload.len() >= COPY_LIMIT => {
+                put_message_header(buffer, payload.len(), more);
+                Ok(Some(payload))
+            }
+            frame => self.encode(frame, buffer).map(|()| None),
+        }
+    }
+}
+
+fn put_message_header(buffer: &mut BytesMut, len: usize, more: bool) {
+    if len > 255 {
+        buffer.put_u8(2 | more as u8);
+        buffer.put_u64(len as u64);
+    } else {
+        buffer.put_u8(more as u8);
+        buffer.put_u8(len as u8);
+    }
 }
 
 impl Encoder<Frame> for Zmtp {
@@ -26,15 +71,7 @@ impl Encoder<Frame> for Zmtp {
             }
 
             Frame::Message { payload, more } => {
-                if payload.len() > 255 {
-                    buffer.put_u8(2 | more as u8);
-                    buffer.put_u64(payload.len() as u64);
-                } else {
-                    buffer.put_u8(more as u8);
-                    buffer.put_u8(payload.len() as u8);
-                }
-
-                // TODO avoid copy by reusing same buffer.
+                put_message_header(buffer, payload.len(), more);
                 buffer.put_slice(&payload);
             }
 
@@ -52,6 +89,10 @@ impl Encoder<Frame> for Zmtp {
                 data.put_slice(socket_type.as_bytes());
 
                 for (key, val) in properties {
+                    if !valid_property(&key, &val) {
+                        return Err(Error::InvalidProperty);
+                    }
+
                     data.put_u8(key.len() as u8);
                     data.put(key.as_bytes());
                     data.put_u32(val.len() as u32);
@@ -227,6 +268,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rejects_invalid_property_names() {
+        for name in vec![String::new(), "X Space".into(), "X".repeat(256)] {
+            let mut properties = std::collections::HashMap::new();
+            properties.insert(name.into(), b"value".to_vec());
+
+            let frame = Frame::Ready {
+                socket_type: SocketType::SERVER,
+                properties,
+            };
+
+            let mut buffer = BytesMut::new();
+            assert_eq!(
+                Zmtp::default().encode(frame, &mut buffer),
+                Err(Error::InvalidProperty)
+            );
+        }
+    }
+
     // #[async_std::test]
     // async fn streams_ready() {
     //     let ready = Ready {
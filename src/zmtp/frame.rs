@@ -4,7 +4,8 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub(crate) enum Security {
+#[non_exhaustive]
+pub enum Security {
     Null,
     Plain,
     Curve,
@@ -17,7 +18,8 @@ impl Default for Security {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub(crate) enum SocketType {
+#[non_exhaustive]
+pub enum SocketType {
     // New socket types
     CLIENT,
     SERVER,
@@ -50,7 +52,8 @@ impl Default for SocketType {
 pub(crate) type SmallBuf = SmallVec<[u8; 16]>;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub(crate) enum Frame {
+#[non_exhaustive]
+pub enum Frame {
     Greeting {
         version: (u8, u8),
         security: Security,
@@ -67,37 +70,37 @@ pub(crate) enum Frame {
     },
 
     Error {
-        reason: SmallVec<[u8; 32]>, // max 255 bytes
+        reason: Vec<u8>, // max 255 bytes
     },
 
     Ping {
         ttl: u16,
-        context: SmallVec<[u8; 16]>, // max 16 bytes
+        context: Vec<u8>, // max 16 bytes
     },
 
     Pong {
-        context: SmallVec<[u8; 16]>, // max 16 bytes
+        context: Vec<u8>, // max 16 bytes
     },
 
     Subscribe {
-        group: SmallVec<[u8; 32]>, // any length
+        group: Vec<u8>, // any length
     },
 
     Cancel {
-        group: SmallVec<[u8; 32]>, // any length
+        group: Vec<u8>, // any length
     },
 
     Join {
-        group: SmallVec<[u8; 16]>, // max 255 bytes, but 15 in practice TODO: use nonzero u8
+        group: Vec<u8>, // max 255 bytes, but 15 in practice TODO: use nonzero u8
     },
 
     Leave {
-        group: SmallVec<[u8; 16]>, // max 255 bytes, but 15 in practice TODO: use nonzero u8
+        group: Vec<u8>, // max 255 bytes, but 15 in practice TODO: use nonzero u8
     },
 }
 
 impl Security {
-    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Security> {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Security> {
         match bytes {
             tag::NULL => Some(Security::Null),
             tag::PLAIN => Some(Security::Plain),
@@ -106,7 +109,7 @@ impl Security {
         }
     }
 
-    pub(crate) fn as_bytes(self) -> &'static [u8] {
+    pub fn as_bytes(self) -> &'static [u8] {
         match self {
             Security::Null => tag::NULL,
             Security::Plain => tag::PLAIN,
@@ -116,7 +119,7 @@ impl Security {
 }
 
 impl SocketType {
-    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<SocketType> {
+    pub fn from_bytes(bytes: &[u8]) -> Option<SocketType> {
         match bytes {
             tag::REQ => Some(SocketType::REQ),
             tag::REP => Some(SocketType::REP),
@@ -140,7 +143,7 @@ impl SocketType {
         }
     }
 
-    pub(crate) fn as_bytes(self) -> &'static [u8] {
+    pub fn as_bytes(self) -> &'static [u8] {
         match self {
             SocketType::REQ => tag::REQ,
             SocketType::REP => tag::REP,
@@ -164,6 +167,17 @@ impl SocketType {
     }
 }
 
+// Property names are limited to 255 bytes of alphanumerics and "-_.+", values
+// by the 32-bit length field.
+pub(crate) fn valid_property(name: &str, value: &[u8]) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"-_.+".contains(&c))
+        && value.len() <= u32::MAX as usize
+}
+
 pub(crate) mod tag {
     pub const SIGNATURE: &[u8] = b"\xff\0\0\0\0\0\0\0\0\x7f";
 
@@ -0,0 +1,380 @@
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{Sink, Stream};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::FramedRead;
+
+use super::{DecodeError, EncodeError, Frame, Zmtp};
+
+// Flush before accepting more frames once this much output is queued.
+const BACKPRESSURE: usize = 8 * 1024;
+
+// Frames a byte stream. Outgoing headers and small payloads are coalesced in a
+// single buffer; large payloads are queued as they are and written together
+// with the surrounding headers in one vectored write.
+//
+// This used to be an alias of tokio_util's Framed. It still reads like one and
+// offers the same accessors, but only implements Sink for Frame, and the read
+// and write buffers, parts and codec constructors are no longer exposed.
+pub struct Framed<T> {
+    inner: FramedRead<T, Zmtp>,
+    outgoing: Outgoing,
+}
+
+#[derive(Default)]
+struct Outgoing {
+    chunks: VecDeque<Bytes>,
+    buffer: BytesMut,
+    len: usize,
+}
+
+impl<T: AsyncRead> Framed<T> {
+    pub fn new(transport: T, codec: Zmtp) -> Self {
+        let capacity = codec.read_buffer_size;
+        Self {
+            inner: FramedRead::with_capacity(transport, codec, capacity),
+            outgoing: Default::default(),
+        }
+    }
+}
+
+impl<T> Framed<T> {
+    pub fn get_ref(&self) -> &T {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    // Discards output that has not been flushed, and input that has been read
+    // from the transport but not decoded yet.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    pub fn codec(&self) -> &Zmtp {
+        self.inner.decoder()
+    }
+
+    pub fn codec_mut(&mut self) -> &mut Zmtp {
+        self.inner.decoder_mut()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Framed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Framed")
+            .field("io", self.get_ref())
+            .field("codec", self.codec())
+            .field("outgoing", &self.outgoing.len)
+            .finish()
+    }
+}
+
+impl<T: AsyncRead + Unpin> Stream for Framed<T> {
+    type Item = Result<Frame, DecodeError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> Sink<Frame> for Framed<T> {
+    type Error = EncodeError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), EncodeError>> {
+        if self.outgoing.len >= BACKPRESSURE {
+            self.poll_flush(cx)
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Frame) -> Result<(), EncodeError> {
+        let this = &mut *self;
+        let outgoing = &mut this.outgoing;
+        let before = outgoing.buffer.len();
+        let payload = this
+            .inner
+            .decoder_mut()
+            .encode_header(frame, &mut outgoing.buffer)?;
+        outgoing.len += outgoing.buffer.len() - before;
+
+        if let Some(payload) = payload {
+            if !outgoing.buffer.is_empty() {
+                outgoing.chunks.push_back(outgoing.buffer.split().freeze());
+            }
+
+            outgoing.len += payload.len();
+            outgoing.chunks.push_back(payload);
+        }
+
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), EncodeError>> {
+        let this = &mut *self;
+        while this.outgoing.has_remaining() {
+            let transport = Pin::new(this.inner.get_mut());
+            let written = futures::ready!(transport.poll_write_buf(cx, &mut this.outgoing))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+            }
+        }
+
+        futures::ready!(Pin::new(this.inner.get_mut()).poll_flush(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), EncodeError>> {
+        futures::ready!(self.as_mut().poll_flush(cx))?;
+        futures::ready!(Pin::new(self.inner.get_mut()).poll_shutdown(cx))?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Queued chunks come before the bytes still in the buffer.
+impl Buf for Outgoing {
+    fn remaining(&self) -> usize {
+        self.len
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self.chunks.front() {
+            Some(chunk) => chunk,
+            None => &self.buffer,
+        }
+    }
+
+    fn bytes_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        let chunks = self.chunks.iter().map(|chunk| &chunk[..]);
+        let slices = chunks
+            .chain(Some(&self.buffer[..]))
+            .filter(|s| !s.is_empty());
+
+        let mut count = 0;
+        for (slice, dst) in slices.zip(dst.iter_mut()) {
+            *dst = IoSlice::new(slice);
+            count += 1;
+        }
+        count
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        self.len -= cnt;
+        while let Some(chunk) = self.chunks.front_mut() {
+            if cnt < chunk.len() {
+                chunk.advance(cnt);
+                return;
+            }
+
+            cnt -= chunk.len();
+            self.chunks.pop_front();
+        }
+
+        self.buffer.advance(cnt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::SinkExt;
+    use std::task::Poll;
+
+    // Records the writes made by the framed transport.
+    #[derive(Default)]
+    struct Recorder {
+        writes: Vec<Vec<u8>>,
+        addresses: Vec<usize>,
+    }
+
+    impl AsyncRead for Recorder {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    impl AsyncWrite for Recorder {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.writes.push(buf.to_vec());
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_write_buf<B: Buf>(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut B,
+        ) -> Poll<io::Result<usize>> {
+            let mut slices = [IoSlice::new(&[]); 64];
+            let count = buf.bytes_vectored(&mut slices);
+            let mut write = Vec::new();
+            for slice in &slices[..count] {
+                self.addresses.push(slice.as_ptr() as usize);
+                write.extend_from_slice(slice);
+            }
+
+            let len = write.len();
+            self.writes.push(write);
+            buf.advance(len);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    // Counts the writes made to the transport.
+    struct Counting<T> {
+        inner: T,
+        writes: usize,
+    }
+
+    impl<T: AsyncRead + Unpin> AsyncRead for Counting<T> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl<T: AsyncWrite + Unpin> AsyncWrite for Counting<T> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.writes += 1;
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_write_buf<B: Buf>(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut B,
+        ) -> Poll<io::Result<usize>> {
+            self.writes += 1;
+            Pin::new(&mut self.inner).poll_write_buf(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    fn message(payload: Bytes, more: bool) -> Frame {
+        Frame::Message { more, payload }
+    }
+
+    #[test]
+    fn writes_large_payloads_without_copying() {
+        let large = Bytes::from(vec![7; 100_000]);
+        let mut framed = Framed::new(Recorder::default(), Zmtp::default());
+
+        futures::executor::block_on(async {
+            framed
+                .feed(message(Bytes::from_static(b"hi"), true))
+                .await
+                .unwrap();
+            framed.feed(message(large.clone(), false)).await.unwrap();
+            framed.flush().await.unwrap();
+        });
+
+        let recorder = framed.get_ref();
+        assert_eq!(recorder.writes.len(), 1);
+        assert!(recorder.addresses.contains(&(large.as_ptr() as usize)));
+
+        let mut expected = b"\x01\x02hi\x02".to_vec();
+        expected.extend_from_slice(&100_000u64.to_be_bytes());
+        expected.extend_from_slice(&large);
+        assert_eq!(recorder.writes[0], expected);
+    }
+
+    #[test]
+    fn coalesces_small_messages() {
+        let mut framed = Framed::new(Recorder::default(), Zmtp::default());
+
+        futures::executor::block_on(async {
+            for _ in 0..10 {
+                framed
+                    .feed(message(Bytes::from_static(b"small"), false))
+                    .await
+                    .unwrap();
+            }
+            framed.flush().await.unwrap();
+        });
+
+        let recorder = framed.get_ref();
+        assert_eq!(recorder.writes, vec![b"\x00\x05small".repeat(10)]);
+        assert_eq!(recorder.addresses.len(), 1);
+    }
+
+    #[cfg(all(feature = "tcp", feature = "tokio"))]
+    #[tokio::test]
+    async fn tcp_writes_frames_in_one_vectored_write() {
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut peer, _) = listener.accept().await.unwrap();
+
+        let large = Bytes::from(vec![7; 8192]);
+        let transport = Counting {
+            inner: stream,
+            writes: 0,
+        };
+
+        let mut framed = Framed::new(transport, Zmtp::default());
+        framed
+            .feed(message(Bytes::from_static(b"hi"), true))
+            .await
+            .unwrap();
+        framed.feed(message(large.clone(), true)).await.unwrap();
+        framed
+            .feed(message(Bytes::from_static(b"bye"), false))
+            .await
+            .unwrap();
+        framed.flush().await.unwrap();
+        assert_eq!(framed.get_ref().writes, 1);
+
+        let mut expected = b"\x01\x02hi\x03".to_vec();
+        expected.extend_from_slice(&8192u64.to_be_bytes());
+        expected.extend_from_slice(&large);
+        expected.extend_from_slice(b"\x00\x03bye");
+
+        let mut received = vec![0; expected.len()];
+        let mut len = 0;
+        while len < received.len() {
+            let read =
+                |cx: &mut Context<'_>| Pin::new(&mut peer).poll_read(cx, &mut received[len..]);
+            let read = futures::future::poll_fn(read).await.unwrap();
+            assert_ne!(read, 0);
+            len += read;
+        }
+
+        assert_eq!(received, expected);
+    }
+}
@@ -1,6 +1,8 @@
 mod decode;
 mod encode;
 mod frame;
+mod framed;
+#[cfg(feature = "udp")]
 pub(crate) mod udp;
 
 use futures::{SinkExt, StreamExt};
@@ -8,45 +10,55 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use tokio_util::codec;
+use std::fmt;
 
-pub(crate) use frame::*;
-pub(crate) type Framed<T> = codec::Framed<T, Zmtp>;
+pub use decode::Error as DecodeError;
+pub use encode::Error as EncodeError;
+pub(crate) use frame::{tag, valid_property, SmallBuf};
+pub use frame::{Frame, Security, SocketType};
+pub use framed::Framed;
 
+// Also the default of the socket options.
+pub(crate) const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+// Implements the tokio-util Decoder and Encoder traits. Fields may be added in
+// later versions, so construct it with Default.
 #[derive(Debug)]
-pub(crate) struct Zmtp {
+#[non_exhaustive]
+pub struct Zmtp {
     pub max_message_size: usize,
+    pub read_buffer_size: usize,
 }
 
 impl Default for Zmtp {
     fn default() -> Self {
         Self {
-            #[cfg(test)]
-            max_message_size: 1 << 24,
-
-            #[cfg(not(test))]
-            max_message_size: 1 << 32,
+            max_message_size: MAX_MESSAGE_SIZE,
+            read_buffer_size: 8 * 1024,
         }
     }
 }
 
-#[derive(Debug, Default, Copy, Clone)]
-pub(crate) struct Params {
+#[derive(Debug, Default, Clone)]
+#[non_exhaustive]
+pub struct Params {
     pub socket_type: SocketType,
     pub security: Security,
+    pub properties: HashMap<Cow<'static, str>, Vec<u8>>,
 }
 
 #[derive(Default, Debug, Clone)]
-pub(crate) struct Info {
+#[non_exhaustive]
+pub struct Info {
     pub socket_type: SocketType,
     pub properties: HashMap<Cow<'static, str>, Vec<u8>>,
 }
 
-pub(crate) fn frame<T: AsyncRead + AsyncWrite + Unpin>(transport: T) -> Framed<T> {
+pub fn frame<T: AsyncRead + AsyncWrite + Unpin>(transport: T) -> Framed<T> {
     Framed::new(transport, Default::default())
 }
 
-pub(crate) async fn connect<T: AsyncRead + AsyncWrite + Unpin>(
+pub async fn connect<T: AsyncRead + AsyncWrite + Unpin>(
     transport: &mut Framed<T>,
     params: &Params,
 ) -> Result<Info, Error> {
@@ -67,7 +79,7 @@ pub(crate) async fn connect<T: AsyncRead + AsyncWrite + Unpin>(
     transport
         .send(Frame::Ready {
             socket_type: params.socket_type,
-            properties: Default::default(),
+            properties: params.properties.clone(),
         })
         .await?;
 
@@ -88,14 +100,29 @@ pub(crate) async fn connect<T: AsyncRead + AsyncWrite + Unpin>(
 }
 
 #[derive(Debug, PartialEq, Eq)]
-pub(crate) enum Error {
+#[non_exhaustive]
+pub enum Error {
     Disconnected,
     UnexpectedFrame,
     HandshakeFailed,
-    Encode(encode::Error),
-    Decode(decode::Error),
+    Encode(EncodeError),
+    Decode(DecodeError),
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disconnected => write!(f, "disconnected during handshake"),
+            Self::UnexpectedFrame => write!(f, "unexpected frame during handshake"),
+            Self::HandshakeFailed => write!(f, "handshake rejected by peer"),
+            Self::Encode(err) => write!(f, "{}", err),
+            Self::Decode(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<encode::Error> for Error {
     fn from(cause: encode::Error) -> Self {
         Self::Encode(cause)
@@ -112,7 +139,6 @@ impl From<decode::Error> for Error {
 mod tests {
     use super::*;
     use bytes::{Bytes, BytesMut};
-    use smallvec::smallvec;
     use tokio_util::codec::{Decoder, Encoder};
 
     macro_rules! assert_roundtrips {
@@ -185,6 +211,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn roundtrips_ready_with_properties() {
+        assert_roundtrips!(
+            b"\x04\x2c\x05READY\x0bSocket-Type\0\0\0\x06CLIENT\x08Identity\0\0\0\x03abc",
+            Frame::Ready {
+                socket_type: SocketType::CLIENT,
+                properties: map!("Identity" => b"abc"),
+            }
+        );
+    }
+
     #[test]
     fn roundtrips_ready_zmtp_spec2() {
         assert_roundtrips!(
@@ -299,4 +336,78 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn decode_fails_before_allocating_too_large_messages() {
+        let mut decoder = Zmtp {
+            max_message_size: 100,
+            ..Default::default()
+        };
+
+        let mut buf = BytesMut::from(&b"\x00\x65"[..]);
+        assert_eq!(
+            decode::Error::MessageTooLarge,
+            decoder.decode(&mut buf).expect_err("success")
+        );
+
+        let mut buf = BytesMut::from(&b"\x02\0\0\0\0\x01\0\0\0"[..]);
+        let capacity = buf.capacity();
+        assert_eq!(
+            decode::Error::MessageTooLarge,
+            decoder.decode(&mut buf).expect_err("success")
+        );
+        assert_eq!(buf.capacity(), capacity);
+    }
+
+    #[test]
+    fn max_message_size_only_applies_to_messages() {
+        let mut decoder = Zmtp {
+            max_message_size: 4,
+            ..Default::default()
+        };
+
+        let mut buf = BytesMut::from(&b"\x04\x07\x04PING\0\x0a"[..]);
+        let ping = Frame::Ping {
+            ttl: 10,
+            context: Vec::new(),
+        };
+        assert_eq!(Some(ping), decoder.decode(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn decodes_large_frames_in_place() {
+        let len = 1 << 20;
+        let mut data = b"\x02\0\0\0\0\0\x10\0\0".to_vec();
+        data.resize(9 + len, 7);
+
+        let mut codec = Zmtp::default();
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&data[..100]);
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        assert!(buf.capacity() >= 9 + len);
+
+        // Remaining reads go straight into the sized buffer.
+        let start = buf.as_ptr() as usize;
+        buf.extend_from_slice(&data[100..]);
+        match codec.decode(&mut buf).unwrap() {
+            Some(Frame::Message { payload, more }) => {
+                assert!(!more);
+                assert_eq!(payload.len(), len);
+                assert_eq!(payload.as_ptr() as usize, start + 9);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn decode_reserves_read_buffer_size() {
+        let mut codec = Zmtp {
+            read_buffer_size: 64 * 1024,
+            ..Default::default()
+        };
+
+        let mut buf = BytesMut::from(&b"\x00"[..]);
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        assert!(buf.capacity() >= 64 * 1024);
+    }
 }
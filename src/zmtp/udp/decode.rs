@@ -23,7 +23,7 @@ impl Decoder for Zudp {
         buffer.advance(group_len);
 
         if buffer.len() > self.max_message_size {
-            return Err(Error::InvalidFrameSize);
+            return Err(Error::MessageTooLarge);
         }
 
         Ok(Some(Frame::Message {
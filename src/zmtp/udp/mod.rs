@@ -15,17 +15,8 @@ impl Default for Zudp {
     }
 }
 
-use tokio::net::UdpSocket;
-use tokio_util::udp;
-
 pub(crate) use frame::*;
 
-pub(crate) type Framed = udp::UdpFramed<Zudp>;
-
-pub(crate) fn frame(socket: UdpSocket) -> Framed {
-    Framed::new(socket, Default::default())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,4 +86,20 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn decode_fails_on_too_large_messages() {
+        let mut decoder = Zudp {
+            max_message_size: 4,
+        };
+
+        let mut buf = BytesMut::from(&b"\x01ghello"[..]);
+        assert_eq!(
+            super::decode::Error::MessageTooLarge,
+            decoder.decode(&mut buf).expect_err("success")
+        );
+
+        let mut buf = BytesMut::from(&b"\x01gabcd"[..]);
+        assert!(decoder.decode(&mut buf).unwrap().is_some());
+    }
 }
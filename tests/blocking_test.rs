@@ -0,0 +1,57 @@
+use rmq::blocking::{Client, Dish, Radio, Server};
+use rmq::Error;
+use std::thread;
+use std::time::Duration;
+
+mod test;
+use claim::*;
+
+#[test]
+fn blocking_client_server() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let server = Server::new().unwrap();
+        let addr = server.listen(&addr).unwrap();
+
+        let client = Client::new().unwrap();
+        assert_ok!(client.connect(&addr));
+
+        let responder = thread::spawn(move || {
+            let request = server.recv_request().unwrap();
+            let reply = [&b"re: "[..], request.as_bytes()].concat();
+            server.respond(&request, reply).unwrap();
+        });
+
+        assert_ok_eq!(client.request("hello"), b"re: hello");
+        responder.join().unwrap();
+
+        assert_eq!(
+            client.recv_timeout(Duration::from_millis(10)),
+            Err(Error::Timeout)
+        );
+    }
+}
+
+#[test]
+fn blocking_radio_dish() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let radio = Radio::new().unwrap();
+        let addr = radio.listen(test::endpoint(transport)).unwrap();
+
+        let group = "foo".parse().unwrap();
+
+        let dish = Dish::new().unwrap();
+        assert_ok!(dish.connect(&addr));
+        dish.join(group);
+
+        thread::sleep(Duration::from_millis(50));
+
+        radio.broadcast("hello foo", group).unwrap();
+        assert_ok_eq!(dish.recv_timeout(Duration::from_secs(1)), b"hello foo");
+    }
+}
@@ -1,9 +1,12 @@
-use rmq::{Client, Server};
+use rmq::{Client, Error, Options, Server};
+use std::sync::Arc;
+use std::time::Duration;
 
 mod test;
 use claim::*;
 
-#[tokio::test]
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
 async fn client_server_routing() {
     subscribe_tracing!();
 
@@ -41,3 +44,172 @@ async fn client_server_routing() {
         assert_ne!(msg1.route, msg2.route);
     }
 }
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn client_server_mixed_message_sizes() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let s = Server::default();
+        assert_ok!(s.listen(&addr).await);
+
+        let c = Client::default();
+        assert_ok!(c.connect(&addr).await);
+
+        // Small messages are coalesced around large ones, which are written
+        // from their own buffers.
+        let sizes = [1, 5000, 3, 4 << 20, 0, 200, 1 << 16];
+        for (i, &size) in sizes.iter().enumerate() {
+            assert_ok!(c.send(vec![i as u8; size]).await);
+        }
+
+        for (i, &size) in sizes.iter().enumerate() {
+            let msg = s.recv().await.unwrap();
+            assert_eq!(msg, &vec![i as u8; size][..]);
+        }
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn client_server_batches() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let s = Server::default();
+        assert_ok!(s.listen(&addr).await);
+
+        let c = Client::default();
+        assert_ok!(c.connect(&addr).await);
+
+        assert_ok!(c.send_batch(Vec::<Vec<u8>>::new()).await);
+        assert_ok!(c.send_batch((0..100u8).map(|i| vec![i])).await);
+        assert_ok!(c.send("last").await);
+
+        let mut batch = Vec::new();
+        while batch.len() < 101 {
+            let received = s.recv_batch(&mut batch, 32).await.unwrap();
+            assert!(received > 0 && received <= 32);
+        }
+
+        for (i, msg) in batch[..100].iter().enumerate() {
+            assert_eq!(*msg, [i as u8]);
+        }
+        assert_eq!(batch[100], b"last");
+        assert_eq!(s.stats().messages_in, 101);
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn oversized_messages_disconnect_peer() {
+    subscribe_tracing!();
+
+    // Inproc peers share memory and are not limited.
+    let transports = test::transports().into_iter();
+    for transport in transports.filter(|t| !matches!(t, test::Transport::INPROC)) {
+        let addr = test::endpoint(transport);
+
+        let s = Server::with_options(Options {
+            max_message_size: 1000,
+            ..Default::default()
+        });
+        assert_ok!(s.listen(&addr).await);
+
+        let c = Client::default();
+        assert_ok!(c.connect(&addr).await);
+
+        assert_ok!(c.send(vec![1; 1000]).await);
+        let request = s.recv().await.unwrap();
+        assert_eq!(request, &vec![1; 1000][..]);
+
+        assert_ok!(c.send(vec![2; 1001]).await);
+        for _ in 0..100 {
+            if s.stats().peers.is_empty() {
+                break;
+            }
+
+            test::sleep(Duration::from_millis(10)).await;
+        }
+
+        let stats = s.stats();
+        assert!(stats.peers.is_empty());
+        assert_eq!(stats.oversized_messages, 1);
+        assert_eq!(stats.disconnects, 1);
+        assert_eq!(stats.messages_in, 1);
+
+        // The session has ended, so the peer can no longer be reached.
+        assert_eq!(
+            s.route("gone", request.route).await,
+            Err(Error::RoutingError)
+        );
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn many_tasks_share_sockets() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let s = Arc::new(Server::default());
+        assert_ok!(s.listen(&addr).await);
+
+        let c = Arc::new(Client::default());
+        assert_ok!(c.connect(&addr).await);
+
+        let senders: Vec<_> = (0..8u8)
+            .map(|task| {
+                let c = c.clone();
+                test::spawn(async move {
+                    for i in 0..50u8 {
+                        c.send(vec![task, i]).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let mut requests = Vec::new();
+        for _ in 0..400 {
+            requests.push(s.recv().await.unwrap());
+        }
+        futures::future::join_all(senders).await;
+
+        // Messages of each task arrive in the order they were sent.
+        let mut next = [0u8; 8];
+        for request in &requests {
+            let task = request.as_bytes()[0] as usize;
+            assert_eq!(request.as_bytes()[1], next[task]);
+            next[task] += 1;
+        }
+
+        let routers: Vec<_> = requests
+            .chunks(50)
+            .map(|chunk| {
+                let s = s.clone();
+                let replies: Vec<_> = chunk
+                    .iter()
+                    .map(|r| (r.as_bytes().to_vec(), r.route))
+                    .collect();
+                test::spawn(async move {
+                    for (body, route) in replies {
+                        s.route(body, route).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        futures::future::join_all(routers).await;
+
+        for _ in 0..400 {
+            assert_ok!(c.recv().await);
+        }
+        assert_eq!(s.stats().messages_out, 400);
+    }
+}
@@ -5,7 +5,8 @@ mod test;
 use claim::*;
 use std::thread;
 
-#[tokio::test]
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
 async fn client_compat() {
     subscribe_tracing!();
 
@@ -40,7 +41,7 @@ async fn client_compat() {
     let client2 = Client::default();
     assert_ok!(client2.connect(&addr).await);
 
-    tokio::time::delay_for(std::time::Duration::from_millis(1)).await;
+    test::sleep(std::time::Duration::from_millis(1)).await;
 
     assert_ok!(client1.send("hello 1").await);
     assert_ok!(client2.send("hello 2").await);
@@ -49,7 +50,8 @@ async fn client_compat() {
     assert_ok_eq!(client2.recv().await, b"hello world 2");
 }
 
-#[tokio::test]
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
 async fn server_compat() {
     subscribe_tracing!();
 
@@ -79,7 +81,7 @@ async fn server_compat() {
         assert_eq!(client2.recv_msg().unwrap().as_bytes(), b"hello world 2");
     });
 
-    tokio::time::delay_for(std::time::Duration::from_millis(1)).await;
+    test::sleep(std::time::Duration::from_millis(1)).await;
 
     let msg1 = server.recv().await.unwrap();
     assert_eq!(msg1.as_bytes(), b"hello 1");
@@ -0,0 +1,120 @@
+use futures::executor::block_on;
+use rmq::{Client, Error, Options, Server};
+
+mod test;
+use claim::*;
+
+fn with_io_thread() -> Options {
+    Options {
+        io_thread: true,
+        ..Default::default()
+    }
+}
+
+// Blocks on the sockets outside of any runtime, so sessions can only make
+// progress if they were spawned elsewhere.
+fn exchange(server: Options, client: Options) {
+    for transport in test::transports() {
+        let s = Server::with_options(server.clone());
+        let addr = block_on(s.listen(test::endpoint(transport))).unwrap();
+
+        let c = Client::with_options(client.clone());
+        assert_ok!(block_on(c.connect(&addr)));
+
+        for i in 0..10u8 {
+            assert_ok!(block_on(c.send(vec![i; 100])));
+            let request = block_on(s.recv()).unwrap();
+            assert_eq!(request, &[i; 100][..]);
+
+            assert_ok!(block_on(s.route("ok", request.route)));
+            assert_eq!(block_on(c.recv()).unwrap(), b"ok");
+        }
+    }
+}
+
+#[test]
+fn sessions_run_on_io_thread() {
+    subscribe_tracing!();
+    exchange(with_io_thread(), with_io_thread());
+}
+
+#[cfg(not(feature = "async-std"))]
+#[test]
+fn sessions_run_on_given_runtime() {
+    subscribe_tracing!();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let options = Options {
+        runtime: Some(runtime.handle().clone()),
+        ..Default::default()
+    };
+
+    exchange(options.clone(), options);
+}
+
+#[cfg(not(feature = "async-std"))]
+#[test]
+fn io_thread_and_runtime_are_exclusive() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let s = Server::with_options(Options {
+        runtime: Some(runtime.handle().clone()),
+        ..with_io_thread()
+    });
+
+    assert_eq!(
+        block_on(s.listen(test::endpoint(test::transports()[0]))),
+        Err(Error::OptionInvalid)
+    );
+}
+
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+#[test]
+fn io_thread_pinned_to_cpu() {
+    subscribe_tracing!();
+
+    let pinned = Options {
+        cpu_affinity: vec![0],
+        ..with_io_thread()
+    };
+
+    exchange(pinned.clone(), pinned);
+}
+
+#[cfg(feature = "affinity")]
+#[test]
+fn invalid_cpu_affinity() {
+    let s = Server::with_options(Options {
+        cpu_affinity: vec![1 << 20],
+        ..with_io_thread()
+    });
+
+    assert_eq!(
+        block_on(s.listen(test::endpoint(test::transports()[0]))),
+        Err(Error::OptionInvalid)
+    );
+
+    // Affinity only applies to an I/O thread.
+    let s = Server::with_options(Options {
+        cpu_affinity: vec![0],
+        ..Default::default()
+    });
+
+    assert_eq!(
+        block_on(s.listen(test::endpoint(test::transports()[0]))),
+        Err(Error::OptionInvalid)
+    );
+}
+
+#[cfg(not(feature = "affinity"))]
+#[test]
+fn cpu_affinity_requires_feature() {
+    let s = Server::with_options(Options {
+        cpu_affinity: vec![0],
+        ..with_io_thread()
+    });
+
+    assert_eq!(
+        block_on(s.listen(test::endpoint(test::transports()[0]))),
+        Err(Error::OptionInvalid)
+    );
+}
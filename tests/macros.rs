@@ -61,6 +61,7 @@ macro_rules! envelope {
                 payload: crate::message::Payload::from(vec![$payload]),
                 ..Default::default()
             },
+            received: 0,
         }
     };
 }
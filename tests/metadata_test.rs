@@ -0,0 +1,103 @@
+use rmq::{zmtp::SocketType, Client, Error, Options, Server};
+use std::time::SystemTime;
+
+mod test;
+use claim::*;
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn ready_sends_identity_and_metadata() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        // Only stream transports perform a handshake.
+        if let test::Transport::INPROC = transport {
+            continue;
+        }
+
+        let addr = test::endpoint(transport);
+
+        let s = Server::default();
+        let addr = s.listen(&addr).await.unwrap();
+
+        let mut options = Options::default();
+        options.identity = Some(b"client-1".to_vec());
+        options
+            .metadata
+            .insert("X-Hostname".into(), b"example".to_vec());
+
+        let c = Client::with_options(options);
+        assert_ok!(c.connect(&addr).await);
+        assert_ok!(c.send("hello").await);
+
+        let msg = s.recv().await.unwrap();
+        assert_eq!(msg.peer_identity(), Some(&b"client-1"[..]));
+        assert_eq!(msg.meta("X-Hostname"), Some(&b"example"[..]));
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn envelope_reports_connection_and_receive_time() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let s = Server::default();
+        let addr = s.listen(&addr).await.unwrap();
+
+        let c = Client::default();
+        assert_ok!(c.connect(&addr).await);
+
+        let before = SystemTime::now();
+        assert_ok!(c.send("hello").await);
+        let msg = s.recv().await.unwrap();
+
+        let received = msg.received_at().unwrap();
+        assert!(received >= before && received <= SystemTime::now());
+        assert_none!(msg.user_id());
+
+        if let test::Transport::TCP = transport {
+            assert_eq!(msg.peer_socket_type(), Some(SocketType::CLIENT));
+            assert_eq!(msg.local_endpoint(), Some(&addr));
+        }
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn invalid_options_are_rejected() {
+    let invalid = vec![
+        Options {
+            identity: Some(vec![]),
+            ..Default::default()
+        },
+        Options {
+            identity: Some(vec![0, 1]),
+            ..Default::default()
+        },
+        Options {
+            identity: Some(vec![1; 256]),
+            ..Default::default()
+        },
+        Options {
+            metadata: vec![("Hostname".into(), vec![])].into_iter().collect(),
+            ..Default::default()
+        },
+        Options {
+            metadata: vec![("X-Host name".into(), vec![])].into_iter().collect(),
+            ..Default::default()
+        },
+        Options {
+            metadata: vec![("Xé".into(), vec![])].into_iter().collect(),
+            ..Default::default()
+        },
+    ];
+
+    for options in invalid {
+        let c = Client::with_options(options);
+        let result = c.connect(test::endpoint(test::Transport::INPROC)).await;
+        assert_eq!(result.unwrap_err(), Error::OptionInvalid);
+    }
+}
@@ -3,7 +3,8 @@ use rmq::Peer;
 mod test;
 use claim::*;
 
-#[tokio::test]
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
 async fn peer_peer_routing() {
     subscribe_tracing!();
 
@@ -0,0 +1,209 @@
+use rmq::{proxy, Client, Dish, Error, Gather, Radio, Scatter, Server};
+use std::time::Duration;
+
+mod test;
+use claim::*;
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn proxy_server_client() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let frontend = Server::default();
+        let frontend_addr = frontend.listen(test::endpoint(transport)).await.unwrap();
+
+        let worker = Server::default();
+        let worker_addr = worker.listen(test::endpoint(transport)).await.unwrap();
+
+        let backend = Client::default();
+        assert_ok!(backend.connect(&worker_addr).await);
+
+        let (controller, control) = proxy::control();
+        let broker = test::spawn(async move {
+            proxy::proxy_rpc_steerable(&frontend, &backend, None, control).await
+        });
+
+        let responder = test::spawn(async move {
+            for _ in 0..2 {
+                let request = worker.recv_request().await.unwrap();
+                let reply = [&b"re: "[..], request.as_bytes()].concat();
+                worker.respond(&request, reply).await.unwrap();
+            }
+        });
+
+        let client1 = Client::default();
+        assert_ok!(client1.connect(&frontend_addr).await);
+
+        let client2 = Client::default();
+        assert_ok!(client2.connect(&frontend_addr).await);
+
+        assert_ok_eq!(client1.request("hello 1").await, b"re: hello 1");
+        assert_ok_eq!(client2.request("hello 2").await, b"re: hello 2");
+        responder.await;
+
+        let statistics = controller.statistics().await.unwrap();
+        assert_eq!(statistics.frontend.messages_in, 2);
+        assert_eq!(statistics.frontend.messages_out, 2);
+        assert_eq!(statistics.backend.messages_in, 2);
+        assert_eq!(statistics.backend.messages_out, 2);
+
+        controller.terminate();
+        assert_ok!(broker.await);
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn proxy_server_client_routes_replies_to_their_clients() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let frontend = Server::default();
+        let frontend_addr = frontend.listen(test::endpoint(transport)).await.unwrap();
+
+        let worker = Server::default();
+        let worker_addr = worker.listen(test::endpoint(transport)).await.unwrap();
+
+        let backend = Client::default();
+        assert_ok!(backend.connect(&worker_addr).await);
+
+        let (controller, control) = proxy::control();
+        let broker = test::spawn(async move {
+            proxy::proxy_rpc_steerable(&frontend, &backend, None, control).await
+        });
+
+        // Replies are sent in reverse order, and still reach the client that
+        // made the request.
+        let responder = test::spawn(async move {
+            let first = worker.recv_request().await.unwrap();
+            let second = worker.recv_request().await.unwrap();
+            for request in &[second, first] {
+                let reply = [&b"re: "[..], request.as_bytes()].concat();
+                worker.respond(request, reply).await.unwrap();
+            }
+        });
+
+        let client1 = Client::default();
+        assert_ok!(client1.connect(&frontend_addr).await);
+
+        let client2 = Client::default();
+        assert_ok!(client2.connect(&frontend_addr).await);
+
+        let (reply1, reply2) =
+            futures::future::join(client1.request("hello 1"), client2.request("hello 2")).await;
+        assert_ok_eq!(reply1, b"re: hello 1");
+        assert_ok_eq!(reply2, b"re: hello 2");
+        responder.await;
+
+        controller.terminate();
+        assert_ok!(broker.await);
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn proxy_gather_scatter_plain_payloads() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let frontend = Gather::default();
+        let frontend_addr = frontend.listen(test::endpoint(transport)).await.unwrap();
+
+        let backend = Scatter::default();
+        let backend_addr = backend.listen(test::endpoint(transport)).await.unwrap();
+
+        let (controller, control) = proxy::control();
+        let broker =
+            test::spawn(
+                async move { proxy::proxy_steerable(&frontend, &backend, None, control).await },
+            );
+
+        let producer = Scatter::default();
+        assert_ok!(producer.connect(&frontend_addr).await);
+
+        let consumer = Gather::default();
+        assert_ok!(consumer.connect(&backend_addr).await);
+
+        for payload in &[&b""[..], b"abc", b"abcd", b"hello world"] {
+            assert_ok!(producer.send(*payload).await);
+            assert_ok_eq!(consumer.recv().await, payload);
+        }
+
+        controller.terminate();
+        assert_ok!(broker.await);
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn proxy_rejects_routed_sockets_without_rpc() {
+    let frontend = Server::default();
+    let backend = Client::default();
+
+    assert_eq!(
+        proxy(&frontend, &backend, None).await,
+        Err(Error::Unsupported)
+    );
+    assert_eq!(
+        proxy(&backend, &frontend, None).await,
+        Err(Error::Unsupported)
+    );
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn proxy_rejects_routed_capture() {
+    let frontend = Dish::default();
+    let backend = Radio::default();
+    let capture = Server::default();
+
+    assert_eq!(
+        proxy(&frontend, &backend, Some(&capture)).await,
+        Err(Error::Unsupported)
+    );
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn proxy_dish_radio() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let publisher = Radio::default();
+        let publisher_addr = publisher.listen(test::endpoint(transport)).await.unwrap();
+
+        let frontend = Dish::default();
+        assert_ok!(frontend.connect(&publisher_addr).await);
+
+        let backend = Radio::default();
+        let backend_addr = backend.listen(test::endpoint(transport)).await.unwrap();
+
+        let group = "foo".parse().unwrap();
+        frontend.join(group);
+
+        let subscriber = Dish::default();
+        assert_ok!(subscriber.connect(&backend_addr).await);
+        subscriber.join(group);
+
+        let capture = Dish::default();
+        let capture_addr = capture.listen(test::endpoint(transport)).await.unwrap();
+        let capture_radio = Radio::default();
+        assert_ok!(capture_radio.connect(&capture_addr).await);
+        capture.join(group);
+
+        let broker = test::spawn(async move {
+            let _ = proxy(&frontend, &backend, Some(&capture_radio)).await;
+        });
+
+        test::sleep(Duration::from_millis(50)).await;
+        publisher.broadcast("hello foo", group).unwrap();
+
+        let message = subscriber.recv().await.unwrap();
+        assert_eq!(message, b"hello foo");
+        assert_eq!(*message.message.group(), group);
+        assert_ok_eq!(capture.recv().await, b"hello foo");
+
+        drop(broker);
+    }
+}
@@ -3,7 +3,8 @@ use rmq::{Dish, Radio};
 mod test;
 use claim::*;
 
-#[tokio::test]
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
 async fn radio_dish_broadcasting() {
     subscribe_tracing!();
 
@@ -24,7 +25,7 @@ async fn radio_dish_broadcasting() {
         dish2.join(blank_group);
         dish2.join(bar_group);
 
-        tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+        test::sleep(std::time::Duration::from_millis(50)).await;
 
         radio.broadcast("hello foo", foo_group).unwrap();
         radio.broadcast("hello bar", bar_group).unwrap();
@@ -38,3 +39,29 @@ async fn radio_dish_broadcasting() {
         assert_ok_eq!(dish2.recv().await, b"hello");
     }
 }
+
+// Only radios send over UDP, so dishes listen and radios connect to them.
+#[cfg(feature = "udp")]
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn radio_dish_over_udp() {
+    subscribe_tracing!();
+
+    let foo_group = "foo".parse().unwrap();
+    let bar_group = "bar".parse().unwrap();
+
+    let dish = Dish::default();
+    let addr = dish
+        .listen(test::endpoint(test::Transport::UDP))
+        .await
+        .unwrap();
+    dish.join(foo_group);
+
+    let radio = Radio::default();
+    assert_ok!(radio.connect(&addr).await);
+
+    radio.broadcast("hello bar", bar_group).unwrap();
+    radio.broadcast("hello foo", foo_group).unwrap();
+
+    assert_ok_eq!(dish.recv().await, b"hello foo");
+}
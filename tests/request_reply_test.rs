@@ -0,0 +1,62 @@
+use rmq::{Client, Error, Server};
+use std::time::Duration;
+
+mod test;
+use claim::*;
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn client_server_request_reply() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let server = Server::default();
+        assert_ok!(server.listen(&addr).await);
+
+        let client = Client::default();
+        assert_ok!(client.connect(&addr).await);
+
+        let responder = test::spawn(async move {
+            let first = server.recv_request().await.unwrap();
+            let second = server.recv_request().await.unwrap();
+
+            // Reply out of order.
+            let reply = [&b"re: "[..], second.as_bytes()].concat();
+            server.respond(&second, reply).await.unwrap();
+            let reply = [&b"re: "[..], first.as_bytes()].concat();
+            server.respond(&first, reply).await.unwrap();
+        });
+
+        let (reply1, reply2) = tokio::join!(client.request("hello 1"), client.request("hello 2"));
+
+        assert_ok_eq!(reply1, b"re: hello 1");
+        assert_ok_eq!(reply2, b"re: hello 2");
+
+        responder.await;
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn client_request_times_out() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let server = Server::default();
+        assert_ok!(server.listen(&addr).await);
+
+        let client = Client::default();
+        assert_ok!(client.connect(&addr).await);
+
+        assert_eq!(
+            client
+                .request_timeout("hello", Duration::from_millis(10))
+                .await,
+            Err(Error::Timeout)
+        );
+    }
+}
@@ -0,0 +1,104 @@
+#![cfg(feature = "shm")]
+
+use rmq::{Client, Endpoint, Error, Server};
+
+mod test;
+use claim::*;
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn messages_larger_than_ring_are_split() {
+    subscribe_tracing!();
+
+    let s = Server::default();
+    let addr = s
+        .listen(test::endpoint(test::Transport::SHM))
+        .await
+        .unwrap();
+
+    let c = Client::default();
+    assert_ok!(c.connect(&addr).await);
+
+    // Exceeds the capacity of the ring in both directions.
+    let body = (0..3 << 20).map(|i| i as u8).collect::<Vec<_>>();
+    for _ in 0..3 {
+        assert_ok!(c.send(body.clone()).await);
+        let request = s.recv().await.unwrap();
+        assert_eq!(request, &body[..]);
+        assert_eq!(request.local_endpoint(), Some(&addr));
+
+        assert_ok!(s.route(body.clone(), request.route).await);
+        assert_eq!(c.recv().await.unwrap(), &body[..]);
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn stalled_connector_does_not_block_listener() {
+    subscribe_tracing!();
+
+    let s = Server::default();
+    let addr = s
+        .listen(test::endpoint(test::Transport::SHM))
+        .await
+        .unwrap();
+
+    // Connects to the socket of the listener but never starts the handshake.
+    let name = addr.to_string().trim_start_matches("shm://").to_owned();
+    let path = std::env::temp_dir().join(format!("rmq-{}.sock", name));
+    let _stalled = std::os::unix::net::UnixStream::connect(path).unwrap();
+
+    let c = Client::default();
+    assert_ok!(c.connect(&addr).await);
+    assert_ok!(c.send("hello").await);
+    assert_eq!(s.recv().await.unwrap(), b"hello");
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn many_small_messages_keep_order() {
+    subscribe_tracing!();
+
+    let s = Server::default();
+    let addr = s
+        .listen(test::endpoint(test::Transport::SHM))
+        .await
+        .unwrap();
+
+    let c = Client::default();
+    assert_ok!(c.connect(&addr).await);
+
+    let sender = test::spawn(async move {
+        for i in 0..100_000u32 {
+            c.send(i.to_le_bytes().to_vec()).await.unwrap();
+        }
+
+        c
+    });
+
+    for i in 0..100_000u32 {
+        assert_eq!(s.recv().await.unwrap(), &i.to_le_bytes()[..]);
+    }
+
+    sender.await;
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn names_are_exclusive() {
+    let addr = test::endpoint(test::Transport::SHM);
+    let s1 = Server::default();
+    let endpoint = s1.listen(addr.as_str()).await.unwrap();
+    assert_eq!(endpoint.to_string(), addr);
+    assert!(matches!(endpoint, Endpoint::Shm(..)));
+
+    let s2 = Server::default();
+    assert_eq!(
+        s2.listen(addr.as_str()).await.unwrap_err(),
+        Error::AddressInUse
+    );
+    assert_eq!(
+        s2.listen("shm://a/b").await.unwrap_err(),
+        Error::AddressInvalid
+    );
+}
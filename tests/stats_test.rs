@@ -0,0 +1,83 @@
+use rmq::{Client, Dish, Options, Radio, Server};
+
+mod test;
+use claim::*;
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn stats_count_messages_per_socket_and_peer() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let s = Server::default();
+        let addr = s.listen(&addr).await.unwrap();
+
+        let c = Client::default();
+        let route = c.connect(&addr).await.unwrap();
+
+        assert_ok!(c.send("hello").await);
+        let request = s.recv().await.unwrap();
+        assert_ok!(s.route("hi", request.route).await);
+        assert_ok!(c.recv().await);
+
+        let stats = c.stats();
+        assert_eq!((stats.messages_out, stats.bytes_out), (1, 5));
+        assert_eq!((stats.messages_in, stats.bytes_in), (1, 2));
+        assert_eq!(stats.queued_in, 0);
+        assert_eq!(stats.peers[&route].messages_out, 1);
+
+        let stats = s.stats();
+        assert_eq!((stats.messages_in, stats.bytes_in), (1, 5));
+        assert_eq!((stats.messages_out, stats.bytes_out), (1, 2));
+        assert_eq!(stats.peers[&request.route].messages_in, 1);
+
+        if let test::Transport::TCP = transport {
+            assert_eq!(c.stats().connects, 1);
+            assert_eq!(s.stats().connects, 1);
+        }
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn stats_count_disconnects_once_sessions_end() {
+    subscribe_tracing!();
+
+    // Inproc peers have no session.
+    let transports = test::transports().into_iter();
+    for transport in transports.filter(|t| !matches!(t, test::Transport::INPROC)) {
+        let radio = Radio::default();
+        let addr = radio.listen(test::endpoint(transport)).await.unwrap();
+
+        // Sessions on an I/O thread end once their socket is dropped.
+        let group = "foo".parse().unwrap();
+        let dish = Dish::with_options(Options {
+            io_thread: true,
+            ..Default::default()
+        });
+        assert_ok!(dish.connect(&addr).await);
+        dish.join(group);
+        test::sleep(std::time::Duration::from_millis(50)).await;
+
+        // Both sockets only use one direction of their sessions.
+        radio.broadcast("hello", group).unwrap();
+        assert_ok_eq!(dish.recv().await, b"hello");
+        assert_eq!(radio.stats().disconnects, 0);
+        assert_eq!(dish.stats().disconnects, 0);
+
+        drop(dish);
+        for _ in 0..100 {
+            if radio.stats().peers.is_empty() {
+                break;
+            }
+
+            test::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let stats = radio.stats();
+        assert!(stats.peers.is_empty());
+        assert_eq!(stats.disconnects, 1);
+    }
+}
@@ -0,0 +1,119 @@
+use futures::{SinkExt, StreamExt};
+use rmq::{Client, Dish, Error, Gather, IntoMessage, Radio, Route, Scatter, Server};
+use std::time::Duration;
+
+mod test;
+use claim::*;
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn client_server_stream_sink() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let server = Server::default();
+        assert_ok!(server.listen(&addr).await);
+
+        let client = Client::default();
+        assert_ok!(client.connect(&addr).await);
+
+        let (mut client_tx, mut client_rx) = client.split();
+        let (mut server_tx, mut server_rx) = server.split();
+
+        let echo = test::spawn(async move {
+            for _ in 0..2 {
+                let envelope = server_rx.next().await.unwrap().unwrap();
+                let route = envelope.route;
+                server_tx.send((envelope.message, route)).await.unwrap();
+            }
+        });
+
+        assert_ok!(client_tx.send("hello 1".into_message()).await);
+        assert_ok!(client_tx.send("hello 2".into_message()).await);
+
+        assert_ok_eq!(client_rx.next().await.unwrap(), b"hello 1");
+        assert_ok_eq!(client_rx.next().await.unwrap(), b"hello 2");
+
+        echo.await;
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn server_sink_drops_unroutable_messages() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let mut server = Server::default();
+        assert_ok!(server.listen(&addr).await);
+
+        let client = Client::default();
+        assert_ok!(client.connect(&addr).await);
+
+        let unknown = Route::from(u32::MAX);
+        assert_eq!(
+            server.send(("lost".into_message(), unknown)).await,
+            Err(Error::RoutingError)
+        );
+
+        assert_ok!(client.send("hello").await);
+        let envelope = assert_ok!(server.next().await.unwrap());
+        assert_ok!(server.send(("hello".into_message(), envelope.route)).await);
+        assert_ok_eq!(client.recv().await, b"hello");
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn scatter_gather_stream_sink() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let addr = test::endpoint(transport);
+
+        let mut gather = Gather::default();
+        assert_ok!(gather.listen(&addr).await);
+
+        let mut scatter = Scatter::default();
+        assert_ok!(scatter.connect(&addr).await);
+
+        let messages = vec!["hello 1", "hello 2", "hello 3"];
+        let mut outgoing =
+            futures::stream::iter(messages.clone()).map(|message| Ok(message.into_message()));
+        assert_ok!(scatter.send_all(&mut outgoing).await);
+
+        for message in messages {
+            assert_ok_eq!(gather.next().await.unwrap(), message.as_bytes());
+        }
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn radio_dish_stream_sink() {
+    subscribe_tracing!();
+
+    for transport in test::transports() {
+        let mut radio = Radio::default();
+        let addr = radio.listen(test::endpoint(transport)).await.unwrap();
+
+        let group = "foo".parse().unwrap();
+
+        let mut dish = Dish::default();
+        assert_ok!(dish.connect(&addr).await);
+        dish.join(group);
+
+        test::sleep(Duration::from_millis(50)).await;
+
+        assert_ok!(radio.send(("hello 1".into_message(), group)).await);
+        assert_ok!(radio.send(("hello 2".into_message(), group)).await);
+        assert_ok!(radio.close().await);
+
+        assert_ok_eq!(dish.next().await.unwrap(), b"hello 1");
+        assert_ok_eq!(dish.next().await.unwrap(), b"hello 2");
+    }
+}
@@ -1,5 +1,7 @@
 #![allow(dead_code)]
+use futures::Future;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
 
 pub static MSG: &str = "12345678ABCDEFGH12345678abcdefgh";
 
@@ -14,6 +16,8 @@ pub enum Transport {
     TCP,
     IPC,
     INPROC,
+    SHM,
+    UDP,
 }
 
 pub fn transports() -> Vec<Transport> {
@@ -24,6 +28,8 @@ pub fn transports() -> Vec<Transport> {
         Transport::IPC,
         #[cfg(feature = "inproc")]
         Transport::INPROC,
+        #[cfg(feature = "shm")]
+        Transport::SHM,
     ]
 }
 
@@ -36,6 +42,11 @@ pub fn endpoint(transport: Transport) -> String {
             format!("tcp://127.0.0.1:{}", port)
         }
 
+        Transport::UDP => {
+            let port = PORT.fetch_add(1, Ordering::SeqCst);
+            format!("udp://127.0.0.1:{}", port)
+        }
+
         Transport::IPC => {
             let id: usize = rand::random();
             format!(
@@ -52,5 +63,39 @@ pub fn endpoint(transport: Transport) -> String {
             let id: usize = rand::random();
             format!("inproc://rmq-test-{}", id)
         }
+
+        Transport::SHM => {
+            let id: u32 = rand::random();
+            format!("shm://rmq-test-{}", id)
+        }
     }
 }
+
+#[cfg(feature = "async-std")]
+pub fn spawn<F>(future: F) -> impl Future<Output = F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    async_std::task::spawn(future)
+}
+
+#[cfg(not(feature = "async-std"))]
+pub fn spawn<F>(future: F) -> impl Future<Output = F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    use futures::FutureExt;
+    tokio::spawn(future).map(Result::unwrap)
+}
+
+#[cfg(feature = "async-std")]
+pub async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+#[cfg(not(feature = "async-std"))]
+pub async fn sleep(duration: Duration) {
+    tokio::time::delay_for(duration).await
+}
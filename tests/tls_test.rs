@@ -0,0 +1,174 @@
+#![cfg(feature = "tls")]
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, IsCa, KeyPair,
+};
+use rmq::{Client, Error, Options, Server, TlsOptions};
+use std::time::Duration;
+
+mod test;
+use claim::*;
+
+struct Authority {
+    certificate: Certificate,
+    key: KeyPair,
+}
+
+impl Authority {
+    fn new() -> Self {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "rmq test CA");
+
+        Self {
+            certificate: params.self_signed(&key).unwrap(),
+            key,
+        }
+    }
+
+    fn root(&self) -> Vec<u8> {
+        self.certificate.der().to_vec()
+    }
+
+    fn issue(&self, name: &str) -> TlsOptions {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["127.0.0.1".to_owned()]).unwrap();
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, name);
+        let certificate = params
+            .signed_by(&key, &self.certificate, &self.key)
+            .unwrap();
+
+        TlsOptions {
+            certificate_chain: vec![certificate.der().to_vec()],
+            private_key: Some(key.serialize_der()),
+            roots: vec![self.root()],
+            ..Default::default()
+        }
+    }
+}
+
+fn with_tls(tls: TlsOptions) -> Options {
+    Options {
+        tls: Some(tls),
+        ..Default::default()
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn mutual_tls_exposes_peer_subjects() {
+    subscribe_tracing!();
+
+    let authority = Authority::new();
+    let s = Server::with_options(with_tls(TlsOptions {
+        mutual: true,
+        ..authority.issue("server")
+    }));
+
+    let addr = s.listen("tls://127.0.0.1:0").await.unwrap();
+
+    let c = Client::with_options(with_tls(authority.issue("client")));
+    assert_ok!(c.connect(&addr).await);
+
+    assert_ok!(c.send("hello").await);
+    let request = s.recv().await.unwrap();
+    assert_eq!(request, b"hello");
+    assert_eq!(request.peer_subject(), Some("CN=client"));
+    assert_eq!(request.user_id(), Some(&b"CN=client"[..]));
+
+    assert_ok!(s.route("hi", request.route).await);
+    let reply = c.recv().await.unwrap();
+    assert_eq!(reply, b"hi");
+    assert_eq!(reply.peer_subject(), Some("CN=server"));
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn untrusted_server_fails_handshake() {
+    subscribe_tracing!();
+
+    let s = Server::with_options(with_tls(Authority::new().issue("server")));
+    let addr = s.listen("tls://127.0.0.1:0").await.unwrap();
+
+    let c = Client::with_options(with_tls(Authority::new().issue("client")));
+    assert_ok!(c.connect(&addr).await);
+
+    for _ in 0..100 {
+        if c.stats().handshake_failures > 0 {
+            break;
+        }
+
+        test::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_gt!(c.stats().handshake_failures, 0);
+    assert_eq!(c.stats().connects, 0);
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn stalled_handshake_does_not_block_listener() {
+    subscribe_tracing!();
+
+    let authority = Authority::new();
+    let s = Server::with_options(with_tls(authority.issue("server")));
+    let addr = s.listen("tls://127.0.0.1:0").await.unwrap();
+
+    // Connects but never starts the TLS handshake.
+    let address = addr.to_string().trim_start_matches("tls://").to_owned();
+    let _stalled = std::net::TcpStream::connect(address).unwrap();
+
+    let c = Client::with_options(with_tls(authority.issue("client")));
+    assert_ok!(c.connect(&addr).await);
+    assert_ok!(c.send("hello").await);
+    assert_eq!(s.recv().await.unwrap(), b"hello");
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn tls_endpoints_require_options() {
+    let s = Server::default();
+    assert_eq!(
+        s.listen("tls://127.0.0.1:0").await.unwrap_err(),
+        Error::OptionInvalid
+    );
+
+    // Listeners need a certificate.
+    let s = Server::with_options(with_tls(TlsOptions {
+        roots: Authority::new().issue("client").roots,
+        ..Default::default()
+    }));
+
+    assert_eq!(
+        s.listen("tls://127.0.0.1:0").await.unwrap_err(),
+        Error::OptionInvalid
+    );
+}
+
+#[cfg(feature = "ws")]
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn secure_websocket_exposes_peer_subjects() {
+    subscribe_tracing!();
+
+    let authority = Authority::new();
+    let s = Server::with_options(with_tls(TlsOptions {
+        mutual: true,
+        ..authority.issue("server")
+    }));
+
+    let addr = s.listen("wss://127.0.0.1:0/zmq").await.unwrap();
+
+    let c = Client::with_options(with_tls(authority.issue("client")));
+    assert_ok!(c.connect(&addr).await);
+
+    assert_ok!(c.send("hello").await);
+    let request = s.recv().await.unwrap();
+    assert_eq!(request, b"hello");
+    assert_eq!(request.peer_subject(), Some("CN=client"));
+}
@@ -0,0 +1,83 @@
+#![cfg(feature = "io-uring")]
+
+use rmq::{Client, Options, Server};
+
+mod test;
+use claim::*;
+
+fn with_io_uring() -> Options {
+    Options {
+        io_uring: true,
+        ..Default::default()
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn client_server_over_io_uring() {
+    subscribe_tracing!();
+
+    let s = Server::with_options(with_io_uring());
+    let addr = s.listen("tcp://127.0.0.1:0").await.unwrap();
+
+    let c = Client::with_options(with_io_uring());
+    assert_ok!(c.connect(&addr).await);
+
+    // Larger messages span several registered buffers.
+    for i in 0..10 {
+        let body = vec![i; i as usize * 100_000];
+        assert_ok!(c.send(body.clone()).await);
+        let request = s.recv().await.unwrap();
+        assert_eq!(request, &body[..]);
+        assert_eq!(request.local_endpoint(), Some(&addr));
+
+        assert_ok!(s.route("ok", request.route).await);
+        assert_eq!(c.recv().await.unwrap(), b"ok");
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn more_clients_than_registered_buffers() {
+    subscribe_tracing!();
+
+    let s = Server::with_options(with_io_uring());
+    let addr = s.listen("tcp://127.0.0.1:0").await.unwrap();
+
+    let mut clients = Vec::new();
+    for i in 0..100u32 {
+        let c = Client::with_options(with_io_uring());
+        assert_ok!(c.connect(&addr).await);
+        assert_ok!(c.send(i.to_be_bytes().to_vec()).await);
+        clients.push(c);
+    }
+
+    for _ in 0..clients.len() {
+        let request = s.recv().await.unwrap();
+        let body = request.as_bytes().to_vec();
+        assert_ok!(s.route(body, request.route).await);
+    }
+
+    for (i, c) in clients.iter().enumerate() {
+        assert_eq!(c.recv().await.unwrap(), &(i as u32).to_be_bytes()[..]);
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn interoperates_with_epoll_sockets() {
+    subscribe_tracing!();
+
+    let s = Server::with_options(with_io_uring());
+    let addr = s.listen("tcp://127.0.0.1:0").await.unwrap();
+
+    let c = Client::default();
+    assert_ok!(c.connect(&addr).await);
+
+    assert_ok!(c.send("hello").await);
+    let request = s.recv().await.unwrap();
+    assert_eq!(request, b"hello");
+
+    assert_ok!(s.route("world", request.route).await);
+    assert_eq!(c.recv().await.unwrap(), b"world");
+}
@@ -0,0 +1,144 @@
+#![cfg(feature = "ws")]
+
+use async_tungstenite::tungstenite::{self, client::IntoClientRequest, http::HeaderValue};
+use futures::{SinkExt, StreamExt};
+use rmq::{Client, Server};
+use std::time::Duration;
+
+mod test;
+use claim::*;
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn client_server_over_websocket() {
+    subscribe_tracing!();
+
+    let s = Server::default();
+    let addr = s.listen("ws://127.0.0.1:0/zmq").await.unwrap();
+    assert!(addr.to_string().ends_with("/zmq"));
+
+    let c = Client::default();
+    assert_ok!(c.connect(&addr).await);
+
+    for i in 0..10 {
+        let body = vec![i; i as usize * 100];
+        assert_ok!(c.send(body.clone()).await);
+        let request = s.recv().await.unwrap();
+        assert_eq!(request, &body[..]);
+        assert_eq!(request.local_endpoint(), Some(&addr));
+
+        assert_ok!(s.route("ok", request.route).await);
+        assert_eq!(c.recv().await.unwrap(), b"ok");
+    }
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn wrong_path_fails_handshake() {
+    subscribe_tracing!();
+
+    let s = Server::default();
+    let addr = s.listen("ws://127.0.0.1:0/zmq").await.unwrap();
+    let addr = addr.to_string().replace("/zmq", "/other");
+
+    let c = Client::default();
+    assert_ok!(c.connect(addr.as_str()).await);
+
+    for _ in 0..100 {
+        if c.stats().handshake_failures > 0 {
+            break;
+        }
+
+        test::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_gt!(c.stats().handshake_failures, 0);
+    assert_eq!(c.stats().connects, 0);
+}
+
+// Connects a bare websocket to the endpoint, offering the given subprotocols.
+async fn websocket(
+    addr: &str,
+    protocols: Option<&'static str>,
+) -> Result<async_tungstenite::WebSocketStream<async_std::net::TcpStream>, tungstenite::Error> {
+    let mut request = addr.into_client_request().unwrap();
+    if let Some(protocols) = protocols {
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(protocols),
+        );
+    }
+
+    let address = addr.trim_start_matches("ws://").split('/').next().unwrap();
+    let stream = async_std::net::TcpStream::connect(address).await.unwrap();
+    let (socket, _) = async_tungstenite::client_async(request, stream).await?;
+    Ok(socket)
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn plain_websocket_peer_round_trip() {
+    subscribe_tracing!();
+
+    let s = Server::default();
+    let addr = s.listen("ws://127.0.0.1:0/zmq").await.unwrap().to_string();
+    let mut ws = websocket(&addr, Some("ZWS2.0/NULL")).await.unwrap();
+
+    // READY command with Socket-Type CLIENT, then a single frame message.
+    let mut ready = vec![0x02, 5];
+    ready.extend_from_slice(b"READY");
+    ready.push(11);
+    ready.extend_from_slice(b"Socket-Type");
+    ready.extend_from_slice(&6u32.to_be_bytes());
+    ready.extend_from_slice(b"CLIENT");
+    assert_ok!(ws.send(tungstenite::Message::binary(ready)).await);
+    assert_ok!(
+        ws.send(tungstenite::Message::binary(&b"\x00hello"[..]))
+            .await
+    );
+
+    let request = s.recv().await.unwrap();
+    assert_eq!(request, b"hello");
+    assert_ok!(s.route("hi", request.route).await);
+
+    let mut messages = Vec::new();
+    while let Some(message) = ws.next().await {
+        if let tungstenite::Message::Binary(message) = message.unwrap() {
+            messages.push(message);
+            if messages.len() == 2 {
+                break;
+            }
+        }
+    }
+
+    assert!(messages[0].starts_with(b"\x02\x05READY"));
+    assert_eq!(&messages[1][..], b"\x00hi");
+}
+
+#[cfg_attr(feature = "async-std", async_std::test)]
+#[cfg_attr(not(feature = "async-std"), tokio::test)]
+async fn missing_subprotocol_is_rejected() {
+    subscribe_tracing!();
+
+    let s = Server::default();
+    let addr = s.listen("ws://127.0.0.1:0/zmq").await.unwrap().to_string();
+
+    match websocket(&addr, None).await {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 400),
+        other => panic!("handshake not rejected: {:?}", other.map(|_| ())),
+    }
+
+    let response = assert_err!(websocket(&addr, Some("chat")).await);
+    assert!(matches!(response, tungstenite::Error::Http(..)));
+
+    for _ in 0..100 {
+        if s.stats().handshake_failures >= 2 {
+            break;
+        }
+
+        test::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(s.stats().handshake_failures, 2);
+    assert_eq!(s.stats().connects, 0);
+}
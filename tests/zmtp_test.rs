@@ -0,0 +1,42 @@
+use bytes::{Bytes, BytesMut};
+use rmq::zmtp::{Frame, Security, SocketType, Zmtp};
+use tokio_util::codec::{Decoder, Encoder};
+
+use claim::*;
+
+#[test]
+fn zmtp_codec_roundtrips_handshake() {
+    let frames = vec![
+        Frame::Greeting {
+            version: (3, 1),
+            security: Security::Null,
+        },
+        Frame::Ready {
+            socket_type: SocketType::SERVER,
+            properties: Default::default(),
+        },
+        Frame::Message {
+            more: false,
+            payload: Bytes::from_static(b"hello"),
+        },
+    ];
+
+    let mut codec = Zmtp::default();
+    let mut buffer = BytesMut::new();
+    for frame in frames.clone() {
+        assert_ok!(codec.encode(frame, &mut buffer));
+    }
+
+    for frame in frames {
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(frame));
+    }
+
+    assert_none!(codec.decode(&mut buffer).unwrap());
+}
+
+#[test]
+fn zmtp_codec_rejects_invalid_frames() {
+    let mut codec = Zmtp::default();
+    let mut buffer = BytesMut::from(&b"\xf0\x00"[..]);
+    assert_err!(codec.decode(&mut buffer));
+}